@@ -48,6 +48,11 @@ fn build_web_app() {
 fn main() {
     build_web_app();
 
+    // the resources and the manifest only exist for Windows executables
+    if std::env::var_os("CARGO_CFG_WINDOWS").is_none() {
+        return;
+    }
+
     let mut res =  winres::WindowsResource::new();
     res.set_icon("icons/Icon.ico");
     res.set_icon_with_id("icons/generate.ico", "2");
@@ -1,8 +1,12 @@
 import math
 import os
+import uuid
+import base64
 import random
 import hashlib
 import functools
+import itertools
+import collections
 
 from typing import List
 from operator import itemgetter
@@ -17,6 +21,17 @@ FRAGMENTS = [
     "\\?\"[{'&>;+}=]<"                                          #  special
 ]
 
+AMBIGUOUS = "l1I0O\"'`´"
+
+ASCII_PRINTABLE = ''.join(map(chr, range(0x21, 0x7f)))
+
+# characters that can be typed without dead keys or compose sequences
+LAYOUTS = {
+    "us": ASCII_PRINTABLE,
+    "de": ''.join(c for c in ASCII_PRINTABLE if c not in "^`") + "§°€",
+    "fr": ''.join(c for c in ASCII_PRINTABLE if c not in "^`~") + "§°€",
+}
+
 
 def requires_wordlist(f):
     @functools.wraps(f)
@@ -29,12 +44,33 @@ def requires_wordlist(f):
 def inject_config(f):
     @functools.wraps(f)
     def wrapper(binstr, config):
-        f_mapping = itemgetter('characters', 'digits', 'punctuation', 'special')(config)
-        chars = ''.join([b[0] for b in zip(FRAGMENTS, f_mapping) if b[1]])
-        return f(binstr, chars, config["length"])
+        return f(binstr, build_alphabet(config), config["length"])
     return wrapper
 
 
+def build_alphabet(config):
+    f_mapping = itemgetter('characters', 'digits', 'punctuation', 'special')(config)
+    chars = ''.join([b[0] for b in zip(FRAGMENTS, f_mapping) if b[1]])
+    return restrict_alphabet(chars, config)
+
+
+def restrict_alphabet(chars, config):
+    # version 1 configs predate the alphabet options and must keep
+    # deriving the exact same passwords
+    if config.get("version", 1) < 2:
+        return chars
+    if config.get("ascii_only"):
+        chars = ''.join(c for c in chars if c.isascii())
+    if config.get("exclude_ambiguous"):
+        chars = ''.join(c for c in chars if c not in AMBIGUOUS)
+    if config.get("layout") is not None:
+        allowed = LAYOUTS[config["layout"]]
+        chars = ''.join(c for c in chars if c in allowed)
+    if len(chars) < 2:
+        raise Exception("AlphabetError: The selected options leave less than two characters")
+    return chars
+
+
 def _init_wordlist():
     global wordlist
     if wordlist is not None:
@@ -152,13 +188,89 @@ def encode_log(binstr, chars, length):
     return ''.join(res[:length])
 
 
+BYTE_ENCODINGS = {
+    "hex": lambda b: b.hex(),
+    "base32": lambda b: base64.b32encode(b).decode('ascii').rstrip('='),
+    "base64url": lambda b: base64.urlsafe_b64encode(b).decode('ascii').rstrip('='),
+}
+
+
+def chain_blocks(block):
+    counter = 0
+    while True:
+        yield from block
+        counter += 1
+        block = hashlib.sha512(block + counter.to_bytes(4, byteorder='big')).digest()
+
+
+def extend_stream(seed, config):
+    # the encoding name is mixed into the seed, so a hex key and a
+    # base32 key derived from the same phrase are unrelated
+    return chain_blocks(hash_extend(bytes(seed) + config["encoding"].encode('ascii'), config))
+
+
+def hash_stream(seed, encoding, nbytes):
+    # only the phrase and the encoding: the character options don't apply to
+    # byte encodings and the length only cuts the stream, so changing them
+    # must not change the key
+    block = hashlib.sha512(bytes(seed) + encoding.encode('ascii')).digest()
+    return bytes(itertools.islice(chain_blocks(block), nbytes))
+
+
+@inject_config
+def encode_uniform(stream, chars, length):
+    # rejection sampling: bytes above the largest multiple of the alphabet
+    # size would favour the first characters, so they are skipped
+    limit = 256 - 256 % len(chars)
+    res = []
+    for byte in stream:
+        if len(res) >= length:
+            break
+        if byte < limit:
+            res.append(chars[byte % len(chars)])
+    return ''.join(res)
+
+
+def encode_bytes(seed, config):
+    encoding = config["encoding"]
+    if encoding == "uuid":
+        raw = bytearray(hash_stream(seed, encoding, 16))
+        raw[6] = (raw[6] & 0x0f) | 0x40  # version 4
+        raw[8] = (raw[8] & 0x3f) | 0x80  # RFC 4122 variant
+        return str(uuid.UUID(bytes=bytes(raw)))
+    if encoding not in BYTE_ENCODINGS:
+        raise Exception(f"EncodingError: Unknown output encoding '{encoding}'")
+    if not 1 <= config["length"] <= 255:
+        raise Exception("EncodingError: Length has to be between 1 and 255 bytes")
+    return BYTE_ENCODINGS[encoding](hash_stream(seed, encoding, config["length"]))
+
+
+def derive_password(seed, config):
+    if config.get("encoding", "log") == "log":
+        if config.get("version", 1) >= 3:
+            return encode_uniform(extend_stream(seed, config), config)
+        return encode_log(hash_extend(seed, config), config)
+    return encode_bytes(seed, config)
+
+
+def encoder_histogram(config, samples, seed=None):
+    # character frequencies over random seeds, in alphabet order. A fixed
+    # `seed` draws the same seeds every time, for reproducible tests
+    rand = random.Random(seed) if seed is not None else None
+    next_seed = (lambda: rand.getrandbits(128).to_bytes(16, byteorder='big')) if rand else (lambda: os.urandom(16))
+    histogram = collections.Counter()
+    for _ in range(samples):
+        histogram.update(derive_password(next_seed(), config))
+    return [(c, histogram[c]) for c in build_alphabet(config)]
+
+
 @export
 def generate_phrase(config):
     seed = os.urandom(16)
     checksum = calc_checksum(seed)
     
     binstr = (int.from_bytes(seed, byteorder='big') << 4) | checksum
-    return generate_mnemonic(binstr), encode_log(hash_extend(seed, config), config)
+    return generate_mnemonic(binstr), derive_password(seed, config)
 
 
 @export
@@ -168,7 +280,27 @@ def from_phrase(phrase, config):
     if checksum != calc_checksum(bitstr):
         raise Exception("ChecksumError: Checksum doesn't match! Probably you misstyped something.")
 
-    return phrase, encode_log(hash_extend(bitstr, config), config)
+    return phrase, derive_password(bitstr, config)
+
+
+@export
+def hash_word(salt, word):
+    return hashlib.sha256(bytes(salt) + word.encode('utf-8')).hexdigest()
+
+
+STORE_KDF_ITERATIONS = 200_000
+
+
+@export
+def derive_store_key(kind, secret, salt):
+    if kind == "phrase":
+        chunks = retrieve_chunks(secret)
+        material, checksum = retrieve_bitstr(chunks)
+        if checksum != calc_checksum(material):
+            raise Exception("ChecksumError: Checksum doesn't match! Probably you misstyped something.")
+    else:
+        material = secret.encode('utf-8')
+    return hashlib.pbkdf2_hmac('sha512', material, bytes(salt), STORE_KDF_ITERATIONS, dklen=64)
 
 
 @export
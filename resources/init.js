@@ -7,6 +7,29 @@ window.addEventListener('keydown', ev => {
     }
 })
 
+// every message carries the origin this page was loaded from and a nonce only
+// this script knows, page scripts posting on their own are rejected. The
+// wrapped sender stays in here, the web-app only gets to invoke commands.
+const ipc = (function () {
+    const post = window.ipc.postMessage.bind(window.ipc);
+    const { stringify } = JSON;
+    const origin = window.location.origin;
+    const nonce = __IPC_NONCE__;
+
+    function send(message) {
+        post(stringify({ ...message, origin, nonce }));
+    }
+
+    function invoke(command, inner, handles) {
+        send({ callback: handles.callback, error: handles.error, command, inner });
+    }
+
+    // channels of a previous page are gone, the backend queues their messages until they are established again
+    send({ callback: 0, error: 0, command: "application-page-loading", inner: null });
+
+    return Object.freeze({ invoke });
+})();
+
 const ipcHandler = (function () {
     let handlerId = 0;
     const handleRegister = new Map();
@@ -46,21 +69,14 @@ const ipcHandler = (function () {
         });
         return handles;
     }
-    return { _dispatchResolver, _respondChannelMessage, registerResolver };
+    return Object.freeze({ _dispatchResolver, _respondChannelMessage, registerResolver });
 })();
-window.ipcHandler = ipcHandler;
+// the backend resolves responses through it, page scripts may not swap it out
+Object.defineProperty(window, 'ipcHandler', { value: ipcHandler });
 
 window.addEventListener('application-init', () => {
-    window.RustInterface._update({
-        sendIPCMessage: window.ipc.postMessage,
+    window.RustInterface._update(Object.freeze({
+        ipc,
         ipcHandler
-    });
+    }));
 })
-
-function _triggerWindowClose() {
-    const closeEvent = new CustomEvent('close', { cancelable: true })
-    window.dispatchEvent(closeEvent);
-    if (!closeEvent.defaultPrevented) {
-        window.ipc.postMessage('{"callback":0,"error":0,"command":"application-close-window","inner":null}');
-    }
-}
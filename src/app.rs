@@ -0,0 +1,294 @@
+use crate::{
+    events::{ApplicationOpenLocation, EventLoopMessage, WindowButton},
+    ipc::Channels,
+    webview::WindowShell
+};
+
+/// What `run` keeps between events, apart from the native window and the
+/// settings. It is driven through `WindowShell`, so it works with a
+/// `MemoryWebview` as well.
+pub struct AppState {
+    is_launcher: bool,
+    /// Sent to the web-app once it has initialized, `None` keeps the window hidden
+    application_open_location: Option<ApplicationOpenLocation>,
+    initialized: bool,
+    force_close: bool,
+    // the window is shown once the web-app has navigated to the open location
+    show_on_content_loaded: bool
+}
+
+impl AppState {
+    pub fn new(is_launcher: bool, application_open_location: Option<ApplicationOpenLocation>) -> Self {
+        Self {
+            is_launcher,
+            application_open_location,
+            initialized: false,
+            force_close: false,
+            show_on_content_loaded: false
+        }
+    }
+
+    pub fn handle(&mut self, message: EventLoopMessage, window: &dyn WindowShell, channels: &Channels) {
+        match message {
+            EventLoopMessage::WebAppInit => {
+                if !self.initialized {
+                    if let Some(aol) = self.application_open_location.clone() {
+                        let _ = channels.send_message("ui-events", aol);
+                        self.show_on_content_loaded = true;
+                    }
+                    self.initialized = true;
+                }
+            }
+            EventLoopMessage::PageLoading => {
+                channels.detach_all();
+            }
+            EventLoopMessage::PageContentLoaded => {
+                if self.show_on_content_loaded {
+                    self.show_on_content_loaded = false;
+                    window.show();
+                }
+            }
+            EventLoopMessage::EstablishChannel(req) => {
+                let _ = channels.open_channel(&req.0, window.sink(), req.1);
+            }
+            EventLoopMessage::CloseChannel(id) => {
+                channels.close_channel(id);
+            }
+            EventLoopMessage::WindowShowSysMenu { x, y } => {
+                window.show_sys_menu(x, y);
+            }
+            EventLoopMessage::WindowSysCommand(msg) => {
+                match msg {
+                    WindowButton::Close => window.close(),
+                    WindowButton::Minimize => window.minimize()
+                };
+            }
+            EventLoopMessage::WindowFocus => {
+                let _ = channels.send_message("window-events", "focus");
+            }
+            EventLoopMessage::WindowBlur => {
+                let _ = channels.send_message("window-events", "blur");
+            }
+            EventLoopMessage::WindowMinimize => {
+                let _ = channels.send_message("window-events", "minimized");
+            }
+            EventLoopMessage::ApplicationOpenWindow(aol) => {
+                if !window.is_visible() {
+                    let _ = channels.send_message("ui-events", aol);
+                    self.show_on_content_loaded = true;
+                } else {
+                    window.request_attention();
+                }
+            }
+            EventLoopMessage::ApplicationCloseWindow => {
+                if self.is_launcher {
+                    window.hide();
+                } else {
+                    self.force_close = true;
+                    window.close();
+                }
+            }
+            EventLoopMessage::ApplicationQuit => {
+                self.force_close = true;
+                window.close();
+            }
+            EventLoopMessage::ConfigChanged(changes) => {
+                for change in changes {
+                    let _ = channels.send_message("config-events", change);
+                }
+            }
+            EventLoopMessage::QuizReminder(reminder) => {
+                let listening = channels.get_channel("quiz-events").is_some();
+                // queued until the web-app listens on quiz-events
+                let _ = channels.send_message("quiz-events", reminder);
+                // the notification opens the window, which picks up the reminder
+                if !listening && self.is_launcher && !window.is_visible() {
+                    window.notify("Mnemonic", "It's time to review your mnemonic phrase.");
+                }
+            }
+        }
+    }
+
+    /// Lets the web-app decide about closing the window. Returns whether
+    /// closing has to be prevented until it does.
+    pub fn close_requested(&self, window: &dyn WindowShell) -> bool {
+        window.notify_close_requested();
+        !self.force_close
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::{
+        events::QuizEvent,
+        ipc::{CallbackFn, ChannelHandshakeRequest, ChannelUuid},
+        webview::{MemoryWebview, WebviewRecord}
+    };
+
+    fn establish(state: &mut AppState, window: &Arc<MemoryWebview>, channels: &Channels, name: &str, token: usize) {
+        let request = ChannelHandshakeRequest(name.to_string(), CallbackFn(token));
+        state.handle(EventLoopMessage::EstablishChannel(request), window, channels);
+    }
+
+    /// `data` of the messages sent on any channel
+    fn messages(window: &MemoryWebview) -> Vec<Value> {
+        window.channel_events().into_iter()
+            .filter(|event| event["type"] == "message")
+            .map(|event| event["data"].clone())
+            .collect()
+    }
+
+    #[test]
+    fn open_location_is_sent_once_and_shown_after_content_loaded() {
+        let window = MemoryWebview::new();
+        let channels = Channels::new();
+        let mut state = AppState::new(false, Some(ApplicationOpenLocation::Generate));
+
+        state.handle(EventLoopMessage::WebAppInit, &window, &channels);
+        state.handle(EventLoopMessage::WebAppInit, &window, &channels);
+        assert!(window.channel_events().is_empty());
+
+        // queued until the web-app establishes the channel
+        establish(&mut state, &window, &channels, "ui-events", 7);
+        let events = window.channel_events();
+        assert_eq!(events[0]["type"], "accept");
+        assert_eq!(events[0]["token"], 7);
+        assert_eq!(messages(&window), vec![json!("Generate")]);
+        assert!(!window.records().contains(&WebviewRecord::Shown));
+
+        state.handle(EventLoopMessage::PageContentLoaded, &window, &channels);
+        state.handle(EventLoopMessage::PageContentLoaded, &window, &channels);
+        let shown = window.records().iter().filter(|record| **record == WebviewRecord::Shown).count();
+        assert_eq!(shown, 1);
+    }
+
+    #[test]
+    fn hidden_launcher_stays_hidden_without_open_location() {
+        let window = MemoryWebview::new();
+        let channels = Channels::new();
+        let mut state = AppState::new(true, None);
+
+        state.handle(EventLoopMessage::WebAppInit, &window, &channels);
+        state.handle(EventLoopMessage::PageContentLoaded, &window, &channels);
+        assert!(window.records().is_empty());
+    }
+
+    #[test]
+    fn launcher_hides_on_close_until_quit() {
+        let window = MemoryWebview::new();
+        let channels = Channels::new();
+        let mut state = AppState::new(true, None);
+
+        state.handle(EventLoopMessage::ApplicationCloseWindow, &window, &channels);
+        assert!(state.close_requested(&window));
+        assert_eq!(window.take_records(), vec![WebviewRecord::Hidden, WebviewRecord::CloseRequested]);
+
+        state.handle(EventLoopMessage::ApplicationQuit, &window, &channels);
+        assert!(!state.close_requested(&window));
+        assert_eq!(window.take_records(), vec![WebviewRecord::Closed, WebviewRecord::CloseRequested]);
+    }
+
+    #[test]
+    fn window_closes_when_not_a_launcher() {
+        let window = MemoryWebview::new();
+        let channels = Channels::new();
+        let mut state = AppState::new(false, None);
+
+        assert!(state.close_requested(&window));
+        state.handle(EventLoopMessage::ApplicationCloseWindow, &window, &channels);
+        assert!(!state.close_requested(&window));
+        assert!(window.records().contains(&WebviewRecord::Closed));
+    }
+
+    #[test]
+    fn open_window_requests_attention_when_visible() {
+        let window = MemoryWebview::new();
+        let channels = Channels::new();
+        let mut state = AppState::new(true, None);
+
+        state.handle(EventLoopMessage::ApplicationOpenWindow(ApplicationOpenLocation::Retrieve), &window, &channels);
+        establish(&mut state, &window, &channels, "ui-events", 1);
+        state.handle(EventLoopMessage::PageContentLoaded, &window, &channels);
+        assert_eq!(messages(&window), vec![json!("Retrieve")]);
+        assert!(window.is_visible());
+
+        state.handle(EventLoopMessage::ApplicationOpenWindow(ApplicationOpenLocation::Auto), &window, &channels);
+        assert_eq!(window.records().last(), Some(&WebviewRecord::AttentionRequested));
+        assert_eq!(messages(&window).len(), 1);
+    }
+
+    #[test]
+    fn window_buttons_drive_the_window() {
+        let window = MemoryWebview::new();
+        let channels = Channels::new();
+        let mut state = AppState::new(false, None);
+
+        state.handle(EventLoopMessage::WindowShowSysMenu { x: 3, y: 4 }, &window, &channels);
+        state.handle(EventLoopMessage::WindowSysCommand(WindowButton::Minimize), &window, &channels);
+        state.handle(EventLoopMessage::WindowSysCommand(WindowButton::Close), &window, &channels);
+        assert_eq!(window.records(), vec![
+            WebviewRecord::SysMenu { x: 3, y: 4 },
+            WebviewRecord::Minimized,
+            WebviewRecord::Closed
+        ]);
+    }
+
+    #[test]
+    fn reminder_notifies_only_while_nobody_listens() {
+        let window = MemoryWebview::new();
+        let channels = Channels::new();
+        let mut state = AppState::new(true, None);
+
+        state.handle(EventLoopMessage::QuizReminder(QuizEvent::Reminder { due: 1 }), &window, &channels);
+        assert!(matches!(window.records().last(), Some(WebviewRecord::Notified { .. })));
+
+        establish(&mut state, &window, &channels, "quiz-events", 2);
+        assert_eq!(messages(&window), vec![json!({ "Reminder": { "due": 1 } })]);
+
+        window.take_records();
+        state.handle(EventLoopMessage::QuizReminder(QuizEvent::Reminder { due: 2 }), &window, &channels);
+        assert_eq!(messages(&window), vec![json!({ "Reminder": { "due": 2 } })]);
+        assert!(!window.records().iter().any(|record| matches!(record, WebviewRecord::Notified { .. })));
+    }
+
+    #[test]
+    fn page_loading_detaches_channels() {
+        let window = MemoryWebview::new();
+        let channels = Channels::new();
+        let mut state = AppState::new(false, None);
+
+        establish(&mut state, &window, &channels, "window-events", 1);
+        state.handle(EventLoopMessage::WindowFocus, &window, &channels);
+        assert_eq!(messages(&window), vec![json!("focus")]);
+
+        state.handle(EventLoopMessage::PageLoading, &window, &channels);
+        state.handle(EventLoopMessage::WindowBlur, &window, &channels);
+        assert_eq!(messages(&window), vec![json!("focus")]);
+        assert!(channels.get_channel("window-events").is_none());
+
+        // the blur waits for the reloaded page
+        establish(&mut state, &window, &channels, "window-events", 2);
+        assert_eq!(messages(&window), vec![json!("focus"), json!("blur")]);
+    }
+
+    #[test]
+    fn closing_a_channel_queues_its_messages_again() {
+        let window = MemoryWebview::new();
+        let channels = Channels::new();
+        let mut state = AppState::new(false, None);
+
+        establish(&mut state, &window, &channels, "window-events", 1);
+        let (d1, d2, d3, d4): ChannelUuid = serde_json::from_value(window.channel_events()[0]["acceptId"].clone()).unwrap();
+        let id = uuid::Uuid::from_fields(d1, d2, d3, &d4);
+        state.handle(EventLoopMessage::CloseChannel(id), &window, &channels);
+        assert_eq!(window.channel_events().last().unwrap()["type"], "close");
+
+        state.handle(EventLoopMessage::WindowMinimize, &window, &channels);
+        assert!(messages(&window).is_empty());
+    }
+}
@@ -0,0 +1,360 @@
+use std::collections::{BTreeMap, HashMap};
+
+use bson::Bson;
+use serde_json::Value;
+
+/// Named TypeScript declarations collected while describing the commands
+#[derive(Default)]
+pub struct Declarations {
+    items: BTreeMap<String, String>
+}
+
+impl Declarations {
+    /// Adds `name` the first time it is seen and returns it for use as a type.
+    pub fn declare<F: FnOnce(&mut Declarations) -> String>(&mut self, name: &str, body: F) -> String {
+        if !self.items.contains_key(name) {
+            // placeholder first, so recursive types terminate
+            self.items.insert(name.to_string(), String::new());
+            let body = body(self);
+            self.items.insert(name.to_string(), body);
+        }
+        name.to_string()
+    }
+}
+
+/// A Rust type as it looks to the web-app after going through serde.
+pub trait TypeScript {
+    /// Whether the web-app may leave it out, as a trailing argument or field
+    const OPTIONAL: bool = false;
+
+    fn ts_type(declarations: &mut Declarations) -> String;
+}
+
+/// `{ name: type, optional?: type }`, a name ending in `?` marks an optional field
+pub fn object<N: AsRef<str>>(fields: &[(N, String)]) -> String {
+    let fields: Vec<String> = fields.iter()
+        .map(|(name, ty)| format!("{}: {}", name.as_ref(), ty))
+        .collect();
+    format!("{{ {} }}", fields.join(", "))
+}
+
+/// Options of a stringified `serde(...)` attribute, nothing for other attributes
+fn serde_options(attribute: &str) -> Vec<(String, Option<String>)> {
+    let options = attribute.trim()
+        .strip_prefix("serde")
+        .and_then(|rest| rest.trim_start().strip_prefix('('))
+        .and_then(|rest| rest.strip_suffix(')'));
+    let options = match options {
+        Some(options) => options,
+        None => return Vec::new()
+    };
+    options.split(',')
+        .map(|option| option.trim())
+        .filter(|option| !option.is_empty())
+        .map(|option| match option.split_once('=') {
+            Some((key, value)) => (key.trim().to_string(), Some(value.trim().trim_matches('"').to_string())),
+            None => (option.to_string(), None)
+        })
+        .collect()
+}
+
+/// `Some(value)` if one of `attributes` sets the serde option `key`
+fn serde_option(attributes: &[&str], key: &str) -> Option<Option<String>> {
+    attributes.iter()
+        .flat_map(|attribute| serde_options(attribute))
+        .find(|(name, _)| name == key)
+        .map(|(_, value)| value)
+}
+
+fn camel_case(name: &str) -> String {
+    let mut parts = name.split('_');
+    let mut result = parts.next().unwrap_or_default().to_string();
+    for part in parts {
+        let mut chars = part.chars();
+        if let Some(first) = chars.next() {
+            result.extend(first.to_uppercase());
+            result.push_str(chars.as_str());
+        }
+    }
+    result
+}
+
+/// The name serde gives `field`, followed by `?` if it has a default. Worked
+/// out from the stringified attributes of the struct and of the field, see
+/// `ts_interface!`.
+pub fn field_name(field: &str, struct_attributes: &[&str], field_attributes: &[&str]) -> String {
+    let name = match serde_option(field_attributes, "rename") {
+        Some(Some(name)) => name,
+        _ => match serde_option(struct_attributes, "rename_all") {
+            Some(Some(rule)) if rule == "camelCase" => camel_case(field),
+            Some(rule) => panic!("ts_interface! doesn't support rename_all = {:?}", rule),
+            None => field.to_string()
+        }
+    };
+    let optional = serde_option(field_attributes, "default").is_some()
+        || serde_option(struct_attributes, "default").is_some();
+    if optional { format!("{}?", name) } else { name }
+}
+
+/// Union of string literals, how serde writes unit enum variants
+pub fn string_union(variants: &[&str]) -> String {
+    variants.iter()
+        .map(|variant| format!("\"{}\"", variant))
+        .collect::<Vec<String>>()
+        .join(" | ")
+}
+
+/// Declares a struct and implements `TypeScript` for it. The field names are
+/// taken from the struct and its serde attributes, `field?` for fields with
+/// `#[serde(default)]`.
+#[macro_export]
+macro_rules! ts_interface {
+    (
+        $(#[$attr:meta])*
+        $vis:vis struct $name:ident {
+            $(
+                $(#[$field_attr:meta])*
+                $field_vis:vis $field:ident: $field_ty:ty
+            ),* $(,)?
+        }
+    ) => {
+        $(#[$attr])*
+        $vis struct $name {
+            $(
+                $(#[$field_attr])*
+                $field_vis $field: $field_ty
+            ),*
+        }
+
+        impl $crate::bindings::TypeScript for $name {
+            fn ts_type(declarations: &mut $crate::bindings::Declarations) -> String {
+                declarations.declare(stringify!($name), |declarations| {
+                    let attributes: &[&str] = &[$(stringify!($attr)),*];
+                    $crate::bindings::object(&[
+                        $((
+                            $crate::bindings::field_name(stringify!($field), attributes, &[$(stringify!($field_attr)),*]),
+                            <$field_ty as $crate::bindings::TypeScript>::ts_type(declarations)
+                        )),*
+                    ])
+                })
+            }
+        }
+    };
+}
+
+/// Implements `TypeScript` for an enum of unit variants with their serialized names.
+#[macro_export]
+macro_rules! ts_string_enum {
+    ($ty:ty, [$($variant:literal),* $(,)?]) => {
+        impl $crate::bindings::TypeScript for $ty {
+            fn ts_type(declarations: &mut $crate::bindings::Declarations) -> String {
+                declarations.declare(stringify!($ty), |_| $crate::bindings::string_union(&[$($variant),*]))
+            }
+        }
+    };
+}
+
+macro_rules! ts_primitive {
+    ($ts:literal, $($ty:ty),*) => {
+        $(
+            impl TypeScript for $ty {
+                fn ts_type(_: &mut Declarations) -> String {
+                    $ts.to_string()
+                }
+            }
+        )*
+    };
+}
+
+ts_primitive!("boolean", bool);
+ts_primitive!("number", u8, u16, u32, u64, usize, i8, i16, i32, i64, isize, f32, f64);
+ts_primitive!("string", String, str);
+ts_primitive!("null", ());
+ts_primitive!("any", Value, Bson);
+
+impl<T: TypeScript + ?Sized> TypeScript for &T {
+    fn ts_type(declarations: &mut Declarations) -> String {
+        T::ts_type(declarations)
+    }
+}
+
+impl<T: TypeScript> TypeScript for Option<T> {
+    const OPTIONAL: bool = true;
+
+    fn ts_type(declarations: &mut Declarations) -> String {
+        format!("{} | null", T::ts_type(declarations))
+    }
+}
+
+/// Element types that are unions need parentheses inside an array
+fn array_of(element: String) -> String {
+    if element.contains(' ') && !element.starts_with('{') {
+        format!("({})[]", element)
+    } else {
+        format!("{}[]", element)
+    }
+}
+
+impl<T: TypeScript> TypeScript for Vec<T> {
+    fn ts_type(declarations: &mut Declarations) -> String {
+        array_of(T::ts_type(declarations))
+    }
+}
+
+impl<T: TypeScript, const N: usize> TypeScript for [T; N] {
+    fn ts_type(declarations: &mut Declarations) -> String {
+        array_of(T::ts_type(declarations))
+    }
+}
+
+impl<V: TypeScript> TypeScript for HashMap<String, V> {
+    fn ts_type(declarations: &mut Declarations) -> String {
+        format!("Record<string, {}>", V::ts_type(declarations))
+    }
+}
+
+impl<V: TypeScript> TypeScript for BTreeMap<String, V> {
+    fn ts_type(declarations: &mut Declarations) -> String {
+        format!("Record<string, {}>", V::ts_type(declarations))
+    }
+}
+
+/// Argument list of a command, see `registry::CommandArguments`
+pub trait TypeScriptArguments {
+    /// Types of the arguments and whether each one may be left out
+    fn ts_arguments(declarations: &mut Declarations) -> Vec<(String, bool)>;
+}
+
+macro_rules! ts_tuples {
+    ($($arg:ident),*) => {
+        impl<$($arg: TypeScript,)*> TypeScript for ($($arg,)*) {
+            fn ts_type(declarations: &mut Declarations) -> String {
+                let items: Vec<String> = vec![$($arg::ts_type(declarations)),*];
+                format!("[{}]", items.join(", "))
+            }
+        }
+
+        impl<$($arg: TypeScript,)*> TypeScriptArguments for ($($arg,)*) {
+            #[allow(unused_variables)]
+            fn ts_arguments(declarations: &mut Declarations) -> Vec<(String, bool)> {
+                vec![$(($arg::ts_type(declarations), $arg::OPTIONAL)),*]
+            }
+        }
+    };
+}
+
+ts_tuples!(A);
+ts_tuples!(A, B);
+ts_tuples!(A, B, C);
+ts_tuples!(A, B, C, D);
+ts_tuples!(A, B, C, D, E);
+ts_tuples!(A, B, C, D, E, G);
+
+impl TypeScriptArguments for () {
+    fn ts_arguments(_: &mut Declarations) -> Vec<(String, bool)> {
+        Vec::new()
+    }
+}
+
+/// Return type of a command, see `registry::IntoCommandOutput`
+pub trait TypeScriptResult {
+    fn ts_result(declarations: &mut Declarations) -> String;
+}
+
+impl<T: TypeScript, E> TypeScriptResult for Result<T, E> {
+    fn ts_result(declarations: &mut Declarations) -> String {
+        T::ts_type(declarations)
+    }
+}
+
+/// Signature of a registered command as it appears in the generated module
+pub struct CommandSignature {
+    pub arguments: Vec<(String, bool)>,
+    pub result: String
+}
+
+/// Arguments as a tuple, trailing optional ones marked with `?`
+fn argument_tuple(arguments: &[(String, bool)]) -> String {
+    let required = arguments.iter()
+        .rposition(|(_, optional)| !optional)
+        .map_or(0, |index| index + 1);
+    let items: Vec<String> = arguments.iter()
+        .enumerate()
+        .map(|(index, (ty, _))| {
+            if index < required {
+                ty.clone()
+            } else {
+                format!("({})?", ty)
+            }
+        })
+        .collect();
+    format!("[{}]", items.join(", "))
+}
+
+/// Where `npm run bindings` and the test below keep the generated module
+pub const BINDINGS_PATH: &str = "web-app/src/bindings.ts";
+
+/// Renders the TypeScript module describing `commands` and `channels`.
+pub fn render(
+    commands: Vec<(&str, CommandSignature)>,
+    channels: Vec<(&str, String)>,
+    declarations: Declarations
+) -> String {
+    let mut output = String::from(
+        "// Generated by `mnemonic --emit-bindings`, don't edit by hand.\n\n"
+    );
+    for (name, body) in declarations.items.iter() {
+        output.push_str(&format!("export type {} = {};\n", name, body));
+    }
+
+    output.push_str("\nexport interface Commands {\n");
+    for (name, signature) in commands.iter() {
+        output.push_str(&format!(
+            "    {}: {{ args: {}, result: {} }};\n",
+            name, argument_tuple(&signature.arguments), signature.result
+        ));
+    }
+    output.push_str("}\n\nexport interface Channels {\n");
+    for (name, ty) in channels.iter() {
+        output.push_str(&format!("    \"{}\": {};\n", name, ty));
+    }
+    output.push_str("}\n");
+    output
+}
+
+/// The bindings for every registered command and channel
+pub fn generate() -> String {
+    let mut declarations = Declarations::default();
+    let mut commands = crate::handler::command_signatures(&mut declarations);
+    commands.sort_by(|a, b| a.0.cmp(b.0));
+    let channels = crate::handler::channel_types(&mut declarations);
+    render(commands, channels, declarations)
+}
+
+/// Writes the bindings for every registered command and channel to `path`.
+pub fn emit(path: &str) -> Result<(), String> {
+    std::fs::write(path, generate()).map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn field_names_follow_serde() {
+        let camel: &[&str] = &["derive(Serialize)", "serde(rename_all = \"camelCase\")"];
+        assert_eq!(field_name("checkbox_checked", camel, &[]), "checkboxChecked");
+        assert_eq!(field_name("dialog_type", &[], &["serde(default)"]), "dialog_type?");
+        assert_eq!(field_name("version", camel, &["serde(default = \"default_version\")"]), "version?");
+        assert_eq!(field_name("kind", camel, &["doc = \" Kind, e.g. serde(rename)\"", "serde(rename = \"type\")"]), "type");
+    }
+
+    /// The web-app builds against the checked-in module, regenerate it with
+    /// `npm run bindings` when this fails.
+    #[test]
+    fn checked_in_bindings_are_current() {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join(BINDINGS_PATH);
+        let checked_in = std::fs::read_to_string(path).unwrap().replace("\r\n", "\n");
+        assert!(checked_in == generate(), "{} is out of date, run `npm run bindings`", BINDINGS_PATH);
+    }
+}
@@ -0,0 +1,156 @@
+use std::{fs::OpenOptions, io::Write, time::{SystemTime, UNIX_EPOCH}};
+
+use lazy_static::lazy_static;
+use serde::Deserialize;
+
+use crate::config;
+
+/// Rejected requests are appended to this file in the data directory
+const VIOLATIONS_LOG: &str = "ipc-violations.log";
+
+/// Entry of `resources/capabilities.toml`
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct OriginCapabilities {
+    origin: String,
+    #[serde(default)]
+    debug: bool,
+    commands: Vec<String>,
+    #[serde(default)]
+    deny: Vec<String>,
+}
+
+impl OriginCapabilities {
+    fn allows(&self, command: &str) -> bool {
+        !self.deny.iter().any(|denied| denied == command)
+            && self.commands.iter().any(|allowed| allowed == "*" || allowed == command)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    origin: Vec<OriginCapabilities>,
+}
+
+fn read_manifest() -> Manifest {
+    toml::from_str(include_str!("../resources/capabilities.toml"))
+        .expect("resources/capabilities.toml is invalid")
+}
+
+lazy_static! {
+    static ref MANIFEST: Manifest = read_manifest();
+}
+
+/// Origin of the manifest for `location.origin` of a page. WebView2 serves
+/// custom schemes as `https://<scheme>.<host>`, so `mne://apps` reports
+/// itself as `https://mne.apps`.
+pub fn manifest_origin(page_origin: &str) -> String {
+    match page_origin.strip_prefix("https://mne.") {
+        Some(host) => format!("mne://{}", host),
+        None => page_origin.to_string()
+    }
+}
+
+/// Fails unless a page loaded from `origin` may invoke `command`.
+pub fn check(origin: &str, command: &str) -> Result<(), String> {
+    let capabilities = MANIFEST.origin.iter()
+        .filter(|entry| cfg!(debug_assertions) || !entry.debug)
+        .find(|entry| entry.origin == origin)
+        .ok_or_else(|| format!("origin {} may not use IPC", origin))?;
+
+    if capabilities.allows(command) {
+        Ok(())
+    } else {
+        Err(format!("origin {} may not invoke {}", origin, command))
+    }
+}
+
+/// Appends a rejected request to the violations log, the webview has no
+/// console to write to.
+pub fn log_violation(message: &str) -> Result<(), String> {
+    let path = config::data_directory()?.join(VIOLATIONS_LOG);
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|err| err.to_string())?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    writeln!(file, "{} {}", now, message).map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capabilities(commands: &[&str], deny: &[&str]) -> OriginCapabilities {
+        OriginCapabilities {
+            origin: "mne://apps".to_string(),
+            debug: false,
+            commands: commands.iter().map(|command| command.to_string()).collect(),
+            deny: deny.iter().map(|command| command.to_string()).collect()
+        }
+    }
+
+    #[test]
+    fn listed_commands_are_allowed() {
+        let entry = capabilities(&["getWordlist", "checkChecksum"], &[]);
+        assert!(entry.allows("getWordlist"));
+        assert!(entry.allows("checkChecksum"));
+        assert!(!entry.allows("configSetProperty"));
+        assert!(!entry.allows("getWord"));
+    }
+
+    #[test]
+    fn deny_takes_commands_away_from_the_wildcard() {
+        let entry = capabilities(&["*"], &["clipboardWriteTextSecure"]);
+        assert!(entry.allows("getWordlist"));
+        assert!(!entry.allows("clipboardWriteTextSecure"));
+
+        let entry = capabilities(&["clipboardWriteTextSecure"], &["clipboardWriteTextSecure"]);
+        assert!(!entry.allows("clipboardWriteTextSecure"));
+    }
+
+    #[test]
+    fn manifest_rejects_unknown_fields() {
+        let manifest = "[[origin]]\norigin = \"mne://apps\"\ncommands = [\"*\"]\nallow = [\"getWordlist\"]";
+        assert!(toml::from_str::<Manifest>(manifest).is_err());
+    }
+
+    #[test]
+    fn webview_origins_map_to_the_manifest() {
+        assert_eq!(manifest_origin("https://mne.apps"), "mne://apps");
+        assert_eq!(manifest_origin("http://localhost:3000"), "http://localhost:3000");
+        assert_eq!(manifest_origin("https://example.com"), "https://example.com");
+    }
+
+    #[test]
+    fn origins_are_matched_exactly() {
+        assert_eq!(check("mne://apps", "clipboardWriteTextSecure"), Ok(()));
+        assert_eq!(
+            check("mne://apps.evil", "getWordlist"),
+            Err("origin mne://apps.evil may not use IPC".to_string())
+        );
+        assert_eq!(
+            check("https://mne.apps", "getWordlist"),
+            Err("origin https://mne.apps may not use IPC".to_string())
+        );
+    }
+
+    #[test]
+    fn dev_server_is_denied_the_sensitive_commands() {
+        if !cfg!(debug_assertions) {
+            assert!(check("http://localhost:3000", "getWordlist").is_err());
+            return;
+        }
+        assert_eq!(check("http://localhost:3000", "getWordlist"), Ok(()));
+        assert_eq!(
+            check("http://localhost:3000", "autostartRegistryExecuteCommand"),
+            Err("origin http://localhost:3000 may not invoke autostartRegistryExecuteCommand".to_string())
+        );
+        assert!(check("http://localhost:3000", "clipboardWriteTextSecure").is_err());
+    }
+}
@@ -1,163 +1,110 @@
-use serde::Deserialize;
-use serde_json::Value;
-use tauri_runtime::Dispatch;
-use tauri_runtime::{
-    window::DetachedWindow, EventLoopProxy
-};
-use tauri_runtime_wry::{Wry, EventProxy};
+use serde::{Deserialize, Serialize};
+use tauri_runtime::{Dispatch, EventLoopProxy};
+use tauri_runtime_wry::EventProxy;
 
+#[cfg(all(windows, not(test)))]
 use winsafe::{prelude::*, co, HINSTANCE};
+#[cfg(all(windows, not(test)))]
 use winsafe::{
     TaskDialogIndirect, TASKDIALOG_BUTTON, TASKDIALOGCONFIG, 
     HWND, WString, IconIdTdicon
 };
 
-use crate::ipc::{Invoke, deserialize_arguments, get_argument};
-use crate::events::EventLoopMessage;
+use crate::ipc::Window;
+use crate::events::{EventLoopMessage, WindowButton};
+use crate::registry::{Deferred, deferred};
+#[cfg(windows)]
 use crate::win32;
+use crate::workers;
 
-#[derive(Debug, Clone)]
-pub enum WindowButton {
-    Close,
-    Minimize,
+#[derive(Serialize, Deserialize)]
+pub enum AutostartCommand {
+    Get, Activate, Deactivate
 }
 
-fn get_wordlist_impl() -> String {
-    include_str!("../resources/wordlist.txt").to_string()
-}
+crate::ts_string_enum!(AutostartCommand, ["Get", "Activate", "Deactivate"]);
 
-pub fn get_wordlist(invoke: Invoke) {
-    invoke.resolver.resolve(get_wordlist_impl());
+pub fn get_wordlist() -> Result<String, String> {
+    Ok(include_str!("../resources/wordlist.txt").to_string())
 }
 
-pub fn window_drag_move(
-    window: DetachedWindow<EventLoopMessage, Wry<EventLoopMessage>>,
-    invoke: Invoke
-) {
-    let result = window.dispatcher.start_dragging();
-
-    match result {
-        Ok(..) => {
-            invoke.resolver.resolve(Value::Null);
-        }
-        Err(err) => {
-            invoke.resolver.reject(err.to_string());
-        } 
-    }
+pub fn window_drag_move(window: Window) -> Result<(), String> {
+    window.dispatcher.start_dragging().map_err(|err| err.to_string())
 }
 
-pub fn handle_window_buttons(
+fn handle_window_buttons(
     proxy: EventProxy<EventLoopMessage>,
-    invoke: Invoke,
     message: WindowButton,
-) {
-    let result = proxy.send_event(EventLoopMessage::WindowSysCommand(message));
-
-    match result {
-        Ok(..) => {
-            invoke.resolver.resolve(Value::Null);
-        }
-        Err(err) => {
-            invoke.resolver.reject(err.to_string());
-        } 
-    }
+) -> Result<(), String> {
+    proxy.send_event(EventLoopMessage::WindowSysCommand(message))
+        .map_err(|err| err.to_string())
 }
 
-fn window_show_sys_menu_impl(proxy: EventProxy<EventLoopMessage>, x: i32, y: i32) -> Result<(), tauri_runtime::Error> {
-    proxy.send_event(EventLoopMessage::WindowShowSysMenu { x, y })
+pub fn window_close(proxy: EventProxy<EventLoopMessage>) -> Result<(), String> {
+    handle_window_buttons(proxy, WindowButton::Close)
 }
 
-pub fn window_show_sys_menu(
-    proxy: EventProxy<EventLoopMessage>,
-    invoke: Invoke
-) -> Option<()> {
-    let arguments = deserialize_arguments(invoke.clone())?;
-    let mut iter = arguments.iter();
-    let resolver = invoke.resolver.clone();
-
-    let result = window_show_sys_menu_impl(
-        proxy,
-        get_argument(iter.next()?, resolver.clone())?,
-        get_argument(iter.next()?, resolver.clone())?,
-    );
-
-    match result {
-        Ok(..) => {
-            resolver.resolve(Value::Null);
-        }
-        Err(err) => {
-            resolver.reject(err.to_string());
-        } 
-    }
-
-    Some(())
+pub fn window_minimize(proxy: EventProxy<EventLoopMessage>) -> Result<(), String> {
+    handle_window_buttons(proxy, WindowButton::Minimize)
 }
 
-fn window_set_title_impl(
-    window: DetachedWindow<EventLoopMessage, Wry<EventLoopMessage>>,
-    title: String
-) -> Result<(), tauri_runtime::Error>{
-    window.dispatcher.set_title(title)
+pub fn window_show_sys_menu(proxy: EventProxy<EventLoopMessage>, x: i32, y: i32) -> Result<(), String> {
+    proxy.send_event(EventLoopMessage::WindowShowSysMenu { x, y })
+        .map_err(|err| err.to_string())
 }
 
-pub fn window_set_title(
-    window: DetachedWindow<EventLoopMessage, Wry<EventLoopMessage>>,
-    invoke: Invoke
-) -> Option<()> {
-    let arguments = deserialize_arguments(invoke.clone())?;
-    let mut iter = arguments.iter();
-    let resolver = invoke.resolver.clone();
-
-    let result = window_set_title_impl(
-        window,
-        get_argument(iter.next()?, resolver.clone())?
-    );
-
-    match result {
-        Ok(..) => {
-            resolver.resolve(Value::Null);
-        }
-        Err(err) => {
-            resolver.reject(err.to_string());
-        } 
-    }
-
-    Some(())
+pub fn window_set_title(window: Window, title: String) -> Result<(), String> {
+    window.dispatcher.set_title(title).map_err(|err| err.to_string())
 }
 
-#[derive(Deserialize, Debug)]
-pub struct MessageBoxOptions {
-    pub message: String,
+crate::ts_interface! {
+    #[derive(Deserialize, Debug, Default)]
+    pub struct MessageBoxOptions {
+        pub message: String,
 
-    #[serde(default)]
-    pub dialog_type: String,
+        #[serde(default)]
+        pub dialog_type: String,
 
-    #[serde(default)]
-    pub buttons: Vec<String>,
+        #[serde(default)]
+        pub buttons: Vec<String>,
 
-    #[serde(default)]
-    pub default_id: u32,
+        #[serde(default)]
+        pub default_id: u32,
 
-    #[serde(default)]
-    pub title: String,
+        #[serde(default)]
+        pub title: String,
 
-    #[serde(default)]
-    pub detail: String,
+        #[serde(default)]
+        pub detail: String,
 
-    #[serde(default)]
-    pub checkbox_label: String,
+        #[serde(default)]
+        pub checkbox_label: String,
 
-    #[serde(default)]
-    pub checkbox_checked: bool,
+        #[serde(default)]
+        pub checkbox_checked: bool,
 
-    #[serde(default)]
-    pub no_link: bool
+        #[serde(default)]
+        pub no_link: bool
+    }
 }
 
-fn show_message_box_impl(
+crate::ts_interface! {
+    #[derive(Serialize, Debug)]
+    #[serde(rename_all = "camelCase")]
+    pub struct MessageBoxResponse {
+        /// Common button id, or 100 plus the index of a custom button
+        pub response: u16,
+        pub checkbox_checked: bool
+    }
+}
+
+/// Shows a task dialog above `hwnd`, 0 for none, and returns the response
+/// as in `MessageBoxResponse`.
+#[cfg(all(windows, not(test)))]
+pub(crate) fn show_message_box_impl(
     hwnd: isize,
     options: MessageBoxOptions
-) -> Result<(co::DLGID, bool), String> {
+) -> Result<(u16, bool), String> {
     let hwnd = unsafe {
         HWND::from_ptr(hwnd as *mut isize)
     };
@@ -261,70 +208,61 @@ fn show_message_box_impl(
     let (dlgid, _) = TaskDialogIndirect(&tdc, verification_flag_checked)
         .map_err(|err| err.to_string())?;
 
-    Ok((dlgid, flag_checked))
+    Ok((u16::from(dlgid), flag_checked))
 }
 
-pub fn show_message_box(
-    window: DetachedWindow<EventLoopMessage, Wry<EventLoopMessage>>,
-    invoke: Invoke
-) -> () {
-    fn thread_main(hwnd: isize, invoke: Invoke) -> Option<()> {
-        let arguments = deserialize_arguments(invoke.clone())?;
-        let mut iter = arguments.iter();
-        let resolver = invoke.resolver.clone();
-        
-        let result = show_message_box_impl(
-            hwnd,
-            get_argument(iter.next()?, resolver.clone())?
-        );
-        match result {
-            Ok((response, checkbox_checked)) => {
-                resolver.resolve(serde_json::json!({ 
-                    "response": u16::from(response),
-                    "checkboxChecked": checkbox_checked
-                }));
-            },
-            Err(err) => {
-                resolver.reject(err.to_string());
-            }
-        };
-        Some(())
+/// Without Windows, and in tests, the message goes to stderr and counts as
+/// confirmed with OK.
+#[cfg(any(not(windows), test))]
+pub(crate) fn show_message_box_impl(
+    _hwnd: isize,
+    options: MessageBoxOptions
+) -> Result<(u16, bool), String> {
+    const IDOK: u16 = 1;
+    if options.detail.is_empty() {
+        eprintln!("{}", options.message);
+    } else {
+        eprintln!("{}: {}", options.message, options.detail);
     }
-    
-    std::thread::spawn(move || {
-        let hwnd = window.dispatcher.hwnd().unwrap();
-        thread_main(hwnd.0, invoke);
-    });
+    Ok((IDOK, options.checkbox_checked))
 }
 
+#[cfg(windows)]
+fn window_handle(window: &Window) -> Result<isize, String> {
+    window.dispatcher.hwnd().map(|hwnd| hwnd.0).map_err(|err| err.to_string())
+}
 
-pub fn autostart_registry_execute_command(invoke: Invoke) -> Option<()> {
-    let arguments = deserialize_arguments(invoke.clone())?;
-    let mut iter = arguments.iter();
-    let resolver = invoke.resolver.clone();
+#[cfg(not(windows))]
+fn window_handle(_window: &Window) -> Result<isize, String> {
+    Ok(0)
+}
 
-    let result = win32::autostart_registry_execute_command(
-        get_argument(iter.next()?, resolver.clone())?
-    );
+/// Resolves once the dialog is closed, the dialog itself runs on a thread of its own.
+pub fn show_message_box(window: Window, options: MessageBoxOptions) -> Deferred<MessageBoxResponse> {
+    deferred(async move {
+        let hwnd = window_handle(&window)?;
+        let (response, checkbox_checked) =
+            workers::spawn_blocking(move || show_message_box_impl(hwnd, options)).await?;
+        Ok::<_, String>(MessageBoxResponse { response, checkbox_checked })
+    })
+}
 
-    resolver.resolve(result);
-    
-    Some(())
+#[cfg(windows)]
+pub fn autostart_registry_execute_command(command: AutostartCommand) -> Result<Option<bool>, String> {
+    Ok(win32::autostart_registry_execute_command(command))
 }
 
-pub fn clipboard_write_text_secure(invoke: Invoke) -> Option<()> {
-    let arguments = deserialize_arguments(invoke.clone())?;
-    let mut iter = arguments.iter();
-    let resolver = invoke.resolver.clone();
+#[cfg(not(windows))]
+pub fn autostart_registry_execute_command(_command: AutostartCommand) -> Result<Option<bool>, String> {
+    Err("Autostart is only available on Windows".to_string())
+}
 
-    let result = win32::clipboard_write_text_secure(
-        get_argument(iter.next()?, resolver.clone())?
-    );
+#[cfg(windows)]
+pub fn clipboard_write_text_secure(text: String) -> Result<(), String> {
+    win32::clipboard_write_text_secure(text).map_err(|err| err.to_string())
+}
 
-    match result {
-        Ok(..) => resolver.resolve(Value::Null),
-        Err(err) => resolver.reject(err.to_string())
-    }
-    
-    Some(())
-}
\ No newline at end of file
+#[cfg(not(windows))]
+pub fn clipboard_write_text_secure(_text: String) -> Result<(), String> {
+    Err("The secure clipboard is only available on Windows".to_string())
+}
@@ -1,54 +1,206 @@
 use bson::{Bson, Document};
-use serde::Deserialize;
-use std::{fs::{File, self}};
+use lazy_static::lazy_static;
+use std::{
+    fs::{File, self}, io::{Seek, SeekFrom, Write}, path::{Path, PathBuf}, time::{Duration, SystemTime},
+    sync::{Mutex, Condvar, Once, atomic::{AtomicBool, Ordering}}
+};
+use serde::Serialize;
 use serde_json::Value;
+use tauri_runtime::EventLoopProxy;
+use tauri_runtime_wry::EventProxy;
 
-use crate::{ipc::{Invoke, deserialize_arguments, get_argument}};
+use crate::{
+    settings::{Settings, Profiles, DEFAULT_PROFILE}, events::{ConfigChangeEvent, EventLoopMessage}, commands, history, vault,
+    overrides::{self, ConfigLayer}
+};
+#[cfg(windows)]
+use crate::win32;
+
+pub(crate) const SCHEMA_VERSION_KEY: &str = "schemaVersion";
+
+/// Layout version of the documents written by this build
+const SCHEMA_VERSION: i32 = 5;
+
+type Migration = fn(&mut Document) -> Result<(), String>;
+
+/// `MIGRATIONS[n]` upgrades a document from version `n` to `n + 1`.
+/// Documents written before versioning was introduced count as version 0.
+static MIGRATIONS: &[Migration] = &[
+    migrate_unversioned,
+    migrate_to_profiles,
+    migrate_add_history,
+    migrate_quiz_per_phrase,
+    migrate_sparse_profiles,
+];
+
+/// Set when `user.db` was written by a newer build or couldn't be migrated;
+/// the document is still readable but never overwritten.
+static READ_ONLY: AtomicBool = AtomicBool::new(false);
+
+/// Number of previous generations kept as `user.db.1` (newest) to `user.db.N`
+const BACKUP_COUNT: u32 = 3;
 
 enum Access {
     Read, Write
 }
 
-fn file_access(access: Access) -> Result<File, String> {
-    let mut config_path = std::env::current_exe().unwrap();
-    config_path.pop();
-    config_path.push("data");
-    if !config_path.exists() {
-        fs::create_dir(&config_path).map_err(|e| e.to_string())?
+/// Set by `--portable`, a `portable` file next to the executable has the same effect
+static PORTABLE: AtomicBool = AtomicBool::new(false);
+static MIGRATE_EXECUTABLE_DATA: Once = Once::new();
+
+lazy_static! {
+    /// Modification time of `user.db` after our last write, the directory
+    /// watcher ignores the file as long as it still has it
+    static ref LAST_WRITTEN: Mutex<Option<SystemTime>> = Mutex::new(None);
+    /// Outcome of recovering a damaged `user.db`, kept until the file is written
+    /// again so later reads neither recover nor ask the user a second time
+    static ref RECOVERED: Mutex<Option<Document>> = Mutex::new(None);
+    /// Why `READ_ONLY` was set, part of the error every rejected write returns
+    static ref READ_ONLY_REASON: Mutex<Option<String>> = Mutex::new(None);
+    /// Replaces the data directory, so replaying an IPC trace can't touch the user's settings
+    static ref DATA_DIRECTORY: Mutex<Option<PathBuf>> = Mutex::new(None);
+}
+
+pub fn set_portable(portable: bool) {
+    PORTABLE.store(portable, Ordering::SeqCst);
+}
+
+fn executable_directory() -> PathBuf {
+    let mut directory = std::env::current_exe().unwrap();
+    directory.pop();
+    directory
+}
+
+pub fn set_data_directory(directory: PathBuf) {
+    *DATA_DIRECTORY.lock().unwrap() = Some(directory);
+}
+
+pub fn is_portable() -> bool {
+    PORTABLE.load(Ordering::SeqCst) || executable_directory().join("portable").exists()
+}
+
+/// `%APPDATA%\Mnemonic` on Windows, `$XDG_CONFIG_HOME/mnemonic` elsewhere
+fn user_data_directory() -> Option<PathBuf> {
+    if cfg!(windows) {
+        std::env::var_os("APPDATA").map(|p| PathBuf::from(p).join("Mnemonic"))
+    } else {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|p| PathBuf::from(p).join(".config")))
+            .map(|p| p.join("mnemonic"))
     }
-    config_path.push("user.db");
+}
+
+/// Moves a `user.db` left next to the executable by older builds into the
+/// user data directory, unless that directory already has one. The old file
+/// is only deleted once the copy reads back the same.
+fn migrate_executable_data(directory: &Path) {
+    let legacy = executable_directory().join("data").join("user.db");
+    let target = directory.join("user.db");
+    // left behind by builds that renamed the old file instead of deleting it
+    let renamed = legacy.with_extension("db.migrated");
+    if renamed.exists() && target.exists() {
+        let _ = fs::remove_file(&renamed);
+    }
+    if legacy.exists() && !target.exists() {
+        if fs::copy(&legacy, &target).is_err() {
+            return;
+        }
+        let verified = match (fs::read(&legacy), fs::read(&target)) {
+            (Ok(original), Ok(copy)) => original == copy,
+            _ => false
+        };
+        if verified {
+            let _ = fs::remove_file(&legacy);
+        } else {
+            let _ = fs::remove_file(&target);
+        }
+    }
+}
+
+/// Directory for `user.db` and any other per-user state.
+pub fn data_directory() -> Result<PathBuf, String> {
+    if let Some(directory) = DATA_DIRECTORY.lock().unwrap().clone() {
+        fs::create_dir_all(&directory).map_err(|e| e.to_string())?;
+        return Ok(directory);
+    }
+    let user_directory = if is_portable() { None } else { user_data_directory() };
+    let directory = user_directory.clone()
+        .unwrap_or_else(|| executable_directory().join("data"));
+    if !directory.exists() {
+        fs::create_dir_all(&directory).map_err(|e| e.to_string())?
+    }
+    if user_directory.is_some() {
+        MIGRATE_EXECUTABLE_DATA.call_once(|| migrate_executable_data(&directory));
+    }
+    Ok(directory)
+}
+
+fn config_path() -> Result<PathBuf, String> {
+    Ok(data_directory()?.join("user.db"))
+}
+
+fn file_access(access: Access) -> Result<File, String> {
+    let config_path = config_path()?;
 
     match access {
         Access::Read => {
             File::open(config_path)
         }
         Access::Write => {
-            File::create(config_path)
+            File::create(config_path.with_extension("db.tmp"))
         }
     }.map_err(|err| err.to_string())        
 }
 
-fn iter_prop_tree_mut<'a>(
-    path: &Vec<String>,
-    root_doc: &'a mut Document
-) -> &'a mut Document {
-    let mut current = root_doc;
+/// Path segment that appends to an array instead of addressing an element
+pub(crate) const APPEND_SEGMENT: &str = "-";
+
+fn parse_index(prop: &str, len: usize) -> Result<usize, String> {
+    match prop.parse::<usize>() {
+        Ok(index) if index < len => Ok(index),
+        Ok(index) => Err(format!("index {} is out of bounds for an array of length {}", index, len)),
+        Err(_) => Err(format!("{} isn't a valid array index", prop))
+    }
+}
+
+fn child_or_insert<'a>(current: &'a mut Bson, prop: &str) -> Result<&'a mut Bson, String> {
+    match current {
+        Bson::Document(doc) => {
+            if !matches!(doc.get(prop), Some(Bson::Document(_)) | Some(Bson::Array(_))) {
+                doc.insert(prop, Document::new());
+            }
+            Ok(doc.get_mut(prop).unwrap())
+        }
+        Bson::Array(array) => {
+            if prop == APPEND_SEGMENT {
+                array.push(Bson::Document(Document::new()));
+                return Ok(array.last_mut().unwrap());
+            }
+            let index = parse_index(prop, array.len())?;
+            Ok(&mut array[index])
+        }
+        _ => Err(format!("{} isn't an object or array", prop))
+    }
+}
+
+/// Walks `path` below `root`, creating missing objects on the way. Array
+/// elements are addressed by their index, `-` appends a new element.
+pub(crate) fn iter_prop_tree_mut<'a>(
+    path: &[String],
+    root: &'a mut Bson
+) -> Result<&'a mut Bson, String> {
+    let mut current = root;
 
     for prop in path.iter() {
-        let new = current.get_document(prop)
-            .map_or_else(
-                |_| Document::new(),
-                |d| d.clone()
-            );
-        let _ = current.insert(prop, new);
-        current = current.get_document_mut(prop).unwrap();
+        current = child_or_insert(current, prop)?;
     }
 
-    current
+    Ok(current)
 }
 
-fn get_prop_from_path(
-    path: &Vec<String>,
+pub(crate) fn get_prop_from_path(
+    path: &[String],
     root_doc: Document
 ) -> Option<Bson> {
     let mut current = &Bson::Document(root_doc);
@@ -58,6 +210,9 @@ fn get_prop_from_path(
             Bson::Document(doc) => {
                 current = doc.get(prop)?;
             }
+            Bson::Array(array) => {
+                current = array.get(prop.parse::<usize>().ok()?)?;
+            }
             _ => {
                 return None;
             }
@@ -67,25 +222,893 @@ fn get_prop_from_path(
     Some(current.clone())
 }
 
+/// Sets the value at `path`, creating missing parents. The last segment may
+/// be `-` to append to an array.
+pub(crate) fn set_prop_at_path(
+    path: &[String],
+    root_doc: &mut Document,
+    value: Bson
+) -> Result<(), String> {
+    let (property, parents) = path.split_last()
+        .ok_or("dottedPath musn't be empty".to_string())?;
+
+    let mut root = Bson::Document(std::mem::take(root_doc));
+    let result = match iter_prop_tree_mut(parents, &mut root) {
+        Ok(Bson::Document(doc)) => {
+            doc.insert(property.clone(), value);
+            Ok(())
+        }
+        Ok(Bson::Array(array)) if property == APPEND_SEGMENT => {
+            array.push(value);
+            Ok(())
+        }
+        Ok(Bson::Array(array)) => parse_index(property, array.len())
+            .map(|index| array[index] = value),
+        Ok(_) => Err(format!("{} isn't an object or array", parents.join("."))),
+        Err(err) => Err(err)
+    };
+    if let Bson::Document(doc) = root {
+        *root_doc = doc;
+    }
+    result
+}
+
+fn remove_child(current: &mut Bson, path: &[String]) -> Result<Option<Bson>, String> {
+    let (prop, rest) = match path.split_first() {
+        Some(split) => split,
+        None => return Ok(None)
+    };
+
+    if rest.is_empty() {
+        return match current {
+            Bson::Document(doc) => Ok(doc.remove(prop)),
+            Bson::Array(array) => parse_index(prop, array.len()).map(|index| Some(array.remove(index))),
+            _ => Err(format!("{} isn't an object or array", prop))
+        };
+    }
+
+    let child = match current {
+        Bson::Document(doc) => doc.get_mut(prop),
+        Bson::Array(array) => {
+            let index = parse_index(prop, array.len())?;
+            array.get_mut(index)
+        }
+        _ => return Err(format!("{} isn't an object or array", prop))
+    };
+    match child {
+        Some(child) => remove_child(child, rest),
+        None => Ok(None)
+    }
+}
+
+/// Removes the value at `path` and returns it, `None` if there was nothing
+/// to remove. Array elements after a removed one move up by one.
+pub(crate) fn remove_prop_at_path(
+    path: &[String],
+    root_doc: &mut Document
+) -> Result<Option<Bson>, String> {
+    let mut root = Bson::Document(std::mem::take(root_doc));
+    let result = remove_child(&mut root, path);
+    if let Bson::Document(doc) = root {
+        *root_doc = doc;
+    }
+    result
+}
+
+/// Object keys or array indices directly below `path`
+pub(crate) fn list_keys_at_path(
+    path: &[String],
+    root_doc: Document
+) -> Result<Vec<String>, String> {
+    match get_prop_from_path(path, root_doc) {
+        Some(Bson::Document(doc)) => Ok(doc.keys().cloned().collect()),
+        Some(Bson::Array(array)) => Ok((0..array.len()).map(|i| i.to_string()).collect()),
+        Some(_) => Err(format!("{} isn't an object or array", path.join("."))),
+        None => Err(format!("{} doesn't exist", path.join(".")))
+    }
+}
+
+fn migrate_unversioned(_document: &mut Document) -> Result<(), String> {
+    // the untyped layout already matches the first typed schema
+    Ok(())
+}
+
+fn migrate_to_profiles(document: &mut Document) -> Result<(), String> {
+    // the settings of a single user become the default profile
+    let mut settings = std::mem::take(document);
+    if let Some(version) = settings.remove(SCHEMA_VERSION_KEY) {
+        document.insert(SCHEMA_VERSION_KEY, version);
+    }
+    let mut profiles = Document::new();
+    profiles.insert(DEFAULT_PROFILE, settings);
+    document.insert("activeProfile", DEFAULT_PROFILE);
+    document.insert("profiles", profiles);
+    Ok(())
+}
+
+fn migrate_add_history(_document: &mut Document) -> Result<(), String> {
+    // the history starts empty, older builds just mustn't read it
+    Ok(())
+}
+
+fn migrate_quiz_per_phrase(document: &mut Document) -> Result<(), String> {
+    // the single schedule can't be attributed to a phrase, every phrase
+    // starts its own schedule with its next quiz
+    if let Ok(profiles) = document.get_document_mut("profiles") {
+        for (_, settings) in profiles.iter_mut() {
+            if let Bson::Document(settings) = settings {
+                settings.remove("quiz");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Drops the keys of `document` that hold the same value as `defaults`,
+/// including objects left empty by that.
+fn remove_defaults(document: &mut Document, defaults: &Document) {
+    let keys: Vec<String> = document.keys().cloned().collect();
+    for key in keys {
+        let remove = match (document.get_mut(&key), defaults.get(&key)) {
+            (Some(Bson::Document(child)), Some(Bson::Document(default))) => {
+                remove_defaults(child, default);
+                child.is_empty()
+            }
+            (Some(value), Some(default)) => &*value == default,
+            _ => false
+        };
+        if remove {
+            document.remove(&key);
+        }
+    }
+}
+
+fn migrate_sparse_profiles(document: &mut Document) -> Result<(), String> {
+    // every key used to be written, only the values that differ from the
+    // defaults can have been set on purpose
+    let defaults = settings_to_document(&Settings::default());
+    if let Ok(profiles) = document.get_document_mut("profiles") {
+        for (_, settings) in profiles.iter_mut() {
+            if let Bson::Document(settings) = settings {
+                remove_defaults(settings, &defaults);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn document_version(document: &Document) -> i32 {
+    match document.get(SCHEMA_VERSION_KEY) {
+        Some(Bson::Int32(version)) => *version,
+        Some(Bson::Int64(version)) => *version as i32,
+        _ => 0
+    }.max(0)
+}
+
+fn backup_document(version: i32) -> Result<(), String> {
+    let path = config_path()?;
+    let backup = path.with_extension(format!("db.v{}.bak", version));
+    fs::copy(path, backup).map(|_| ()).map_err(|err| err.to_string())
+}
+
+fn backup_path(path: &Path, generation: u32) -> PathBuf {
+    path.with_extension(format!("db.{}", generation))
+}
+
+fn rotate_backups(path: &Path) -> Result<(), String> {
+    if !path.exists() {
+        return Ok(());
+    }
+    for generation in (1..BACKUP_COUNT).rev() {
+        let older = backup_path(path, generation);
+        if older.exists() {
+            fs::rename(&older, backup_path(path, generation + 1)).map_err(|err| err.to_string())?;
+        }
+    }
+    // only rotate documents that can be read back, a corrupt file would push out a good backup
+    if File::open(path).ok().and_then(|f| Document::from_reader(f).ok()).is_some() {
+        fs::copy(path, backup_path(path, 1)).map_err(|err| err.to_string())?;
+    }
+    Ok(())
+}
+
+/// Overwrites the content of `path` in place, so the old content doesn't stay
+/// behind on disk as a renamed file would leave it.
+fn overwrite_file(path: &Path, data: &[u8]) -> Result<(), String> {
+    let mut file = fs::OpenOptions::new().write(true).open(path).map_err(|err| err.to_string())?;
+    let length = file.metadata().map_err(|err| err.to_string())?.len();
+    // zeros over all of it first, `data` may be shorter
+    file.write_all(&vec![0; length as usize]).map_err(|err| err.to_string())?;
+    file.sync_all().map_err(|err| err.to_string())?;
+    file.seek(SeekFrom::Start(0)).map_err(|err| err.to_string())?;
+    file.write_all(data).map_err(|err| err.to_string())?;
+    file.set_len(data.len() as u64).map_err(|err| err.to_string())?;
+    file.sync_all().map_err(|err| err.to_string())
+}
+
+fn shred_file(path: &Path) -> Result<(), String> {
+    overwrite_file(path, &[])?;
+    fs::remove_file(path).map_err(|err| err.to_string())
+}
+
+/// Seals the backups of `user.db` that were written before encryption was
+/// enabled and shreds the copies that can't be sealed: the damaged file kept
+/// by the recovery and the file left behind by the move out of the executable
+/// directory.
+pub(crate) fn seal_plaintext_copies() -> Result<(), String> {
+    let path = config_path()?;
+    let backups = (1..=BACKUP_COUNT).map(|generation| backup_path(&path, generation))
+        .chain((0..SCHEMA_VERSION).map(|version| path.with_extension(format!("db.v{}.bak", version))));
+    for backup in backups.filter(|backup| backup.exists()) {
+        match File::open(&backup).ok().and_then(|f| Document::from_reader(f).ok()) {
+            Some(document) if vault::is_sealed(&document) => (),
+            Some(document) => {
+                let mut buffer = Vec::new();
+                vault::seal_document(document)?.to_writer(&mut buffer).map_err(|err| err.to_string())?;
+                overwrite_file(&backup, &buffer)?;
+            }
+            None => shred_file(&backup)?
+        }
+    }
+    let leftovers = [
+        path.with_extension("db.corrupt"),
+        executable_directory().join("data").join("user.db.migrated")
+    ];
+    for leftover in leftovers.iter().filter(|leftover| leftover.exists()) {
+        shred_file(leftover)?;
+    }
+    Ok(())
+}
+
+/// Writes to `user.db.tmp`, flushes it to disk and renames it over `user.db`,
+/// so a crash leaves either the old or the new document but never a truncated one.
+fn write_document(mut document: Document) -> Result<(), String> {
+    if READ_ONLY.load(Ordering::SeqCst) {
+        return Err(read_only_error());
+    }
+    document.insert(SCHEMA_VERSION_KEY, SCHEMA_VERSION);
+    let document = vault::seal_document(document)?;
+
+    let mut buffer = Vec::new();
+    document.to_writer(&mut buffer).map_err(|err| err.to_string())?;
+
+    let mut temp_file = file_access(Access::Write)?;
+    temp_file.write_all(&buffer).map_err(|err| err.to_string())?;
+    temp_file.sync_all().map_err(|err| err.to_string())?;
+    drop(temp_file);
+
+    let path = config_path()?;
+    rotate_backups(&path)?;
+    fs::rename(path.with_extension("db.tmp"), &path).map_err(|err| err.to_string())?;
+    *LAST_WRITTEN.lock().unwrap() = modified_time(&path);
+    *RECOVERED.lock().unwrap() = None;
+    Ok(())
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// Replaces `user.db` with `backup` through `user.db.tmp`, like `write_document`.
+fn restore_backup(backup: &Path, path: &Path) -> Result<(), String> {
+    let temp = path.with_extension("db.tmp");
+    fs::copy(backup, &temp).map_err(|err| err.to_string())?;
+    fs::OpenOptions::new().write(true).open(&temp)
+        .and_then(|file| file.sync_all())
+        .map_err(|err| err.to_string())?;
+    fs::rename(&temp, path).map_err(|err| err.to_string())
+}
+
+/// Keeps `user.db` from being written for the rest of the session. Only the
+/// first reason is kept and shown to the user.
+fn set_read_only(reason: String) {
+    READ_ONLY.store(true, Ordering::SeqCst);
+    let mut current = READ_ONLY_REASON.lock().unwrap();
+    if current.is_none() {
+        *current = Some(reason.clone());
+        std::thread::spawn(move || {
+            let _ = commands::show_message_box_impl(0, commands::MessageBoxOptions {
+                message: "Your settings are opened read-only".to_string(),
+                detail: format!("Changes to your settings won't be saved, user.db {}.", reason),
+                dialog_type: "warning".to_string(),
+                title: "Mnemonic".to_string(),
+                no_link: true,
+                ..Default::default()
+            });
+        });
+    }
+}
+
+fn read_only_error() -> String {
+    match READ_ONLY_REASON.lock().unwrap().as_ref() {
+        Some(reason) => format!("user.db is opened read-only, {}", reason),
+        None => "user.db is opened read-only".to_string()
+    }
+}
+
+fn notify_recovery(message: String) {
+    // the dialog blocks, so it must not hold up the caller reading the settings
+    std::thread::spawn(move || {
+        let _ = commands::show_message_box_impl(0, commands::MessageBoxOptions {
+            message: "Your settings file was damaged".to_string(),
+            detail: message,
+            dialog_type: "warning".to_string(),
+            title: "Mnemonic".to_string(),
+            no_link: true,
+            ..Default::default()
+        });
+    });
+}
+
+/// Called when `user.db` exists but can't be parsed. Keeps the damaged file
+/// as `user.db.corrupt` and restores the newest backup that still parses.
+/// Only the first call per damaged file recovers, later ones get its result.
+fn recover_corrupt_document() -> Document {
+    let mut recovered = RECOVERED.lock().unwrap();
+    if let Some(document) = recovered.as_ref() {
+        return document.clone();
+    }
+    let document = recover_from_backups();
+    *recovered = Some(document.clone());
+    document
+}
+
+fn recover_from_backups() -> Document {
+    let path = match config_path() {
+        Ok(path) => path,
+        Err(_) => return Document::new()
+    };
+    let _ = fs::copy(&path, path.with_extension("db.corrupt"));
+
+    for generation in 1..=BACKUP_COUNT {
+        let backup = backup_path(&path, generation);
+        let document = File::open(&backup).ok().and_then(|f| Document::from_reader(f).ok());
+        if let Some(document) = document {
+            if restore_backup(&backup, &path).is_ok() {
+                notify_recovery(format!(
+                    "The last good copy of your settings has been restored from {}.",
+                    backup.display()
+                ));
+                return document;
+            }
+        }
+    }
+
+    notify_recovery(
+        "No usable backup was found, all settings have been reset to their defaults. The damaged file was kept as user.db.corrupt.".to_string()
+    );
+    Document::new()
+}
+
+/// Applies the steps from `version` on. A failing step leaves `document` as
+/// the last successful one did.
+fn apply_migrations(document: &mut Document, version: i32) -> Result<(), String> {
+    for migration in MIGRATIONS[version as usize..].iter() {
+        let mut migrated = document.clone();
+        migration(&mut migrated)?;
+        *document = migrated;
+    }
+    Ok(())
+}
+
+/// Brings a document read from disk up to `SCHEMA_VERSION`, keeping a copy of
+/// the original file. On failure `document` holds the steps that succeeded.
+fn migrate_document(document: &mut Document) -> Result<(), String> {
+    let version = document_version(document);
+    if version > SCHEMA_VERSION {
+        set_read_only("it was written by a newer version of Mnemonic".to_string());
+        return Ok(());
+    }
+
+    if version < SCHEMA_VERSION {
+        backup_document(version)?;
+        apply_migrations(document, version)?;
+        write_document(document.clone())?;
+    }
+    Ok(())
+}
+
+/// `user.db` as stored, without opening a sealed document.
+pub(crate) fn read_raw_document() -> Result<Document, String> {
+    let file = file_access(Access::Read)?;
+    Document::from_reader(file).map_err(|err| err.to_string())
+}
+
 fn read_get_document_safe() -> Document {
     let access = file_access(Access::Read);
     match access {
         Ok(file) => {
-            Document::from_reader(file).map_or_else(
-                |_| Document::new(),
-                |d| d
-            )
+            let document = Document::from_reader(file)
+                .unwrap_or_else(|_| recover_corrupt_document());
+            let mut document = vault::open_document(document);
+            if vault::is_locked() {
+                // only migrated in memory, the file is migrated once the sealed part can be read
+                let version = document_version(&document);
+                if version < SCHEMA_VERSION {
+                    let _ = apply_migrations(&mut document, version);
+                }
+                document.remove(SCHEMA_VERSION_KEY);
+                return document;
+            }
+            if let Err(err) = migrate_document(&mut document) {
+                // what could be migrated is still shown, it just isn't written back
+                set_read_only(format!("it couldn't be migrated ({})", err));
+            }
+            document.remove(SCHEMA_VERSION_KEY);
+            document
         }
         Err(_) => Document::new()
     }
 }
 
-fn js_config_set_property_impl(
+pub(crate) fn settings_from_document(document: Document) -> Result<Settings, String> {
+    let settings: Settings = bson::from_document(document).map_err(|err| err.to_string())?;
+    settings.validate()?;
+    Ok(settings)
+}
+
+pub(crate) fn settings_to_document(settings: &Settings) -> Document {
+    bson::to_document(settings).expect("Settings always serialize to a document")
+}
+
+pub(crate) fn profiles_from_document(document: Document) -> Result<Profiles, String> {
+    let profiles: Profiles = bson::from_document(document).map_err(|err| err.to_string())?;
+    profiles.validate()?;
+    Ok(profiles)
+}
+
+pub(crate) fn profiles_to_document(profiles: &Profiles) -> Document {
+    bson::to_document(profiles).expect("Profiles always serialize to a document")
+}
+
+/// Recursively copies `source` into `target`, replacing everything but objects
+/// present in both.
+pub(crate) fn merge_documents(target: &mut Document, source: Document) {
+    for (key, value) in source {
+        match (target.get_mut(&key), value) {
+            (Some(Bson::Document(existing)), Bson::Document(incoming)) => {
+                merge_documents(existing, incoming);
+            }
+            (_, value) => {
+                target.insert(key, value);
+            }
+        }
+    }
+}
+
+struct ConfigState {
+    /// `None` until the first access reads `user.db`
+    settings: Option<Profiles>,
+    /// What is written to `user.db`. Profiles only hold the keys that were
+    /// set, every other setting keeps following the policy or its default.
+    stored: Document,
+    /// Set while the cached settings are newer than the file
+    dirty: bool,
+}
+
+/// Process wide owner of the settings. Reads are served from memory and
+/// writes are flushed by a background thread once they stop coming in.
+struct ConfigStore {
+    state: Mutex<ConfigState>,
+    flush_requested: Condvar,
+    /// Held while `user.db` is written, so flushes land in order and a reload
+    /// never reads the file a flush is about to replace. Locked before `state`.
+    writing: Mutex<()>,
+}
+
+lazy_static! {
+    static ref STORE: ConfigStore = ConfigStore {
+        state: Mutex::new(ConfigState { settings: None, stored: Document::new(), dirty: false }),
+        flush_requested: Condvar::new(),
+        writing: Mutex::new(()),
+    };
+}
+
+static FLUSH_THREAD: Once = Once::new();
+
+/// Writes arriving within this delay after the first one are flushed together
+const WRITE_BEHIND_DELAY: Duration = Duration::from_millis(500);
+
+/// Changes to the data directory are picked up once it stayed unchanged this long
+const RELOAD_DEBOUNCE: Duration = Duration::from_millis(250);
+
+/// The profiles stored in `user.db`. A document that doesn't match the schema,
+/// e.g. because of a single mistyped key, makes the store read-only: falling
+/// back to the defaults alone would let the next flush write over the file.
+fn read_profiles() -> (Profiles, Document) {
+    let document = read_get_document_safe();
+    let profiles = profiles_from_document(document.clone()).unwrap_or_else(|err| {
+        set_read_only(format!("it doesn't match the settings schema ({})", err));
+        Profiles::default()
+    });
+    (profiles, document)
+}
+
+fn load_cached(state: &mut ConfigState) -> &mut Profiles {
+    if state.settings.is_none() {
+        let (profiles, stored) = read_profiles();
+        state.stored = stored;
+        state.settings = Some(profiles);
+    }
+    state.settings.as_mut().unwrap()
+}
+
+/// Keys of the named profile that are stored in `user.db`
+fn stored_profile(stored: &Document, name: &str) -> Document {
+    stored.get_document("profiles").ok()
+        .and_then(|profiles| profiles.get_document(name).ok())
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// Brings the stored document up to date with `new`. Only the settings that
+/// changed are stored, a new profile starts with the ones that differ from
+/// the defaults. `removed` settings are dropped from the stored document
+/// even though `new` still has their default value.
+fn record_stored_changes(
+    stored: &mut Document,
+    old: &Profiles,
+    new: &Profiles,
+    removed: &[(String, Vec<String>)]
+) {
+    let new_document = profiles_to_document(new);
+    stored.insert("activeProfile", new.active_profile.clone());
+    match new_document.get("history") {
+        Some(history) => stored.insert("history", history.clone()),
+        None => stored.remove("history")
+    };
+
+    let defaults = settings_to_document(&Settings::default());
+    let mut profiles = Document::new();
+    for (name, settings) in new.profiles.iter() {
+        let old_settings = old.profiles.get(name).map_or(defaults.clone(), settings_to_document);
+        let new_settings = settings_to_document(settings);
+        let mut changed = Vec::new();
+        diff_documents(&old_settings, &new_settings, &mut Vec::new(), &mut changed);
+
+        let mut profile = stored_profile(stored, name);
+        for path in changed {
+            let _ = match get_prop_from_path(&path, new_settings.clone()) {
+                Some(value) => set_prop_at_path(&path, &mut profile, value),
+                None => remove_prop_at_path(&path, &mut profile).map(|_| ())
+            };
+        }
+        for (_, path) in removed.iter().filter(|(profile, _)| profile == name) {
+            if path.is_empty() {
+                profile = Document::new();
+            } else {
+                let _ = remove_prop_at_path(path, &mut profile);
+            }
+        }
+        profiles.insert(name.clone(), profile);
+    }
+    stored.insert("profiles", profiles);
+}
+
+impl ConfigStore {
+    fn read(&self) -> Profiles {
+        let mut state = self.state.lock().unwrap();
+        load_cached(&mut state).clone()
+    }
+
+    /// The named profile, the active one for `None`, and its keys stored in `user.db`.
+    fn read_profile(&self, profile: Option<&str>) -> Result<(Settings, Document), String> {
+        let mut state = self.state.lock().unwrap();
+        let profiles = load_cached(&mut state);
+        let name = profile.unwrap_or(&profiles.active_profile).to_string();
+        let settings = profiles.get(Some(&name))?.clone();
+        Ok((settings, stored_profile(&state.stored, &name)))
+    }
+
+    /// Updates the settings of a single profile, the active one for `None`.
+    fn update<F>(&self, profile: Option<&str>, f: F) -> Result<(), String>
+    where
+        F: FnOnce(&Settings) -> Result<Settings, String>
+    {
+        self.update_profiles(|current| {
+            let name = profile.unwrap_or(&current.active_profile).to_string();
+            let updated = f(current.get(Some(&name))?)?;
+            let mut profiles = current.clone();
+            profiles.profiles.insert(name, updated);
+            Ok(profiles)
+        })
+    }
+
+    /// Removes a setting of a single profile from `user.db`, it falls back
+    /// to the policy or its default. An empty path removes all of them.
+    fn remove(&self, profile: Option<&str>, path: Vec<String>) -> Result<(), String> {
+        self.write(|current| {
+            let name = profile.unwrap_or(&current.active_profile).to_string();
+            let settings = if path.is_empty() {
+                Settings::default()
+            } else {
+                let mut document = settings_to_document(current.get(Some(&name))?);
+                remove_prop_at_path(&path, &mut document)?;
+                settings_from_document(document)?
+            };
+            let mut profiles = current.clone();
+            profiles.profiles.insert(name.clone(), settings);
+            Ok((profiles, vec![(name, path)]))
+        })
+    }
+
+    fn update_profiles<F>(&self, f: F) -> Result<(), String>
+    where
+        F: FnOnce(&Profiles) -> Result<Profiles, String>
+    {
+        self.write(|current| Ok((f(current)?, Vec::new())))
+    }
+
+    /// Applies `f`, which returns the new profiles and the settings it
+    /// removes by profile name.
+    fn write<F>(&self, f: F) -> Result<(), String>
+    where
+        F: FnOnce(&Profiles) -> Result<(Profiles, Vec<(String, Vec<String>)>), String>
+    {
+        let mut state = self.state.lock().unwrap();
+        let (updated, removed) = f(load_cached(&mut state))?;
+        if &updated == load_cached(&mut state) && removed.is_empty() {
+            // nothing changed, nothing to write
+            return Ok(());
+        }
+        updated.validate()?;
+        // whole documents, imports and reverts may reach locked settings
+        // that the single path checks of the commands never see
+        for (name, settings) in updated.profiles.iter() {
+            if let Some(current) = load_cached(&mut state).profiles.get(name) {
+                overrides::check_changes_writable(current, settings)?;
+            }
+        }
+        if READ_ONLY.load(Ordering::SeqCst) {
+            return Err(read_only_error());
+        }
+        if vault::is_locked() {
+            return Err("Settings are locked, unlock them before making changes".to_string());
+        }
+
+        let mut updated = updated;
+        let current = load_cached(&mut state).clone();
+        history::record(&current, &mut updated);
+        notify_changes(current.active(), updated.active());
+        record_stored_changes(&mut state.stored, &current, &updated, &removed);
+        state.settings = Some(updated);
+        state.dirty = true;
+        FLUSH_THREAD.call_once(|| {
+            std::thread::spawn(flush_loop);
+        });
+        self.flush_requested.notify_one();
+        Ok(())
+    }
+
+    /// Writes pending changes. The file is written without holding `state`,
+    /// reads and further changes go on meanwhile.
+    fn flush(&self) -> Result<(), String> {
+        let _writing = self.writing.lock().unwrap();
+        let stored = {
+            let mut state = self.state.lock().unwrap();
+            if !state.dirty {
+                return Ok(());
+            }
+            load_cached(&mut state);
+            state.dirty = false;
+            state.stored.clone()
+        };
+        write_document(stored).map_err(|err| {
+            // retried with the next flush, along with what changed in between
+            self.state.lock().unwrap().dirty = true;
+            err
+        })
+    }
+
+    /// Writes the cached settings right away, even without pending changes.
+    fn rewrite(&self) -> Result<(), String> {
+        let _writing = self.writing.lock().unwrap();
+        let mut state = self.state.lock().unwrap();
+        load_cached(&mut state);
+        write_document(state.stored.clone())?;
+        state.dirty = false;
+        Ok(())
+    }
+
+    fn is_dirty(&self) -> bool {
+        self.state.lock().unwrap().dirty
+    }
+
+    /// Picks up changes made to `user.db` by another process or by hand.
+    fn reload(&self) {
+        let _writing = self.writing.lock().unwrap();
+        let mut state = self.state.lock().unwrap();
+        if state.dirty || state.settings.is_none() {
+            // pending writes win over the file, an unloaded cache has nothing to compare
+            return;
+        }
+        let written = *LAST_WRITTEN.lock().unwrap();
+        if written.is_some() && config_path().ok().and_then(|path| modified_time(&path)) == written {
+            // our own flush, the cache already has it
+            return;
+        }
+        let (reloaded, stored) = read_profiles();
+        notify_changes(load_cached(&mut state).active(), reloaded.active());
+        state.settings = Some(reloaded);
+        state.stored = stored;
+    }
+
+    /// Reads `user.db` again even if we wrote it last: once the vault key
+    /// changed the same file opens to different settings.
+    fn reopen(&self) {
+        let mut state = self.state.lock().unwrap();
+        let (reopened, stored) = read_profiles();
+        if let Some(current) = state.settings.as_ref() {
+            notify_changes(current.active(), reopened.active());
+        }
+        state.settings = Some(reopened);
+        state.stored = stored;
+        state.dirty = false;
+    }
+}
+
+lazy_static! {
+    static ref EVENT_PROXY: Mutex<Option<EventProxy<EventLoopMessage>>> = Mutex::new(None);
+    /// Observed paths without the leading `globalConfig`
+    static ref SUBSCRIPTIONS: Mutex<Vec<Vec<String>>> = Mutex::new(Vec::new());
+}
+
+pub(crate) fn diff_documents(
+    old: &Document,
+    new: &Document,
+    prefix: &mut Vec<String>,
+    changes: &mut Vec<Vec<String>>
+) {
+    let keys: Vec<&String> = old.keys()
+        .chain(new.keys().filter(|k| !old.contains_key(k.as_str())))
+        .collect();
+
+    for key in keys {
+        prefix.push(key.clone());
+        match (old.get(key), new.get(key)) {
+            (Some(Bson::Document(old_doc)), Some(Bson::Document(new_doc))) => {
+                diff_documents(old_doc, new_doc, prefix, changes);
+            }
+            (old_value, new_value) => {
+                if old_value != new_value {
+                    changes.push(prefix.clone());
+                }
+            }
+        }
+        prefix.pop();
+    }
+}
+
+fn notify_changes(old: &Settings, new: &Settings) {
+    let proxy = match EVENT_PROXY.lock().unwrap().clone() {
+        Some(proxy) => proxy,
+        None => return
+    };
+
+    let new_document = settings_to_document(new);
+    let mut changed = Vec::new();
+    diff_documents(&settings_to_document(old), &new_document, &mut Vec::new(), &mut changed);
+    if changed.is_empty() {
+        return;
+    }
+
+    // a subscription fires if anything below or above it changed
+    let events: Vec<ConfigChangeEvent> = SUBSCRIPTIONS.lock().unwrap()
+        .iter()
+        .filter(|sub| changed.iter().any(|c| c.starts_with(sub) || sub.starts_with(c)))
+        .map(|sub| {
+            let mut path = vec!["globalConfig".to_string()];
+            path.extend(sub.iter().cloned());
+            ConfigChangeEvent { path, value: get_prop_from_path(sub, new_document.clone()) }
+        })
+        .collect();
+
+    if !events.is_empty() {
+        let _ = proxy.send_event(EventLoopMessage::ConfigChanged(events));
+    }
+}
+
+/// Starts forwarding settings changes to the event loop and watching the
+/// data directory for edits made outside this process.
+pub fn install_change_notifications(proxy: EventProxy<EventLoopMessage>) {
+    *EVENT_PROXY.lock().unwrap() = Some(proxy);
+
+    // off Windows edits made outside this process are picked up on the next start
+    #[cfg(windows)]
+    std::thread::spawn(|| {
+        let directory = config_path().ok()
+            .and_then(|p| p.parent().and_then(|p| p.to_str()).map(|p| p.to_string()));
+        if let Some(directory) = directory {
+            let _ = win32::watch_directory(directory, RELOAD_DEBOUNCE, || STORE.reload());
+        }
+    });
+}
+
+fn flush_loop() {
+    loop {
+        {
+            let mut state = STORE.state.lock().unwrap();
+            while !state.dirty {
+                state = STORE.flush_requested.wait(state).unwrap();
+            }
+        }
+        std::thread::sleep(WRITE_BEHIND_DELAY);
+        // a failed flush stays dirty and is retried after the next delay
+        let _ = STORE.flush();
+    }
+}
+
+/// Effective settings of the active profile, falling back to the defaults if
+/// `user.db` is missing or invalid.
+pub fn load_settings() -> Settings {
+    let (settings, stored) = STORE.read_profile(None).expect("the active profile always exists");
+    overrides::apply(&settings, &stored)
+}
+
+/// Effective settings of the named profile, the active one for `None`.
+pub(crate) fn load_profile_settings(profile: Option<&str>) -> Result<Settings, String> {
+    STORE.read_profile(profile).map(|(settings, stored)| overrides::apply(&settings, &stored))
+}
+
+/// Settings of the named profile as stored in `user.db`, without overrides.
+/// Fails while the settings are locked, when only the bootstrap keys are known.
+pub(crate) fn load_stored_settings(profile: Option<&str>) -> Result<Settings, String> {
+    let profiles = STORE.read();
+    if vault::is_locked() {
+        return Err("Settings are locked".to_string());
+    }
+    profiles.get(profile).cloned()
+}
+
+pub(crate) fn load_profiles() -> Profiles {
+    STORE.read()
+}
+
+pub(crate) fn update_profiles<F>(f: F) -> Result<(), String>
+where
+    F: FnOnce(&Profiles) -> Result<Profiles, String>
+{
+    STORE.update_profiles(f)
+}
+
+pub fn update_settings<F: FnOnce(&mut Settings)>(f: F) -> Result<(), String> {
+    STORE.update(None, |current| {
+        let mut settings = current.clone();
+        f(&mut settings);
+        Ok(settings)
+    })
+}
+
+/// Like `update_settings`, for changes to any profile that are computed from
+/// its current settings and may fail.
+pub(crate) fn replace_settings<F>(profile: Option<&str>, f: F) -> Result<(), String>
+where
+    F: FnOnce(&Settings) -> Result<Settings, String>
+{
+    STORE.update(profile, f)
+}
+
+/// Writes pending changes immediately, called before the process exits.
+pub fn flush() -> Result<(), String> {
+    STORE.flush()
+}
+
+/// Re-reads `user.db` after the settings have been locked or unlocked,
+/// dropping the settings opened with the previous key.
+pub(crate) fn reopen() {
+    STORE.reopen()
+}
+
+/// Writes the settings again so they are sealed with the current vault key.
+pub(crate) fn rewrite() -> Result<(), String> {
+    STORE.rewrite()
+}
+
+pub fn js_config_set_property(
     mut dotted_path: Vec<String>,
-    value: Bson
+    value: Bson,
+    profile: Option<String>
 ) -> Result<(), String>{
-    let mut bson_document = read_get_document_safe();
-    
     if dotted_path.is_empty() {
         return Err("dottedPath musn't be empty".to_string());
     }
@@ -95,6 +1118,7 @@ fn js_config_set_property_impl(
     } else {
         return Err("dottedPath must start with globalConfig".to_string());
     }
+    overrides::check_writable(&dotted_path)?;
     let property = match dotted_path.pop() {
         Some(v) => v,
         None => "globalConfig".to_string()
@@ -103,7 +1127,8 @@ fn js_config_set_property_impl(
     if &property == "globalConfig" {
         match value.as_document() {
             Some(write_doc) => {
-                return write_doc.to_writer(file_access(Access::Write)?).map_err(|err| err.to_string());
+                let settings = settings_from_document(write_doc.clone())?;
+                return STORE.update(profile.as_deref(), move |_| Ok(settings));
             }
             None => {
                 return Err("globalConfig has to be an object".to_string());
@@ -111,80 +1136,645 @@ fn js_config_set_property_impl(
         }
     }
 
-    let prop_doc = iter_prop_tree_mut(&dotted_path, &mut bson_document);
-    prop_doc.insert(property, value);
+    dotted_path.push(property);
+    STORE.update(profile.as_deref(), move |current| {
+        let mut bson_document = settings_to_document(current);
+        set_prop_at_path(&dotted_path, &mut bson_document, value)?;
 
-    bson_document.to_writer(file_access(Access::Write)?).map_err(|err| err.to_string())
+        // round trip through the typed model to reject unknown keys and wrong types
+        settings_from_document(bson_document)
+    })
 }
 
-pub fn js_config_set_property(invoke: Invoke) -> Option<()> {
-    let arguments = deserialize_arguments(invoke.clone())?;
-    let mut iter = arguments.iter();
-    let resolver = invoke.resolver.clone();
+#[derive(Serialize)]
+struct ResolvedProperty {
+    value: Option<Bson>,
+    layer: ConfigLayer,
+    /// Locked by policy and therefore read-only
+    locked: bool,
+}
 
-    let result = js_config_set_property_impl(
-        get_argument(iter.next()?, resolver.clone())?,
-        get_argument(iter.next()?, resolver.clone())?
-    );
+fn resolve_property(
+    mut dotted_path: Vec<String>,
+    profile: Option<String>
+) -> Result<ResolvedProperty, String> {
+    // readable while locked, the web-app needs the bootstrap keys to start up
+    let (settings, stored) = STORE.read_profile(profile.as_deref())?;
+    let bson_document = settings_to_document(&overrides::apply(&settings, &stored));
+    
+    dotted_path.drain(0..1);
 
-    match result {
-        Ok(_) => {
-            resolver.resolve(Value::Null);
-        }
-        Err(err) => {
-            resolver.reject(err);
-        }
+    Ok(ResolvedProperty {
+        value: get_prop_from_path(&dotted_path, bson_document),
+        layer: overrides::source_of(&dotted_path, &stored),
+        locked: overrides::is_locked(&dotted_path),
+    })
+}
+
+/// Returns the effective value, or the whole `ResolvedProperty` with `withSource`.
+pub fn js_config_get_property(
+    dotted_path: Vec<String>,
+    profile: Option<String>,
+    with_source: Option<bool>
+) -> Result<Value, String> {
+    let resolved = resolve_property(dotted_path, profile)?;
+    let value = if with_source == Some(true) {
+        serde_json::to_value(resolved)
+    } else {
+        serde_json::to_value(resolved.value)
+    };
+    value.map_err(|err| err.to_string())
+}
+
+/// Removes a key or array element. Keys of the typed settings fall back to
+/// the policy or their default value.
+pub fn js_config_delete_property(
+    dotted_path: Vec<String>,
+    profile: Option<String>
+) -> Result<(), String> {
+    let dotted_path = strip_global_config(dotted_path)?;
+    if !dotted_path.is_empty() {
+        overrides::check_writable(&dotted_path)?;
     }
+    STORE.remove(profile.as_deref(), dotted_path)
+}
 
-    Some(())
+pub fn js_config_list_keys(
+    dotted_path: Vec<String>,
+    profile: Option<String>
+) -> Result<Vec<String>, String> {
+    let dotted_path = strip_global_config(dotted_path)?;
+    let settings = load_profile_settings(profile.as_deref())?;
+    list_keys_at_path(&dotted_path, settings_to_document(&settings))
 }
 
-fn js_config_get_property_impl(
-    mut dotted_path: Vec<String>,
-) -> Option<Bson> {
-    let bson_document = read_get_document_safe();
-    
+/// Writes `value` only if the current value still equals `expected`, `null`
+/// standing for a missing key. Returns whether the write happened.
+pub fn js_config_compare_and_swap(
+    dotted_path: Vec<String>,
+    expected: Value,
+    value: Bson,
+    profile: Option<String>
+) -> Result<bool, String> {
+    let dotted_path = strip_global_config(dotted_path)?;
+    if dotted_path.is_empty() {
+        return Err("dottedPath must point below globalConfig".to_string());
+    }
+    overrides::check_writable(&dotted_path)?;
+
+    let mut swapped = false;
+    STORE.update(profile.as_deref(), |current| {
+        let mut bson_document = settings_to_document(current);
+        let actual = get_prop_from_path(&dotted_path, bson_document.clone())
+            .map_or(Value::Null, |v| v.into_relaxed_extjson());
+        if actual != expected {
+            return Ok(current.clone());
+        }
+        set_prop_at_path(&dotted_path, &mut bson_document, value)?;
+        let settings = settings_from_document(bson_document)?;
+        swapped = true;
+        Ok(settings)
+    })?;
+    Ok(swapped)
+}
+
+fn promise_is_file() -> bool {
+    // a write that hasn't been flushed yet still counts
+    STORE.is_dirty() || file_access(Access::Read).is_ok()
+}
+
+pub fn js_promise_is_file() -> Result<bool, String> {
+    Ok(promise_is_file())
+}
+
+pub(crate) fn strip_global_config(mut dotted_path: Vec<String>) -> Result<Vec<String>, String> {
+    if dotted_path.first().map(|p| p.as_str()) != Some("globalConfig") {
+        return Err("dottedPath must start with globalConfig".to_string());
+    }
     dotted_path.drain(0..1);
+    Ok(dotted_path)
+}
 
-    get_prop_from_path(&dotted_path, bson_document)
+pub fn js_config_observe_property(dotted_path: Vec<String>) -> Result<(), String> {
+    let path = strip_global_config(dotted_path)?;
+    SUBSCRIPTIONS.lock().unwrap().push(path);
+    Ok(())
 }
 
-pub fn js_config_get_property(invoke: Invoke) -> Option<()> {
-    let arguments = deserialize_arguments(invoke.clone())?;
-    let mut iter = arguments.iter();
-    let resolver = invoke.resolver.clone();
+/// Forgets every observed path, the page that observed them is gone.
+pub(crate) fn clear_subscriptions() {
+    SUBSCRIPTIONS.lock().unwrap().clear();
+}
 
-    let value = js_config_get_property_impl(
-        get_argument(iter.next()?, resolver.clone())?,
-    );
+pub fn js_config_unobserve_property(dotted_path: Vec<String>) -> Result<(), String> {
+    let path = strip_global_config(dotted_path)?;
+    let mut subscriptions = SUBSCRIPTIONS.lock().unwrap();
+    if let Some(index) = subscriptions.iter().position(|sub| sub == &path) {
+        subscriptions.remove(index);
+    }
+    Ok(())
+}
+
+pub fn js_config_is_read_only() -> Result<bool, String> {
+    let _ = load_settings();
+    Ok(READ_ONLY.load(Ordering::SeqCst))
+}
 
-    resolver.resolve(value);
+/// Why the settings are read-only, `None` while they can be written
+pub fn js_config_read_only_reason() -> Result<Option<String>, String> {
+    let _ = load_settings();
+    Ok(READ_ONLY_REASON.lock().unwrap().clone())
+}
 
-    Some(())
+#[cfg(test)]
+lazy_static! {
+    static ref TEST_DIRECTORY_LOCK: Mutex<()> = Mutex::new(());
 }
 
-fn promise_is_file() -> bool {
-    match file_access(Access::Read) {
-        Ok(_) => true,
-        Err(_) => false
+/// Empty data directory for a single test, removed again on drop. The
+/// settings are process wide, so tests holding one run one at a time.
+#[cfg(test)]
+pub(crate) struct TestDirectory {
+    pub path: PathBuf,
+    _guard: std::sync::MutexGuard<'static, ()>,
+}
+
+#[cfg(test)]
+pub(crate) fn test_directory() -> TestDirectory {
+    // a failed test poisons the lock, the state is reset anyway
+    let guard = TEST_DIRECTORY_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    let path = std::env::temp_dir().join(format!("mnemonic-test-{}", uuid::Uuid::new_v4().simple()));
+    set_data_directory(path.clone());
+    reset_state();
+    TestDirectory { path, _guard: guard }
+}
+
+#[cfg(test)]
+impl TestDirectory {
+    pub fn user_db(&self) -> PathBuf {
+        self.path.join("user.db")
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestDirectory {
+    fn drop(&mut self) {
+        reset_state();
+        let _ = fs::remove_dir_all(&self.path);
     }
 }
 
-pub fn js_promise_is_file(invoke: Invoke) {
-    invoke.resolver.resolve(promise_is_file())
+#[cfg(test)]
+fn reset_state() {
+    *STORE.state.lock().unwrap_or_else(|err| err.into_inner()) = ConfigState {
+        settings: None,
+        stored: Document::new(),
+        dirty: false
+    };
+    READ_ONLY.store(false, Ordering::SeqCst);
+    *READ_ONLY_REASON.lock().unwrap() = None;
+    *RECOVERED.lock().unwrap() = None;
+    *LAST_WRITTEN.lock().unwrap() = None;
+    SUBSCRIPTIONS.lock().unwrap().clear();
+    vault::reset();
 }
 
-pub fn get_config_or_default<D: for<'de> Deserialize<'de>>(
-    dotted_path: &str,
-    default: Option<D>
-) -> Option<D> {
-    let cfg_doc = read_get_document_safe();
-    let result = get_prop_from_path(&dotted_path.split(".").map(|x| x.to_string()).collect(), cfg_doc);
-    match result {
-        Some(bson) => {
-            let result = bson::from_bson::<D>(bson);
-            return result.ok().or(default);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(dotted_path: &str) -> Vec<String> {
+        dotted_path.split('.').map(|s| s.to_string()).collect()
+    }
+
+    fn global_path(dotted_path: &str) -> Vec<String> {
+        path(&format!("globalConfig.{}", dotted_path))
+    }
+
+    /// The active profile as written to `user.db`
+    fn stored_active_profile() -> Document {
+        flush().unwrap();
+        let document = Document::from_reader(File::open(config_path().unwrap()).unwrap()).unwrap();
+        stored_profile(&document, DEFAULT_PROFILE)
+    }
+
+    #[test]
+    fn only_set_keys_are_stored() {
+        let _directory = test_directory();
+        js_config_set_property(global_path("generalApp.showOnStart"), Bson::Boolean(true), None).unwrap();
+        // the default value, stored all the same
+        js_config_set_property(global_path("restorePage.closeOnBlur"), Bson::Boolean(true), None).unwrap();
+
+        let stored = stored_active_profile();
+        assert_eq!(get_prop_from_path(&path("generalApp.showOnStart"), stored.clone()), Some(Bson::Boolean(true)));
+        assert_eq!(get_prop_from_path(&path("restorePage.closeOnBlur"), stored.clone()), Some(Bson::Boolean(true)));
+        assert_eq!(get_prop_from_path(&path("passwordGenerationRules"), stored), None);
+    }
+
+    #[test]
+    fn deleted_keys_are_removed_from_the_file() {
+        let _directory = test_directory();
+        js_config_set_property(global_path("generalApp.showOnStart"), Bson::Boolean(false), None).unwrap();
+        js_config_set_property(global_path("generatePage.askOnLeave"), Bson::Boolean(false), None).unwrap();
+        js_config_delete_property(global_path("generalApp.showOnStart"), None).unwrap();
+
+        let stored = stored_active_profile();
+        assert_eq!(get_prop_from_path(&path("generalApp.showOnStart"), stored.clone()), None);
+        assert_eq!(get_prop_from_path(&path("generatePage.askOnLeave"), stored), Some(Bson::Boolean(false)));
+
+        js_config_delete_property(path("globalConfig"), None).unwrap();
+        assert!(stored_active_profile().is_empty());
+    }
+
+    fn read_file(path: &Path) -> Document {
+        Document::from_reader(File::open(path).unwrap()).unwrap()
+    }
+
+    fn generation(value: i32) -> Document {
+        bson::doc! { "generation": value }
+    }
+
+    #[test]
+    fn writes_replace_the_file_through_a_temporary_one() {
+        let directory = test_directory();
+        // left behind by a crash, the next write replaces it
+        fs::create_dir_all(&directory.path).unwrap();
+        fs::write(directory.user_db().with_extension("db.tmp"), b"partial").unwrap();
+
+        write_document(generation(1)).unwrap();
+        assert!(!directory.user_db().with_extension("db.tmp").exists());
+        let written = read_file(&directory.user_db());
+        assert_eq!(written.get_i32("generation"), Ok(1));
+        assert_eq!(written.get_i32(SCHEMA_VERSION_KEY), Ok(SCHEMA_VERSION));
+    }
+
+    #[test]
+    fn backups_rotate_and_keep_the_last_generations() {
+        let directory = test_directory();
+        for value in 1..=5 {
+            write_document(generation(value)).unwrap();
         }
-        None => return default
+        let user_db = directory.user_db();
+        assert_eq!(read_file(&user_db).get_i32("generation"), Ok(5));
+        for backup in 1..=BACKUP_COUNT {
+            let document = read_file(&backup_path(&user_db, backup));
+            assert_eq!(document.get_i32("generation"), Ok(5 - backup as i32));
+        }
+        assert!(!backup_path(&user_db, BACKUP_COUNT + 1).exists());
+    }
+
+    #[test]
+    fn damaged_files_are_not_rotated_into_the_backups() {
+        let directory = test_directory();
+        write_document(generation(1)).unwrap();
+        write_document(generation(2)).unwrap();
+        fs::write(directory.user_db(), b"damaged").unwrap();
+        write_document(generation(3)).unwrap();
+
+        // the good backup moved on instead of being replaced
+        let user_db = directory.user_db();
+        assert!(!backup_path(&user_db, 1).exists());
+        assert_eq!(read_file(&backup_path(&user_db, 2)).get_i32("generation"), Ok(1));
+    }
+
+    #[test]
+    fn truncated_file_falls_back_to_the_newest_backup() {
+        let directory = test_directory();
+        write_document(generation(1)).unwrap();
+        write_document(generation(2)).unwrap();
+        let user_db = directory.user_db();
+        let data = fs::read(&user_db).unwrap();
+        fs::write(&user_db, &data[..data.len() / 2]).unwrap();
+
+        let document = read_get_document_safe();
+        assert_eq!(document.get_i32("generation"), Ok(1));
+        assert!(!document.contains_key(SCHEMA_VERSION_KEY));
+        // the backup took its place and the damaged file was kept
+        assert_eq!(read_file(&user_db).get_i32("generation"), Ok(1));
+        assert_eq!(fs::read(user_db.with_extension("db.corrupt")).unwrap(), &data[..data.len() / 2]);
+        assert!(!READ_ONLY.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn damaged_file_without_backups_is_quarantined() {
+        let directory = test_directory();
+        fs::create_dir_all(&directory.path).unwrap();
+        fs::write(directory.user_db(), b"not a document").unwrap();
+
+        assert_eq!(read_get_document_safe(), Document::new());
+        assert_eq!(fs::read(directory.user_db().with_extension("db.corrupt")).unwrap(), b"not a document");
+        // later reads get the outcome of the first recovery
+        assert_eq!(read_get_document_safe(), Document::new());
+    }
+
+    #[test]
+    fn overwritten_files_only_hold_the_new_data() {
+        let directory = test_directory();
+        fs::create_dir_all(&directory.path).unwrap();
+        let file = directory.path.join("overwrite");
+        fs::write(&file, b"a much longer secret").unwrap();
+
+        overwrite_file(&file, b"short").unwrap();
+        assert_eq!(fs::read(&file).unwrap(), b"short");
+
+        shred_file(&file).unwrap();
+        assert!(!file.exists());
+        assert!(shred_file(&file).is_err());
+    }
+
+    fn write_file(path: &Path, document: &Document) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        document.to_writer(&mut File::create(path).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn unversioned_documents_keep_their_layout() {
+        let mut document = bson::doc! { "generalApp": { "showOnStart": true } };
+        MIGRATIONS[0](&mut document).unwrap();
+        assert_eq!(document, bson::doc! { "generalApp": { "showOnStart": true } });
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn settings_become_the_default_profile() {
+        let mut document = bson::doc! {
+            SCHEMA_VERSION_KEY: 1,
+            "generalApp": { "showOnStart": true }
+        };
+        MIGRATIONS[1](&mut document).unwrap();
+        assert_eq!(document, bson::doc! {
+            SCHEMA_VERSION_KEY: 1,
+            "activeProfile": DEFAULT_PROFILE,
+            "profiles": { DEFAULT_PROFILE: { "generalApp": { "showOnStart": true } } }
+        });
+    }
+
+    #[test]
+    fn history_starts_empty() {
+        let original = bson::doc! {
+            SCHEMA_VERSION_KEY: 2,
+            "activeProfile": DEFAULT_PROFILE,
+            "profiles": { DEFAULT_PROFILE: {} }
+        };
+        let mut document = original.clone();
+        MIGRATIONS[2](&mut document).unwrap();
+        assert_eq!(document, original);
+    }
+
+    #[test]
+    fn single_quiz_schedule_is_dropped() {
+        let mut document = bson::doc! {
+            SCHEMA_VERSION_KEY: 3,
+            "activeProfile": "work",
+            "profiles": {
+                DEFAULT_PROFILE: { "quiz": { "intervalDays": 4 }, "generalApp": { "showOnStart": true } },
+                "work": { "quiz": { "intervalDays": 2 } }
+            }
+        };
+        MIGRATIONS[3](&mut document).unwrap();
+        assert_eq!(document.get_document("profiles").unwrap(), &bson::doc! {
+            DEFAULT_PROFILE: { "generalApp": { "showOnStart": true } },
+            "work": {}
+        });
+    }
+
+    #[test]
+    fn profiles_keep_only_values_that_differ_from_the_defaults() {
+        let mut settings = settings_to_document(&Settings::default());
+        set_prop_at_path(&path("generalApp.showOnStart"), &mut settings, Bson::Boolean(true)).unwrap();
+        set_prop_at_path(&path("generatePage.askOnLeave"), &mut settings, Bson::Boolean(false)).unwrap();
+        let mut document = bson::doc! {
+            SCHEMA_VERSION_KEY: 4,
+            "activeProfile": DEFAULT_PROFILE,
+            "profiles": { DEFAULT_PROFILE: settings, "untouched": settings_to_document(&Settings::default()) }
+        };
+        MIGRATIONS[4](&mut document).unwrap();
+        assert_eq!(document.get_document("profiles").unwrap(), &bson::doc! {
+            DEFAULT_PROFILE: {
+                "generalApp": { "showOnStart": true },
+                "generatePage": { "askOnLeave": false }
+            },
+            "untouched": {}
+        });
+    }
+
+    #[test]
+    fn old_files_are_migrated_and_backed_up() {
+        let directory = test_directory();
+        let original = bson::doc! { "generalApp": { "showOnStart": true }, "quiz": { "intervalDays": 4 } };
+        write_file(&directory.user_db(), &original);
+
+        let document = read_get_document_safe();
+        assert_eq!(document, bson::doc! {
+            "activeProfile": DEFAULT_PROFILE,
+            "profiles": { DEFAULT_PROFILE: { "generalApp": { "showOnStart": true } } }
+        });
+        assert_eq!(read_file(&directory.user_db().with_extension("db.v0.bak")), original);
+        assert_eq!(read_file(&directory.user_db()).get_i32(SCHEMA_VERSION_KEY), Ok(SCHEMA_VERSION));
+        assert!(!READ_ONLY.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn migrated_document_is_kept_read_only_when_it_cant_be_written() {
+        let directory = test_directory();
+        let original = bson::doc! { SCHEMA_VERSION_KEY: 1, "generalApp": { "showOnStart": true } };
+        write_file(&directory.user_db(), &original);
+        // keeps `user.db.tmp` from being created
+        fs::create_dir_all(directory.user_db().with_extension("db.tmp")).unwrap();
+
+        let document = read_get_document_safe();
+        assert_eq!(document, bson::doc! {
+            "activeProfile": DEFAULT_PROFILE,
+            "profiles": { DEFAULT_PROFILE: { "generalApp": { "showOnStart": true } } }
+        });
+        assert!(READ_ONLY.load(Ordering::SeqCst));
+        assert_eq!(read_file(&directory.user_db()), original);
+        assert!(js_config_set_property(global_path("generalApp.showOnStart"), Bson::Boolean(false), None).is_err());
+    }
+
+    /// Waits for the flush thread to write pending changes.
+    fn wait_until_flushed() {
+        let deadline = std::time::Instant::now() + WRITE_BEHIND_DELAY * 10;
+        while STORE.is_dirty() {
+            assert!(std::time::Instant::now() < deadline, "changes weren't flushed");
+            std::thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    /// Lets a flush that was due before the test started run out, it would
+    /// write early otherwise.
+    fn settle_flush_thread() {
+        std::thread::sleep(WRITE_BEHIND_DELAY * 2);
+    }
+
+    fn stored_document(profile: Document) -> Document {
+        bson::doc! {
+            SCHEMA_VERSION_KEY: SCHEMA_VERSION,
+            "activeProfile": DEFAULT_PROFILE,
+            "profiles": { DEFAULT_PROFILE: profile }
+        }
+    }
+
+    #[test]
+    fn changes_are_written_behind_together() {
+        let directory = test_directory();
+        js_config_set_property(global_path("generalApp.showOnStart"), Bson::Boolean(true), None).unwrap();
+        assert!(STORE.is_dirty());
+        flush().unwrap();
+        assert!(!STORE.is_dirty());
+        settle_flush_thread();
+
+        js_config_set_property(global_path("generalApp.skipToRetrieve"), Bson::Boolean(true), None).unwrap();
+        js_config_set_property(global_path("generatePage.askOnLeave"), Bson::Boolean(false), None).unwrap();
+        // not written yet, but already promised
+        assert!(!backup_path(&directory.user_db(), 1).exists());
+        assert!(promise_is_file());
+        wait_until_flushed();
+
+        let stored = stored_profile(&read_file(&directory.user_db()), DEFAULT_PROFILE);
+        assert_eq!(get_prop_from_path(&path("generalApp.skipToRetrieve"), stored.clone()), Some(Bson::Boolean(true)));
+        assert_eq!(get_prop_from_path(&path("generatePage.askOnLeave"), stored), Some(Bson::Boolean(false)));
+        // both changes went out in a single write
+        assert!(backup_path(&directory.user_db(), 1).exists());
+        assert!(!backup_path(&directory.user_db(), 2).exists());
+    }
+
+    #[test]
+    fn failed_flush_stays_dirty() {
+        let directory = test_directory();
+        js_config_set_property(global_path("generalApp.showOnStart"), Bson::Boolean(true), None).unwrap();
+        fs::create_dir_all(directory.user_db().with_extension("db.tmp")).unwrap();
+        assert!(flush().is_err());
+        assert!(STORE.is_dirty());
+
+        fs::remove_dir(directory.user_db().with_extension("db.tmp")).unwrap();
+        flush().unwrap();
+        assert!(!STORE.is_dirty());
+        let stored = stored_profile(&read_file(&directory.user_db()), DEFAULT_PROFILE);
+        assert_eq!(get_prop_from_path(&path("generalApp.showOnStart"), stored), Some(Bson::Boolean(true)));
+    }
+
+    #[test]
+    fn pending_changes_win_over_a_reload() {
+        let directory = test_directory();
+        settle_flush_thread();
+        js_config_set_property(global_path("generalApp.showOnStart"), Bson::Boolean(true), None).unwrap();
+        write_file(&directory.user_db(), &stored_document(bson::doc! { "generalApp": { "skipToRetrieve": true } }));
+
+        STORE.reload();
+        let settings = load_stored_settings(None).unwrap();
+        assert!(settings.general_app.show_on_start);
+        assert!(!settings.general_app.skip_to_retrieve);
+
+        // the flush writes over the edit
+        flush().unwrap();
+        let stored = stored_profile(&read_file(&directory.user_db()), DEFAULT_PROFILE);
+        assert_eq!(stored, bson::doc! { "generalApp": { "showOnStart": true } });
+    }
+
+    #[test]
+    fn reload_picks_up_outside_edits_once_flushed() {
+        let directory = test_directory();
+        js_config_set_property(global_path("generalApp.showOnStart"), Bson::Boolean(true), None).unwrap();
+        flush().unwrap();
+
+        // our own write isn't read again
+        STORE.reload();
+        assert!(load_stored_settings(None).unwrap().general_app.show_on_start);
+
+        // coarse timestamps could match the flush otherwise
+        std::thread::sleep(Duration::from_millis(20));
+        write_file(&directory.user_db(), &stored_document(bson::doc! { "generalApp": { "skipToRetrieve": true } }));
+        STORE.reload();
+        let settings = load_stored_settings(None).unwrap();
+        assert!(!settings.general_app.show_on_start);
+        assert!(settings.general_app.skip_to_retrieve);
+        assert!(!STORE.is_dirty());
+    }
+
+    fn get(dotted_path: &str) -> Value {
+        js_config_get_property(global_path(dotted_path), None, None).unwrap()
+    }
+
+    #[test]
+    fn compare_and_swap_only_writes_the_expected_value() {
+        let _directory = test_directory();
+        let show_on_start = global_path("generalApp.showOnStart");
+        let swapped = js_config_compare_and_swap(show_on_start.clone(), Value::Bool(true), Bson::Boolean(false), None);
+        assert_eq!(swapped, Ok(false));
+        assert!(!STORE.is_dirty());
+
+        let swapped = js_config_compare_and_swap(show_on_start.clone(), Value::Bool(false), Bson::Boolean(true), None);
+        assert_eq!(swapped, Ok(true));
+        assert_eq!(get("generalApp.showOnStart"), Value::Bool(true));
+
+        // the value is checked even if the expected one matches
+        let swapped = js_config_compare_and_swap(show_on_start, Value::Bool(true), Bson::String("yes".to_string()), None);
+        assert!(swapped.is_err());
+        assert_eq!(get("generalApp.showOnStart"), Value::Bool(true));
+        assert!(js_config_compare_and_swap(path("globalConfig"), Value::Null, Bson::Null, None).is_err());
+    }
+
+    #[test]
+    fn compare_and_swap_treats_null_as_missing() {
+        let _directory = test_directory();
+        let schedule = global_path("quiz.phrases.abc");
+        let value = Bson::Document(bson::doc! { "intervalDays": 3 });
+        assert_eq!(js_config_compare_and_swap(schedule.clone(), Value::Null, value.clone(), None), Ok(true));
+        assert_eq!(get("quiz.phrases.abc.intervalDays"), serde_json::json!(3));
+        assert_eq!(js_config_compare_and_swap(schedule, Value::Null, value, None), Ok(false));
+    }
+
+    #[test]
+    fn deleting_missing_paths_changes_nothing() {
+        let _directory = test_directory();
+        js_config_set_property(global_path("generalApp.showOnStart"), Bson::Boolean(true), None).unwrap();
+        let before = load_stored_settings(None).unwrap();
+
+        js_config_delete_property(global_path("generalApp.noSuchKey"), None).unwrap();
+        js_config_delete_property(global_path("quiz.phrases.abc.intervalDays"), None).unwrap();
+        assert_eq!(load_stored_settings(None).unwrap(), before);
+
+        // below a value that isn't an object
+        assert!(js_config_delete_property(global_path("generalApp.showOnStart.deeper"), None).is_err());
+        assert!(js_config_delete_property(path("config.generalApp"), None).is_err());
+        assert_eq!(load_stored_settings(None).unwrap(), before);
+    }
+
+    #[test]
+    fn deleting_nested_paths_removes_only_them() {
+        let _directory = test_directory();
+        for key in ["abc", "def"] {
+            let schedule = Bson::Document(bson::doc! { "intervalDays": 3 });
+            js_config_set_property(global_path(&format!("quiz.phrases.{}", key)), schedule, None).unwrap();
+        }
+        js_config_set_property(global_path("quiz.phrases.abc.intervalDays"), Bson::Int32(5), None).unwrap();
+        assert_eq!(js_config_list_keys(global_path("quiz.phrases"), None), Ok(vec!["abc".to_string(), "def".to_string()]));
+
+        js_config_delete_property(global_path("quiz.phrases.abc"), None).unwrap();
+        assert_eq!(js_config_list_keys(global_path("quiz.phrases"), None), Ok(vec!["def".to_string()]));
+        assert_eq!(get("quiz.phrases.def.intervalDays"), serde_json::json!(3));
+
+        // a required key falls back to its default
+        js_config_delete_property(global_path("quiz.phrases.def.intervalDays"), None).unwrap();
+        assert_eq!(get("quiz.phrases.def.intervalDays"), serde_json::json!(1));
+    }
+
+    #[test]
+    fn listing_keys_needs_an_object() {
+        let _directory = test_directory();
+        assert_eq!(
+            js_config_list_keys(global_path("generalApp"), None),
+            Ok(vec!["showOnStart".to_string(), "skipToRetrieve".to_string()])
+        );
+        assert_eq!(js_config_list_keys(global_path("quiz.phrases"), None), Ok(Vec::new()));
+        assert_eq!(
+            js_config_list_keys(global_path("generalApp.noSuchKey"), None),
+            Err("generalApp.noSuchKey doesn't exist".to_string())
+        );
+        assert_eq!(
+            js_config_list_keys(global_path("generalApp.showOnStart"), None),
+            Err("generalApp.showOnStart isn't an object or array".to_string())
+        );
+        assert!(js_config_list_keys(path("generalApp"), None).is_err());
+    }
+}
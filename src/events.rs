@@ -1,13 +1,48 @@
+use bson::Bson;
 use serde::Serialize;
 
-use crate::{ipc::{ChannelHandshakeRequest}, commands::WindowButton};
+use crate::{
+    bindings::{object, Declarations, TypeScript},
+    ipc::ChannelHandshakeRequest
+};
 
 #[derive(Serialize, Debug, Clone)]
 pub enum ApplicationOpenLocation { Auto, Generate, Retrieve }
 
+crate::ts_string_enum!(ApplicationOpenLocation, ["Auto", "Generate", "Retrieve"]);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WindowButton {
+    Close,
+    Minimize,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub enum QuizEvent {
+    Reminder { due: i64 }
+}
+
+impl TypeScript for QuizEvent {
+    fn ts_type(declarations: &mut Declarations) -> String {
+        declarations.declare("QuizEvent", |declarations| format!(
+            "{{ Reminder: {} }}",
+            object(&[("due", i64::ts_type(declarations))])
+        ))
+    }
+}
+
+crate::ts_interface! {
+    #[derive(Serialize, Debug, Clone)]
+    pub struct ConfigChangeEvent {
+        pub path: Vec<String>,
+        pub value: Option<Bson>,
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum EventLoopMessage {
     WebAppInit,
+    PageLoading,
     PageContentLoaded,
     EstablishChannel(ChannelHandshakeRequest),
     CloseChannel(uuid::Uuid),
@@ -20,5 +55,8 @@ pub enum EventLoopMessage {
 
     ApplicationQuit,
     ApplicationOpenWindow(ApplicationOpenLocation),
-    ApplicationCloseWindow
+    ApplicationCloseWindow,
+
+    QuizReminder(QuizEvent),
+    ConfigChanged(Vec<ConfigChangeEvent>)
 }
\ No newline at end of file
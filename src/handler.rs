@@ -0,0 +1,383 @@
+use std::{sync::Arc, time::Duration};
+
+use lazy_static::lazy_static;
+use serde_json::Value;
+use tauri_runtime::{webview::WebviewIpcHandler, Dispatch, Runtime, RuntimeHandle, EventLoopProxy};
+use tauri_runtime_wry::{Wry, EventProxy};
+
+use crate::{
+    events::{EventLoopMessage, ApplicationOpenLocation, ConfigChangeEvent, QuizEvent},
+    capabilities, mnemonic, commands, config, history, profiles, quiz, transfer, vault,
+    bindings::{self, CommandSignature, Declarations, TypeScript},
+    ipc::{
+        self, CallbackFn, ChannelHandshakeRequest, ChannelUuid, Invoke, InvokeError, InvokeMessage,
+        InvokeResolver, InvokeResponse, IpcPayload, Window, CHANNELS
+    },
+    registry::{self, CommandContext, CommandRegistry, CommandScope},
+    trace,
+    webview::{MemoryWebview, Sink}
+};
+
+type RHandle = <Wry<EventLoopMessage> as Runtime<EventLoopMessage>>::Handle;
+
+lazy_static! {
+    /// Handed to init.js and expected back with every request
+    static ref IPC_NONCE: String = uuid::Uuid::new_v4().simple().to_string();
+}
+
+fn ipc_close_channel(
+    proxy: EventProxy<EventLoopMessage>,
+    (d1, d2, d3, d4): ChannelUuid
+) -> Result<(), String> {
+    let uuid = uuid::Uuid::from_fields(d1, d2, d3, &d4);
+    let _ = proxy.send_event(EventLoopMessage::CloseChannel(uuid));
+    Ok(())
+}
+
+fn channel_post(id: ChannelUuid, data: Value) -> Result<(), String> {
+    let channel = CHANNELS.get_channel_by_fields(id)?;
+    CHANNELS.post(&channel.name, data)
+}
+
+fn channel_request(id: ChannelUuid, data: Value) -> Result<Value, String> {
+    let channel = CHANNELS.get_channel_by_fields(id)?;
+    CHANNELS.request(&channel.name, data)
+}
+
+fn channel_reply(id: ChannelUuid, resolver: u32, data: Value, error: Option<String>) -> Result<bool, String> {
+    let channel = CHANNELS.get_channel_by_fields(id)?;
+    let result = match error {
+        Some(error) => Err(error),
+        None => Ok(data)
+    };
+    Ok(channel.resolve(resolver, result))
+}
+
+fn page_content_loaded(proxy: EventProxy<EventLoopMessage>) -> Result<(), String> {
+    let _ = proxy.send_event(EventLoopMessage::PageContentLoaded);
+    Ok(())
+}
+
+fn register_commands(registry: &mut CommandRegistry) {
+    registry.command("cancelInvoke", registry::cancel_invoke).traced();
+    registry.context_command("closeChannel", ipc_close_channel).traced();
+    registry.command("channelPost", channel_post);
+    registry.command("channelRequest", channel_request).on_worker();
+    registry.command("channelReply", channel_reply);
+    registry.context_command("pageContentLoaded", page_content_loaded).traced();
+
+    registry.command("generateMnemonicPhrase", mnemonic::generate_mnemonic_phrase).on_worker().sensitive();
+    registry.command("fromMnemonicPhrase", mnemonic::from_mnemonic_phrase).on_worker().sensitive();
+    registry.command("checkChecksum", mnemonic::check_checksum).on_worker().sensitive();
+    registry.command("getWordlist", commands::get_wordlist).traced();
+
+    registry.context_command("windowDragMove", commands::window_drag_move).traced();
+    registry.context_command("windowShowSysMenu", commands::window_show_sys_menu).traced();
+    registry.context_command("windowClose", commands::window_close).traced();
+    registry.context_command("windowMinimize", commands::window_minimize).traced();
+    registry.context_command("windowSetTitle", commands::window_set_title).traced();
+    registry.context_command("showMessageBox", commands::show_message_box);
+    registry.command("autostartRegistryExecuteCommand", commands::autostart_registry_execute_command).traced();
+    registry.command("clipboardWriteTextSecure", commands::clipboard_write_text_secure).sensitive();
+
+    registry.command("configIsFile", config::js_promise_is_file).traced();
+    registry.command("configObserveProperty", config::js_config_observe_property).traced();
+    registry.command("configUnobserveProperty", config::js_config_unobserve_property).traced();
+    registry.command("configIsReadOnly", config::js_config_is_read_only).traced();
+    registry.command("configReadOnlyReason", config::js_config_read_only_reason).traced();
+    registry.command("configSetProperty", config::js_config_set_property);
+    registry.command("configGetProperty", config::js_config_get_property).traced();
+    registry.command("configDeleteProperty", config::js_config_delete_property).traced();
+    registry.command("configListKeys", config::js_config_list_keys).traced();
+    registry.command("configCompareAndSwap", config::js_config_compare_and_swap);
+
+    registry.command("configEncryptionStatus", vault::config_encryption_status).traced();
+    registry.command("configEnableEncryption", vault::config_enable_encryption).on_worker().sensitive();
+    registry.command("configDisableEncryption", vault::config_disable_encryption).traced();
+    registry.command("configUnlock", vault::config_unlock).on_worker().sensitive();
+    registry.command("configLock", vault::config_lock).traced();
+
+    registry.command("configExportSettings", transfer::config_export_settings);
+    registry.command("configImportSettings", transfer::config_import_settings);
+
+    registry.command("configHistoryList", history::config_history_list).traced();
+    registry.command("configHistoryRevert", history::config_history_revert).traced();
+    registry.command("configHistoryRestore", history::config_history_restore).traced();
+
+    registry.command("profileList", profiles::profile_list).traced();
+    registry.command("profileSwitch", profiles::profile_switch).traced();
+    registry.command("profileCreate", profiles::profile_create).traced();
+    registry.command("profileClone", profiles::profile_clone).traced();
+    registry.command("profileRename", profiles::profile_rename).traced();
+    registry.command("profileDelete", profiles::profile_delete).traced();
+
+    registry.command("quizStart", quiz::quiz_start).on_worker().sensitive();
+    registry.command("quizAnswer", quiz::quiz_answer).on_worker().sensitive();
+    registry.command("quizFinish", quiz::quiz_finish).traced();
+    registry.command("quizCancel", quiz::quiz_cancel).traced();
+    registry.command("quizGetSchedule", quiz::quiz_get_schedule).on_worker().sensitive();
+}
+
+fn is_traced_command(command: &str) -> bool {
+    COMMANDS.get(command).map_or(false, |command| command.is_traced())
+}
+
+fn is_sensitive_command(command: &str) -> bool {
+    COMMANDS.get(command).map_or(false, |command| command.is_sensitive())
+}
+
+/// Invokes `command` without a window, its response goes to `sink`.
+/// Commands that need the window or the event loop are rejected.
+pub fn dispatch_headless(sink: Sink, command: &str, inner: Value, callback: CallbackFn, error: CallbackFn) {
+    let invoke = Invoke {
+        message: InvokeMessage { command: command.to_string(), payload: inner },
+        resolver: InvokeResolver { sink, callback, error }
+    };
+    COMMANDS.dispatch(None, invoke);
+}
+
+/// How long a replayed command may take, key derivations are slow
+const REPLAY_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Runs `command` with the arguments `inner` like the web-app would, for
+/// replaying a trace. `None` for commands that need a window or the event loop.
+pub fn replay_command(command: &str, inner: &Value) -> Option<Result<Value, Value>> {
+    if COMMANDS.get(command)?.context() != CommandContext::None {
+        return None;
+    }
+    let webview = MemoryWebview::new();
+    dispatch_headless(webview.clone(), command, inner.clone(), CallbackFn(1), CallbackFn(2));
+    Some(webview.wait_for_response(1, REPLAY_TIMEOUT)
+        .unwrap_or_else(|| Err(Value::String("no response".to_string()))))
+}
+
+/// Argument and result types of every registered command
+pub fn command_signatures(declarations: &mut Declarations) -> Vec<(&'static str, CommandSignature)> {
+    COMMANDS.signatures(declarations)
+}
+
+/// Message types of the channels the web-app can establish
+pub fn channel_types(declarations: &mut Declarations) -> Vec<(&'static str, String)> {
+    vec![
+        ("config-events", ConfigChangeEvent::ts_type(declarations)),
+        ("quiz-events", QuizEvent::ts_type(declarations)),
+        ("ui-events", ApplicationOpenLocation::ts_type(declarations)),
+        ("window-events", bindings::string_union(&["focus", "blur", "minimized"])),
+    ]
+}
+
+lazy_static! {
+    static ref COMMANDS: CommandRegistry = {
+        let mut registry = CommandRegistry::new();
+        register_commands(&mut registry);
+        registry
+    };
+}
+
+fn handle_invoke_payload(window: Window, payload: IpcPayload, runtime_handle: &RHandle) {
+    let event_proxy = runtime_handle.create_proxy();
+    match payload.command.as_str() {
+        "setInitialized" => {  // Application Initialized
+            let _ = event_proxy.send_event(EventLoopMessage::WebAppInit);
+            InvokeResolver::return_response(
+                &window,
+                InvokeResponse::Ok(Value::Null),
+                payload.callback, 
+                CallbackFn(0)
+            );
+        },
+        "establishChannel" => {
+            match ipc::deserialize::<ChannelHandshakeRequest>(&payload.inner) {
+                Ok(data) => {
+                    let _ = event_proxy.send_event(
+                        EventLoopMessage::EstablishChannel(data)
+                    );
+
+                    InvokeResolver::return_response(
+                        &window,
+                        InvokeResponse::Ok(Value::Null),
+                        payload.callback, 
+                        payload.error
+                    );
+                }
+                Err(err) => {
+                    InvokeResolver::return_response(
+                        &window,
+                        InvokeResponse::Err(InvokeError::from(err)),
+                        payload.callback, 
+                        payload.error
+                    );
+                }
+            }
+        }
+        "application-page-loading" => {
+            let _ = event_proxy.send_event(EventLoopMessage::PageLoading);
+        }
+        _ => {
+            let message = InvokeMessage { 
+                command: payload.command,
+                payload: payload.inner
+            };
+
+            let resolver = InvokeResolver {
+                sink: Arc::new(window.clone()),
+                callback: payload.callback,
+                error: payload.error
+            };
+            let invoke = Invoke { message, resolver };
+            COMMANDS.dispatch(Some(CommandScope { window, proxy: event_proxy }), invoke);
+        }
+    }
+}
+
+/// init.js with the nonce of this process
+pub fn init_script() -> String {
+    include_str!("../resources/init.js")
+        .replace("__IPC_NONCE__", &Value::String(IPC_NONCE.clone()).to_string())
+}
+
+pub fn create_ipc_handler(
+    runtime_handle: RHandle
+) -> WebviewIpcHandler<EventLoopMessage, Wry<EventLoopMessage>> {
+    Box::new(move |window, request| {
+        match serde_json::from_str::<IpcPayload>(&request) {
+            Ok(payload) => {
+                trace::request(&payload, is_traced_command(&payload.command), is_sensitive_command(&payload.command));
+                let allowed = if payload.nonce == *IPC_NONCE {
+                    capabilities::check(&capabilities::manifest_origin(&payload.origin), &payload.command)
+                } else {
+                    Err(format!("{} was posted without the IPC nonce", payload.command))
+                };
+                match allowed {
+                    Ok(()) => handle_invoke_payload(window, payload, &runtime_handle),
+                    Err(err) => {
+                        let _ = capabilities::log_violation(&err);
+                        InvokeResolver::return_response(
+                            &window,
+                            InvokeResponse::Err(InvokeError::from(err)),
+                            payload.callback,
+                            payload.error
+                        );
+                    }
+                }
+            },
+            Err(e) => {
+                let msg = e.to_string();
+                let _ = window.dispatcher.eval_script(&format!(
+                    "console.error({});",
+                    Value::String(msg)
+                ));
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Mutex, atomic::{AtomicUsize, Ordering}};
+
+    use serde_json::json;
+
+    use super::*;
+
+    /// Pending invokes are tracked by their callback handle, tests running
+    /// at the same time mustn't share one
+    static NEXT_CALLBACK: AtomicUsize = AtomicUsize::new(1);
+
+    /// Invokes `command` headlessly and waits for its response.
+    fn invoke(command: &str, arguments: Value) -> Result<Value, Value> {
+        let webview = MemoryWebview::new();
+        let callback = NEXT_CALLBACK.fetch_add(2, Ordering::SeqCst);
+        dispatch_headless(webview.clone(), command, arguments, CallbackFn(callback), CallbackFn(callback + 1));
+        webview.wait_for_response(callback, Duration::from_secs(10)).expect("no response")
+    }
+
+    fn error(result: Result<Value, Value>) -> String {
+        result.unwrap_err().as_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn unknown_commands_are_rejected() {
+        assert_eq!(error(invoke("noSuchCommand", json!([]))), "command noSuchCommand not found");
+    }
+
+    #[test]
+    fn arguments_have_to_be_an_array() {
+        assert_eq!(error(invoke("getWordlist", json!({}))), "payload has to be an array.");
+    }
+
+    #[test]
+    fn window_commands_need_a_window() {
+        assert_eq!(error(invoke("windowClose", json!([]))), "command needs a window");
+        assert_eq!(error(invoke("windowSetTitle", json!(["Mnemonic"]))), "command needs a window");
+        assert!(replay_command("windowClose", &json!([])).is_none());
+    }
+
+    #[test]
+    fn settings_round_trip_through_the_commands() {
+        let _directory = config::test_directory();
+        let path = json!(["globalConfig", "generalApp", "showOnStart"]);
+        assert_eq!(invoke("configSetProperty", json!([path, true])), Ok(Value::Null));
+        assert_eq!(invoke("configGetProperty", json!([path])), Ok(json!(true)));
+
+        let resolved = invoke("configGetProperty", json!([path, null, true])).unwrap();
+        assert_eq!(resolved["layer"], "user");
+        assert_eq!(resolved["locked"], false);
+
+        let keys = invoke("configListKeys", json!([["globalConfig", "generalApp"]])).unwrap();
+        assert_eq!(keys, json!(["showOnStart", "skipToRetrieve"]));
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        let _directory = config::test_directory();
+        let path = json!(["globalConfig", "passwordGenerationRules", "length"]);
+        assert!(invoke("configSetProperty", json!([path, 0])).is_err());
+        assert!(invoke("configSetProperty", json!([path, "long"])).is_err());
+        assert!(invoke("configSetProperty", json!([["globalConfig", "unknownKey"], 1])).is_err());
+        assert_eq!(invoke("configGetProperty", json!([path])), Ok(json!(48)));
+    }
+
+    #[test]
+    fn profile_commands_switch_the_settings() {
+        let _directory = config::test_directory();
+        assert_eq!(invoke("profileCreate", json!(["work"])), Ok(Value::Null));
+        assert_eq!(invoke("profileSwitch", json!(["work"])), Ok(Value::Null));
+        let path = json!(["globalConfig", "restorePage", "closeOnBlur"]);
+        invoke("configSetProperty", json!([path, false])).unwrap();
+
+        let list = invoke("profileList", json!([])).unwrap();
+        assert_eq!(list, json!({ "activeProfile": "work", "profiles": ["default", "work"] }));
+        assert_eq!(invoke("configGetProperty", json!([path, "default"])), Ok(json!(true)));
+        assert_eq!(invoke("configGetProperty", json!([path])), Ok(json!(false)));
+    }
+
+    #[test]
+    fn channel_commands_reach_the_backend_handlers() {
+        let webview = MemoryWebview::new();
+        CHANNELS.open_channel("handler-test", webview.clone(), CallbackFn(3)).unwrap();
+        let id = webview.channel_events()[0]["acceptId"].clone();
+
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let sink = received.clone();
+        CHANNELS.subscribe("handler-test", move |value: u32| sink.lock().unwrap().push(value));
+        CHANNELS.respond("handler-test", |value: u32| Ok(value * 2));
+
+        assert_eq!(invoke("channelPost", json!([id, 5])), Ok(Value::Null));
+        assert_eq!(*received.lock().unwrap(), vec![5]);
+        assert_eq!(invoke("channelRequest", json!([id, 5])), Ok(json!(10)));
+
+        let (d1, d2, d3, d4): ChannelUuid = ipc::deserialize(&id).unwrap();
+        CHANNELS.close_channel(uuid::Uuid::from_fields(d1, d2, d3, &d4));
+        assert_eq!(error(invoke("channelPost", json!([id, 5]))), "channel isn't open");
+        assert_eq!(error(invoke("channelRequest", json!([id, 5]))), "channel isn't open");
+    }
+
+    #[test]
+    fn replay_runs_context_free_commands() {
+        let _directory = config::test_directory();
+        assert_eq!(replay_command("configIsReadOnly", &json!([])), Some(Ok(json!(false))));
+        let wordlist = replay_command("getWordlist", &json!([])).unwrap().unwrap();
+        assert!(wordlist.as_str().unwrap().lines().count() > 1000);
+    }
+}
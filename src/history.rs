@@ -0,0 +1,202 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bson::Document;
+
+use crate::{
+    config,
+    settings::{Profiles, HistoryEntry, HistoryChange}
+};
+
+/// Number of writes kept in `user.db`, older ones are dropped first
+pub const HISTORY_LIMIT: usize = 100;
+
+const HISTORY_KEY: &str = "history";
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64)
+}
+
+/// Profiles and active profile, without the history itself
+fn tracked_document(profiles: &Profiles) -> Document {
+    let mut document = config::profiles_to_document(profiles);
+    document.remove(HISTORY_KEY);
+    document
+}
+
+/// Appends the difference between `old` and `new` to the history of `new`.
+pub fn record(old: &Profiles, new: &mut Profiles) {
+    let old_document = tracked_document(old);
+    let new_document = tracked_document(new);
+
+    let mut changed = Vec::new();
+    config::diff_documents(&old_document, &new_document, &mut Vec::new(), &mut changed);
+    if changed.is_empty() {
+        return;
+    }
+
+    let changes = changed.into_iter()
+        .map(|path| HistoryChange {
+            old_value: config::get_prop_from_path(&path, old_document.clone()),
+            new_value: config::get_prop_from_path(&path, new_document.clone()),
+            path,
+        })
+        .collect();
+
+    new.history.push(HistoryEntry {
+        id: uuid::Uuid::new_v4().to_string(),
+        timestamp: unix_now(),
+        changes,
+    });
+    if new.history.len() > HISTORY_LIMIT {
+        let excess = new.history.len() - HISTORY_LIMIT;
+        new.history.drain(0..excess);
+    }
+}
+
+/// Puts back the previous values of `entries`, newest first. The revert is
+/// a write of its own and ends up in the history as well.
+fn revert_entries(current: &Profiles, entries: &[HistoryEntry]) -> Result<Profiles, String> {
+    let mut document = tracked_document(current);
+    for entry in entries.iter().rev() {
+        for change in entry.changes.iter() {
+            match &change.old_value {
+                Some(value) => config::set_prop_at_path(&change.path, &mut document, value.clone())?,
+                None => {
+                    config::remove_prop_at_path(&change.path, &mut document)?;
+                }
+            }
+        }
+    }
+
+    let mut reverted = config::profiles_from_document(document)?;
+    reverted.history = current.history.clone();
+    Ok(reverted)
+}
+
+pub fn config_history_list(limit: Option<usize>) -> Result<Vec<HistoryEntry>, String> {
+    let history = config::load_profiles().history;
+    let skip = history.len().saturating_sub(limit.unwrap_or(HISTORY_LIMIT));
+    Ok(history.into_iter().skip(skip).rev().collect())
+}
+
+pub fn config_history_revert(id: String) -> Result<(), String> {
+    config::update_profiles(|current| {
+        let entry = current.history.iter()
+            .find(|entry| entry.id == id)
+            .ok_or(format!("history entry {} not found", id))?;
+        revert_entries(current, std::slice::from_ref(entry))
+    })
+}
+
+/// Reverts every write made after `timestamp`.
+pub fn config_history_restore(timestamp: i64) -> Result<(), String> {
+    config::update_profiles(|current| {
+        let start = current.history.iter()
+            .position(|entry| entry.timestamp > timestamp)
+            .unwrap_or(current.history.len());
+        revert_entries(current, &current.history[start..])
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use bson::Bson;
+
+    use super::*;
+    use crate::settings::DEFAULT_PROFILE;
+
+    fn global_path(dotted_path: &str) -> Vec<String> {
+        format!("globalConfig.{}", dotted_path).split('.').map(|s| s.to_string()).collect()
+    }
+
+    fn set(dotted_path: &str, value: Bson) {
+        config::js_config_set_property(global_path(dotted_path), value, None).unwrap();
+    }
+
+    #[test]
+    fn history_keeps_the_latest_entries() {
+        let mut profiles = Profiles::default();
+        for length in 1..=(HISTORY_LIMIT as i32 + 5) {
+            let old = profiles.clone();
+            profiles.profiles.get_mut(DEFAULT_PROFILE).unwrap().password_generation_rules.length = length;
+            record(&old, &mut profiles);
+        }
+        assert_eq!(profiles.history.len(), HISTORY_LIMIT);
+        assert_eq!(profiles.history[0].changes[0].new_value, Some(Bson::Int32(6)));
+        assert_eq!(profiles.history[HISTORY_LIMIT - 1].changes[0].new_value, Some(Bson::Int32(105)));
+
+        // writes without changes aren't recorded
+        let old = profiles.clone();
+        record(&old, &mut profiles);
+        assert_eq!(profiles.history.len(), HISTORY_LIMIT);
+    }
+
+    #[test]
+    fn entries_list_the_changed_paths() {
+        let _directory = config::test_directory();
+        set("generalApp.showOnStart", Bson::Boolean(true));
+
+        let history = config_history_list(None).unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].changes, vec![HistoryChange {
+            path: vec!["profiles", "default", "generalApp", "showOnStart"].into_iter().map(String::from).collect(),
+            old_value: Some(Bson::Boolean(false)),
+            new_value: Some(Bson::Boolean(true))
+        }]);
+    }
+
+    #[test]
+    fn reverting_undoes_a_single_change() {
+        let _directory = config::test_directory();
+        set("generalApp.showOnStart", Bson::Boolean(true));
+        set("generatePage.askOnLeave", Bson::Boolean(false));
+
+        // newest first
+        let history = config_history_list(None).unwrap();
+        assert_eq!(history[1].changes[0].path.last().unwrap(), "showOnStart");
+        config_history_revert(history[1].id.clone()).unwrap();
+
+        let settings = config::load_stored_settings(None).unwrap();
+        assert!(!settings.general_app.show_on_start);
+        assert!(!settings.generate_page.ask_on_leave);
+        // the revert is a change of its own
+        assert_eq!(config_history_list(None).unwrap().len(), 3);
+        assert_eq!(config_history_list(Some(1)).unwrap()[0].changes[0].new_value, Some(Bson::Boolean(false)));
+
+        assert_eq!(
+            config_history_revert("missing".to_string()),
+            Err("history entry missing not found".to_string())
+        );
+    }
+
+    #[test]
+    fn restoring_reverts_everything_after_the_point_in_time() {
+        let _directory = config::test_directory();
+        set("generalApp.showOnStart", Bson::Boolean(true));
+        set("generatePage.askOnLeave", Bson::Boolean(false));
+        set("passwordGenerationRules.length", Bson::Int32(20));
+        // the writes happened within the same second, spread them out
+        config::update_profiles(|current| {
+            let mut profiles = current.clone();
+            for (index, entry) in profiles.history.iter_mut().enumerate() {
+                entry.timestamp = 100 * (index as i64 + 1);
+            }
+            Ok(profiles)
+        }).unwrap();
+
+        config_history_restore(1000).unwrap();
+        assert_eq!(config_history_list(None).unwrap().len(), 3);
+
+        config_history_restore(150).unwrap();
+        let settings = config::load_stored_settings(None).unwrap();
+        assert!(settings.general_app.show_on_start);
+        assert!(settings.generate_page.ask_on_leave);
+        assert_eq!(settings.password_generation_rules.length, 48);
+
+        config_history_restore(0).unwrap();
+        assert_eq!(config::load_stored_settings(None).unwrap(), crate::settings::Settings::default());
+    }
+}
@@ -1,15 +1,19 @@
-use std::{collections::HashMap, sync::{Mutex, Arc}};
+use std::{collections::{HashMap, VecDeque}, future::Future, sync::{Mutex, Arc, atomic::{AtomicU32, Ordering}}};
     
-use serde::{Deserialize, Serialize};
+use lazy_static::lazy_static;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::{Value, value::RawValue};
 
 use serialize_to_javascript::Serialized;
-use tauri_runtime::{
-    window::{DetachedWindow}, webview::WebviewIpcHandler, Dispatch, Runtime, RuntimeHandle, EventLoopProxy
-};
+use tauri_runtime::{window::DetachedWindow, Dispatch, EventLoopProxy};
 use tauri_runtime_wry::{Wry, EventProxy};
 
-use crate::{events::EventLoopMessage, mnemonic, commands, config};
+use crate::{
+    events::EventLoopMessage,
+    trace,
+    webview::{ResponseSink, Sink},
+    workers::{self, Completer}
+};
 
 const MIN_JSON_PARSE_LEN: usize = 10_240;
 const MAX_JSON_STR_LEN: usize = usize::pow(2, 30) - 2;
@@ -24,31 +28,36 @@ pub struct IpcPayload {
     pub callback: CallbackFn,
     pub error: CallbackFn,
     pub command: String,
-    pub inner: Value
+    pub inner: Value,
+    /// `location.origin` of the sending page, as init.js captured it
+    #[serde(default)]
+    pub origin: String,
+    /// Only init.js knows it, so page scripts can't post with another origin
+    #[serde(default)]
+    pub nonce: String
 }
 
-type Window = DetachedWindow<EventLoopMessage, Wry<EventLoopMessage>>;
-type RHandle = <Wry<EventLoopMessage> as Runtime<EventLoopMessage>>::Handle;
+pub type Window = DetachedWindow<EventLoopMessage, Wry<EventLoopMessage>>;
 
 
 #[derive(Clone)]
 pub struct InvokeMessage {
-    pub window: Window,
     pub command: String,
     pub payload: Value
 }
 
 #[derive(Clone)]
 pub struct InvokeResolver {
-    pub window: Window,
+    pub sink: Sink,
     pub callback: CallbackFn,
     pub error: CallbackFn
 }
 
 impl InvokeResolver {
+    #[allow(dead_code)]
     pub fn resolve<T: Serialize>(self, value: T) {
         Self::return_response(
-            self.window, 
+            self.sink.as_ref(), 
             Ok(value).into(), 
             self.callback,
             self.error
@@ -57,7 +66,7 @@ impl InvokeResolver {
 
     pub fn reject<T: Serialize>(self, value: T) {
         Self::return_response(
-            self.window,
+            self.sink.as_ref(),
             Result::<(), _>::Err(value.into()).into(),
             self.callback,
             self.error
@@ -65,18 +74,40 @@ impl InvokeResolver {
     }
 
     pub fn return_response(
-        window: Window,
+        sink: &dyn ResponseSink,
         response: InvokeResponse,
         success_callback: CallbackFn,
         error_callback: CallbackFn
     ) {
+        let result = response.into_result();
+        trace::response(success_callback.0, &result);
+        sink.respond(result, success_callback, error_callback);
+    }
+}
+
+impl ResponseSink for Window {
+    fn respond(&self, result: Result<Value, Value>, callback: CallbackFn, error: CallbackFn) {
         let callback_string = 
-            match format_callback_result(response.into_result(), success_callback, error_callback) {
+            match format_callback_result(result, callback, error) {
                 Ok(cb_str) => cb_str,
-                Err(e) => format_callback(error_callback, &e.to_string())
+                Err(e) => format_callback(error, &e.to_string())
                     .expect("unable to serialze response")
             };
-        let _ = window.dispatcher.eval_script(&callback_string);
+        let _ = self.dispatcher.eval_script(&callback_string);
+    }
+
+    fn channel_event(&self, event: Value) {
+        let script = serialize_js_with(&event, |arg| {
+            format!(
+                r"
+            window.ipcHandler._respondChannelMessage({arg});
+                ",
+                arg = arg
+            )
+        });
+        if let Ok(script) = script {
+            let _ = self.dispatcher.eval_script(script);
+        }
     }
 }
 
@@ -130,6 +161,12 @@ pub struct Invoke {
 
 type WindowEventListenersMap = Mutex<HashMap<String, Arc<Channel>>>;
 type ChannelIdentifier<'a> = (u32, u16, u16, &'a [u8; 8]);
+/// Channel id as the web-app sends it back, see `uuid::Uuid::as_fields`
+pub type ChannelUuid = (u32, u16, u16, [u8; 8]);
+
+type ChannelSubscriber = Arc<dyn Fn(Value) -> Result<(), String> + Send + Sync>;
+type ChannelResponder = Arc<dyn Fn(Value) -> Result<Value, String> + Send + Sync>;
+type ChannelReply = Completer<Result<Value, String>>;
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct ChannelHandshakeRequest(pub String, pub CallbackFn);
@@ -141,96 +178,248 @@ pub struct ChannelMessage<'a> {
     error: Option<Value>
 }
 
+/// Correlates requests made on any channel with the replies of the web-app
+static NEXT_RESOLVER: AtomicU32 = AtomicU32::new(1);
+
 pub struct Channel {
     id: uuid::Uuid,
-    window: Window
+    name: String,
+    sink: Sink,
+    replies: Mutex<HashMap<u32, ChannelReply>>
 }
 
 impl Channel {
     pub fn new(
         id: uuid::Uuid,
-        window: Window,
+        name: &str,
+        sink: Sink,
         response_ch: usize
     ) -> Self {
-        let result = Self { id, window };
+        let result = Self {
+            id,
+            name: name.to_string(),
+            sink,
+            replies: Mutex::new(HashMap::new())
+        };
         result.accept_request(response_ch);
         result
     }
 
     fn accept_request(&self, response_ch: usize) {
-        Self::send(serde_json::json!({
+        self.send(serde_json::json!({
             "type": "accept",
             "token": response_ch,
             "acceptId": self.id.as_fields()
-        }), &self.window)
+        }))
     }
 
-    fn send(value: Value, window: &Window) {
-        let script = serialize_js_with(&value, |arg| {
-            format!(
-                r"
-            window.ipcHandler._respondChannelMessage({arg});
-                ",
-                arg = arg
-            )
-        });
-        if let Ok(script) = script {
-            let _ = window.dispatcher.eval_script(script);
-        }
+    fn send(&self, value: Value) {
+        trace::channel(&self.name, &value);
+        self.sink.channel_event(value);
     }
 
-    #[allow(dead_code)]
     pub fn send_message<T: Serialize>(&self, data: T) {
-        Self::send(serde_json::json!({
+        self.send(serde_json::json!({
             "type": "message",
             "channelId": self.id.as_fields(),
             "data": data
-        }), &self.window)
+        }))
+    }
+
+    /// Sends `data` to the request handler the web-app registered for this
+    /// channel and completes with its reply. Fails once the channel closes.
+    pub fn request<T: Serialize>(&self, data: T) -> impl Future<Output = Result<Value, String>> {
+        let resolver = NEXT_RESOLVER.fetch_add(1, Ordering::SeqCst);
+        let (completer, reply) = workers::oneshot();
+        self.replies.lock().unwrap().insert(resolver, completer);
+        self.send(serde_json::json!({
+            "type": "message",
+            "channelId": self.id.as_fields(),
+            "data": data,
+            "resolver": resolver
+        }));
+        reply
+    }
+
+    /// Completes the request `resolver`, false if it isn't waiting anymore.
+    pub fn resolve(&self, resolver: u32, result: Result<Value, String>) -> bool {
+        let reply = self.replies.lock().unwrap().remove(&resolver);
+        match reply {
+            Some(reply) => {
+                reply.complete(result);
+                true
+            }
+            None => false
+        }
+    }
+
+    /// Fails the requests still waiting for a reply
+    fn fail_replies(&self) {
+        for (_, reply) in self.replies.lock().unwrap().drain() {
+            reply.complete(Err("channel closed".to_string()));
+        }
     }
 
-    #[allow(dead_code)]
     pub fn send_close(&self) {
-        Self::send(serde_json::json!({
+        self.fail_replies();
+        self.send(serde_json::json!({
             "type": "close",
             "channelId": self.id.as_fields()
-        }), &self.window);
+        }));
     }
 }
 
+/// What a full outbound queue does with another message
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[allow(dead_code)]
+pub enum OverflowPolicy {
+    /// Makes room by dropping the oldest queued message
+    DropOldest,
+    /// Keeps the queue as is and drops the new message
+    DropNewest,
+    /// Keeps the queue as is and fails sending the new message
+    Reject
+}
+
+/// Messages sent on a channel the web-app hasn't established (yet)
+struct OutboundQueue {
+    messages: VecDeque<Value>,
+    capacity: usize,
+    policy: OverflowPolicy
+}
+
+impl OutboundQueue {
+    fn new(capacity: usize, policy: OverflowPolicy) -> Self {
+        Self { messages: VecDeque::new(), capacity, policy }
+    }
+
+    fn push(&mut self, message: Value) -> Result<(), String> {
+        if self.capacity == 0 {
+            return Ok(());
+        }
+        if self.messages.len() >= self.capacity {
+            match self.policy {
+                OverflowPolicy::DropOldest => { self.messages.pop_front(); }
+                OverflowPolicy::DropNewest => return Ok(()),
+                OverflowPolicy::Reject => return Err("the queue of the channel is full".to_string())
+            }
+        }
+        self.messages.push_back(message);
+        Ok(())
+    }
+}
+
+/// Capacity of the queue of a channel without one of its own
+const DEFAULT_QUEUE_CAPACITY: usize = 64;
+
 pub struct Channels {
-    channel_items: WindowEventListenersMap
+    channel_items: WindowEventListenersMap,
+    // locked before `channel_items`, so a message can't slip in between
+    // accepting a channel and replaying its queue
+    queues: Mutex<HashMap<String, OutboundQueue>>,
+    subscribers: Mutex<HashMap<String, Vec<ChannelSubscriber>>>,
+    responders: Mutex<HashMap<String, ChannelResponder>>
 }
 
 impl Channels {
     pub fn new() -> Self {
-        Self { channel_items: Mutex::new(HashMap::default()) }
+        Self {
+            channel_items: Mutex::new(HashMap::default()),
+            queues: Mutex::new(HashMap::default()),
+            subscribers: Mutex::new(HashMap::default()),
+            responders: Mutex::new(HashMap::default())
+        }
+    }
+
+    /// Bounds the messages kept for the channel `name` while it isn't
+    /// established. A capacity of 0 drops them instead.
+    pub fn set_queue(&self, name: &str, capacity: usize, policy: OverflowPolicy) {
+        let mut queues = self.queues.lock().unwrap();
+        let queue = queues
+            .entry(name.to_string())
+            .or_insert_with(|| OutboundQueue::new(capacity, policy));
+        queue.capacity = capacity;
+        queue.policy = policy;
+        while queue.messages.len() > capacity {
+            queue.messages.pop_front();
+        }
     }
     
+    /// Accepts the channel `name` and replays the messages queued for it in
+    /// the order they were sent.
     pub fn open_channel(
         &self, 
         name: &str, 
-        window: Window,
+        sink: Sink,
         response_id: CallbackFn
     ) -> Result<(), String> {
+        let mut queues = self.queues.lock().unwrap();
         if let Some(channel) = self.get_channel(name) {
             channel.send_close();
         }
-        let mut items = self.channel_items
+        let channel = Arc::new(Channel::new (
+            uuid::Uuid::new_v4(),
+            name,
+            sink,
+            response_id.0
+        ));
+        self.channel_items
             .lock()
-            .unwrap();
-        items.insert(
-            name.to_string(),
-            Arc::new(Channel::new (
-                uuid::Uuid::new_v4(),
-                window,
-                response_id.0
-            ))
-        );
+            .unwrap()
+            .insert(name.to_string(), channel.clone());
+
+        if let Some(queue) = queues.get_mut(name) {
+            for message in queue.messages.drain(..) {
+                channel.send_message(message);
+            }
+        }
         
         Ok(())
     }
 
-    #[allow(dead_code)]
+    /// Sends `data` on the channel `name`, or queues it until the web-app
+    /// establishes the channel. Fails if the queue is full and rejects it.
+    pub fn send_message<T: Serialize>(&self, name: &str, data: T) -> Result<(), String> {
+        let mut queues = self.queues.lock().unwrap();
+        if let Some(channel) = self.get_channel(name) {
+            channel.send_message(data);
+            return Ok(());
+        }
+        let message = serde_json::to_value(data).map_err(|err| err.to_string())?;
+        queues
+            .entry(name.to_string())
+            .or_insert_with(|| OutboundQueue::new(DEFAULT_QUEUE_CAPACITY, OverflowPolicy::DropOldest))
+            .push(message)
+    }
+
+    /// Closes the channel with `id`, messages are queued again until the
+    /// web-app re-establishes it.
+    pub fn close_channel(&self, id: uuid::Uuid) {
+        let _queues = self.queues.lock().unwrap();
+        let mut items = self.channel_items.lock().unwrap();
+        let name = items.iter()
+            .find(|(_, val)| val.id == id)
+            .map(|(name, _)| name.clone());
+        if let Some(channel) = name.and_then(|name| items.remove(&name)) {
+            channel.send_close();
+        }
+    }
+
+    /// Forgets every channel without notifying the web-app, for when the page
+    /// is reloaded and its listeners are gone.
+    pub fn detach_all(&self) {
+        let _queues = self.queues.lock().unwrap();
+        let channels: Vec<Arc<Channel>> = self.channel_items
+            .lock()
+            .unwrap()
+            .drain()
+            .map(|(_, channel)| channel)
+            .collect();
+        for channel in channels {
+            channel.fail_replies();
+        }
+    }
+
     pub fn get_channel(&self, name: &str) -> Option<Arc<Channel>> {
         self.channel_items
             .lock()
@@ -247,148 +436,95 @@ impl Channels {
             .find(|(_, val)| val.id == id)
             .map(|(_, val)| val.clone())
     }
-}
 
-fn ipc_close_channel(proxy: EventProxy<EventLoopMessage>, invoke: Invoke) -> Option<()> {
-    let arguments = deserialize_arguments(invoke.clone())?;
-    let mut iter = arguments.iter();
-    let resolver = invoke.resolver.clone();
+    /// Calls `handler` with every message the web-app posts on the channel
+    /// `name`. Subscriptions outlive the channel, they apply again once the
+    /// web-app re-establishes it.
+    #[allow(dead_code)]
+    pub fn subscribe<T, F>(&self, name: &str, handler: F)
+    where
+        T: DeserializeOwned,
+        F: Fn(T) + Send + Sync + 'static
+    {
+        let subscriber: ChannelSubscriber = Arc::new(move |data| {
+            let message = serde_json::from_value(data).map_err(|err| err.to_string())?;
+            handler(message);
+            Ok(())
+        });
+        self.subscribers
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_default()
+            .push(subscriber);
+    }
 
-    fn inner(
-        proxy: EventProxy<EventLoopMessage>,
-        (d1, d2, d3, d4): (u32, u16, u16, [u8; 8])
-    ) {
-        let uuid = uuid::Uuid::from_fields(d1, d2, d3, &d4);
-        let _ = proxy.send_event(EventLoopMessage::CloseChannel(uuid));
+    /// Answers the requests the web-app makes on the channel `name`, replacing
+    /// the previous handler.
+    #[allow(dead_code)]
+    pub fn respond<T, R, F>(&self, name: &str, handler: F)
+    where
+        T: DeserializeOwned,
+        R: Serialize,
+        F: Fn(T) -> Result<R, String> + Send + Sync + 'static
+    {
+        let responder: ChannelResponder = Arc::new(move |data| {
+            let request = serde_json::from_value(data).map_err(|err| err.to_string())?;
+            let reply = handler(request)?;
+            serde_json::to_value(reply).map_err(|err| err.to_string())
+        });
+        self.responders
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), responder);
     }
 
-    inner(
-        proxy,
-        get_argument(iter.next()?, resolver.clone())?
-    );
+    pub fn get_channel_by_fields(&self, id: ChannelUuid) -> Result<Arc<Channel>, String> {
+        let (d1, d2, d3, d4) = id;
+        self.get_channel_by_id(uuid::Uuid::from_fields(d1, d2, d3, &d4))
+            .ok_or_else(|| "channel isn't open".to_string())
+    }
 
-    resolver.resolve(Value::Null);
+    /// Hands a message the web-app posted on the channel `name` to its subscribers.
+    pub fn post(&self, name: &str, data: Value) -> Result<(), String> {
+        let subscribers = self.subscribers
+            .lock()
+            .unwrap()
+            .get(name)
+            .cloned()
+            .unwrap_or_default();
+        // called without the lock, so a subscriber may subscribe or post again
+        for subscriber in subscribers.iter() {
+            subscriber(data.clone())?;
+        }
+        Ok(())
+    }
 
-    Some(())
+    /// Answers a request the web-app made on the channel `name`.
+    pub fn request(&self, name: &str, data: Value) -> Result<Value, String> {
+        let responder = self.responders
+            .lock()
+            .unwrap()
+            .get(name)
+            .cloned()
+            .ok_or_else(|| format!("no request handler for channel {}", name))?;
+        responder(data)
+    }
 }
 
-fn invoke_handler(window: Window, proxy: EventProxy<EventLoopMessage>, invoke: Invoke) {
-    let cmd = invoke.message.command.as_str();
-    match cmd {
-        "closeChannel" => {
-            ipc_close_channel(proxy, invoke);
-        }
-        "generateMnemonicPhrase" => {
-            mnemonic::generate_mnemonic_phrase(invoke);
-        }
-        "fromMnemonicPhrase" => {
-            mnemonic::from_mnemonic_phrase(invoke);
-        }
-        "checkChecksum" => {
-            mnemonic::check_checksum(invoke);
-        }
-        "pageContentLoaded" => {
-            let _ = proxy.send_event(EventLoopMessage::PageContentLoaded);
-        }
-        "getWordlist" => {
-            commands::get_wordlist(invoke);
-        }
-        "windowDragMove" => {
-            commands::window_drag_move(window, invoke);
-        }
-        "windowShowSysMenu" => {
-            commands::window_show_sys_menu(proxy, invoke);
-        }
-        "windowClose" => {
-            commands::handle_window_buttons(proxy, invoke, commands::WindowButton::Close);
-        }
-        "windowMinimize" => {
-            commands::handle_window_buttons(proxy, invoke, commands::WindowButton::Minimize);
-        }
-        "windowSetTitle" => {
-            commands::window_set_title(window, invoke);
-        }
-        "showMessageBox" => {
-            commands::show_message_box(window, invoke);
-        }
-        "autostartRegistryExecuteCommand" => {
-            commands::autostart_registry_execute_command(invoke);
-        }
-        "clipboardWriteTextSecure" => {
-            commands::clipboard_write_text_secure(invoke);
-        }
-        "configIsFile" => {
-            config::js_promise_is_file(invoke);
-        }
-        "configSetProperty" => {
-            config::js_config_set_property(invoke);
-        }
-        "configGetProperty" => {
-            config::js_config_get_property(invoke);
-        }
-        _ => {
-            invoke.resolver.reject(format!("command {} not found", cmd));
-        }
+lazy_static! {
+    /// Channels established by the web-app and the handlers listening on them
+    pub static ref CHANNELS: Channels = {
+        let channels = Channels::new();
+        // only the latest location, window state and reminder still matter
+        channels.set_queue("ui-events", 1, OverflowPolicy::DropOldest);
+        channels.set_queue("window-events", 1, OverflowPolicy::DropOldest);
+        channels.set_queue("quiz-events", 1, OverflowPolicy::DropOldest);
+        channels.set_queue("config-events", DEFAULT_QUEUE_CAPACITY, OverflowPolicy::DropOldest);
+        channels
     };
 }
 
-fn handle_invoke_payload(window: Window, payload: IpcPayload, runtime_handle: &RHandle) {
-    let event_proxy = runtime_handle.create_proxy();
-    match payload.command.as_str() {
-        "setInitialized" => {  // Application Initialized
-            let _ = event_proxy.send_event(EventLoopMessage::WebAppInit);
-            InvokeResolver::return_response(
-                window,
-                InvokeResponse::Ok(Value::Null),
-                payload.callback, 
-                CallbackFn(0)
-            );
-        },
-        "establishChannel" => {
-            match deserialize::<ChannelHandshakeRequest>(&payload.inner) {
-                Ok(data) => {
-                    let _ = event_proxy.send_event(
-                        EventLoopMessage::EstablishChannel(data)
-                    );
-
-                    InvokeResolver::return_response(
-                        window,
-                        InvokeResponse::Ok(Value::Null),
-                        payload.callback, 
-                        payload.error
-                    );
-                }
-                Err(err) => {
-                    InvokeResolver::return_response(
-                        window,
-                        InvokeResponse::Err(InvokeError(Value::String(err))),
-                        payload.callback, 
-                        payload.error
-                    );
-                }
-            }
-        }
-        "application-close-window" => {
-            let _ = event_proxy.send_event(EventLoopMessage::ApplicationCloseWindow);
-        }
-        _ => {
-            let message = InvokeMessage { 
-                window: window.clone(),
-                command: payload.command,
-                payload: payload.inner
-            };
-
-            let resolver = InvokeResolver {
-                window: window.clone(),
-                callback: payload.callback,
-                error: payload.error
-            };
-            let invoke = Invoke { message, resolver };
-            invoke_handler(window, event_proxy, invoke);
-        }
-    }
-}
-
 fn serialize_js_with<T, F>(value: &T, cb: F) -> Result<String, serde_json::Error>
 where
     T: Serialize,
@@ -448,50 +584,159 @@ fn format_callback_result<T: Serialize, E: Serialize>(
     }
 }
 
-pub fn create_ipc_handler(
-    runtime_handle: RHandle
-) -> WebviewIpcHandler<EventLoopMessage, Wry<EventLoopMessage>> {
-    Box::new(move |window, request| {
-        match serde_json::from_str::<IpcPayload>(&request) {
-            Ok(payload) => {
-                handle_invoke_payload(window, payload, &runtime_handle);
-            },
-            Err(e) => {
-                let msg = e.to_string();
-                let _ = window.dispatcher.eval_script(&format!(
-                    "console.error({});",
-                    Value::String(msg)
-                ));
-            }
+/// Asks the web-app whether the window may close, it cancels the `close`
+/// event to keep it open. Closes right away while nothing listens on
+/// window-events.
+pub fn request_window_close(proxy: EventProxy<EventLoopMessage>) {
+    let channel = match CHANNELS.get_channel("window-events") {
+        Some(channel) => channel,
+        None => {
+            let _ = proxy.send_event(EventLoopMessage::ApplicationCloseWindow);
+            return;
         }
-    })
+    };
+    let reply = channel.request("close");
+    workers::spawn(async move {
+        if reply.await != Ok(Value::Bool(false)) {
+            let _ = proxy.send_event(EventLoopMessage::ApplicationCloseWindow);
+        }
+    });
 }
 
-pub fn js_window_close_event(window: &Window) {
-    let _ = window.dispatcher.eval_script("_triggerWindowClose();");
+pub fn deserialize<'a, D: Deserialize<'a>>(value: &'a Value) -> Result<D, String> {
+    D::deserialize(value).map_err(|e| e.to_string())
 }
 
-pub fn deserialize_arguments(invoke: Invoke) -> Option<Vec<Value>> {
-    let result: Option<&Vec<Value>> = invoke.message.payload.as_array();
-    if matches!(result, None) {
-        invoke.resolver.reject("payload has to be an array.");
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::webview::MemoryWebview;
+
+    fn open(channels: &Channels, webview: &Arc<MemoryWebview>, name: &str) -> Arc<Channel> {
+        channels.open_channel(name, webview.clone(), CallbackFn(1)).unwrap();
+        channels.get_channel(name).unwrap()
     }
-    if let Some(result) = result {
-        return Some(result.clone());
+
+    /// `data` of the messages the channel `name` replays once it is established
+    fn replayed(channels: &Channels, name: &str) -> Vec<Value> {
+        let webview = MemoryWebview::new();
+        open(channels, &webview, name);
+        webview.channel_events().into_iter()
+            .filter(|event| event["type"] == "message")
+            .map(|event| event["data"].clone())
+            .collect()
     }
-    return None;
-}
 
-pub fn get_argument<'a, D: Deserialize<'a>>(value: &'a Value, resolver: InvokeResolver) -> Option<D> {
-    match D::deserialize(value) {
-        Ok(res) => Some(res),
-        Err(err) => {
-            resolver.reject(err.to_string());
-            None
+    #[test]
+    fn full_queue_drops_the_oldest_message() {
+        let channels = Channels::new();
+        channels.set_queue("test", 2, OverflowPolicy::DropOldest);
+        for value in 1..=3 {
+            assert_eq!(channels.send_message("test", value), Ok(()));
         }
+        assert_eq!(replayed(&channels, "test"), vec![json!(2), json!(3)]);
     }
-}
 
-pub fn deserialize<'a, D: Deserialize<'a>>(value: &'a Value) -> Result<D, String> {
-    D::deserialize(value).map_err(|e| e.to_string())
+    #[test]
+    fn full_queue_drops_the_newest_message() {
+        let channels = Channels::new();
+        channels.set_queue("test", 2, OverflowPolicy::DropNewest);
+        for value in 1..=3 {
+            assert_eq!(channels.send_message("test", value), Ok(()));
+        }
+        assert_eq!(replayed(&channels, "test"), vec![json!(1), json!(2)]);
+    }
+
+    #[test]
+    fn full_queue_rejects_the_message() {
+        let channels = Channels::new();
+        channels.set_queue("test", 2, OverflowPolicy::Reject);
+        assert_eq!(channels.send_message("test", 1), Ok(()));
+        assert_eq!(channels.send_message("test", 2), Ok(()));
+        assert!(channels.send_message("test", 3).is_err());
+        assert_eq!(replayed(&channels, "test"), vec![json!(1), json!(2)]);
+
+        // the replay emptied the queue and the channel takes messages directly
+        assert_eq!(channels.send_message("test", 4), Ok(()));
+    }
+
+    #[test]
+    fn posts_reach_every_subscriber() {
+        let channels = Channels::new();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        for offset in [0, 10] {
+            let received = received.clone();
+            channels.subscribe("test", move |value: u32| received.lock().unwrap().push(value + offset));
+        }
+
+        channels.post("test", json!(1)).unwrap();
+        channels.post("other", json!(2)).unwrap();
+        assert_eq!(*received.lock().unwrap(), vec![1, 11]);
+        assert!(channels.post("test", json!("one")).is_err());
+    }
+
+    #[test]
+    fn subscriptions_outlive_the_channel() {
+        let channels = Channels::new();
+        let webview = MemoryWebview::new();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let sink = received.clone();
+        channels.subscribe("test", move |value: String| sink.lock().unwrap().push(value));
+
+        let channel = open(&channels, &webview, "test");
+        channels.close_channel(channel.id);
+        open(&channels, &webview, "test");
+        channels.post("test", json!("again")).unwrap();
+        assert_eq!(*received.lock().unwrap(), vec!["again".to_string()]);
+    }
+
+    #[test]
+    fn requests_are_answered_by_the_latest_responder() {
+        let channels = Channels::new();
+        assert_eq!(
+            channels.request("test", json!(1)),
+            Err("no request handler for channel test".to_string())
+        );
+
+        channels.respond("test", |value: u32| Ok(value + 1));
+        assert_eq!(channels.request("test", json!(1)), Ok(json!(2)));
+
+        channels.respond("test", |value: u32| if value > 1 { Ok(value * 2) } else { Err("too small".to_string()) });
+        assert_eq!(channels.request("test", json!(2)), Ok(json!(4)));
+        assert_eq!(channels.request("test", json!(1)), Err("too small".to_string()));
+        assert!(channels.request("test", json!("two")).is_err());
+    }
+
+    #[test]
+    fn requests_to_the_web_app_complete_with_its_reply() {
+        let channels = Channels::new();
+        let webview = MemoryWebview::new();
+        let channel = open(&channels, &webview, "test");
+
+        let reply = channel.request("close");
+        let event = webview.channel_events().pop().unwrap();
+        assert_eq!(event["type"], "message");
+        assert_eq!(event["data"], "close");
+        let resolver = event["resolver"].as_u64().unwrap() as u32;
+
+        assert!(channel.resolve(resolver, Ok(json!(false))));
+        assert!(!channel.resolve(resolver, Ok(json!(true))));
+        assert_eq!(workers::block_on(reply), Ok(json!(false)));
+    }
+
+    #[test]
+    fn closing_fails_pending_requests() {
+        let channels = Channels::new();
+        let webview = MemoryWebview::new();
+        let channel = open(&channels, &webview, "test");
+
+        let reply = channel.request(Value::Null);
+        channels.close_channel(channel.id);
+        assert_eq!(workers::block_on(reply), Err("channel closed".to_string()));
+        assert_eq!(webview.channel_events().last().unwrap()["type"], "close");
+    }
 }
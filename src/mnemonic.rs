@@ -1,8 +1,8 @@
-use crate::ipc::{Invoke, deserialize_arguments, get_argument};
+use crate::selftest;
 use serde::{Deserialize, Serialize};
 use lazy_static::lazy_static;
 
-use pyo3::{prelude::*, types::{PyDict, PyTuple}};
+use pyo3::{prelude::*, types::{PyBytes, PyDict, PyTuple}};
 
 lazy_static! {
     static ref CONTEXT: Py<PyDict> = {
@@ -21,18 +21,114 @@ lazy_static! {
 
     static ref WORDLIST: Vec<String> = {
         include_str!("../resources/wordlist.txt")
-            .split("\r\n").map(|f| f.to_string()).collect()
+            .lines().map(|f| f.to_string()).collect()
     };
 }
 
 
-#[derive(Deserialize, Debug)]
-struct ConfigData {
-    characters: bool,
-    digits: bool,
-    punctuation: bool,
-    special: bool,
-    length: i32,
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum OutputEncoding {
+    /// Human readable password built from the selected character groups
+    Log,
+    Hex,
+    Base32,
+    Base64Url,
+    /// UUIDv4-shaped string, `length` is ignored
+    Uuid,
+}
+
+impl OutputEncoding {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Log => "log",
+            Self::Hex => "hex",
+            Self::Base32 => "base32",
+            Self::Base64Url => "base64url",
+            Self::Uuid => "uuid",
+        }
+    }
+}
+
+impl Default for OutputEncoding {
+    fn default() -> Self {
+        Self::Log
+    }
+}
+
+crate::ts_string_enum!(OutputEncoding, ["log", "hex", "base32", "base64url", "uuid"]);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum KeyboardLayout { Us, De, Fr }
+
+crate::ts_string_enum!(KeyboardLayout, ["us", "de", "fr"]);
+
+impl KeyboardLayout {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Us => "us",
+            Self::De => "de",
+            Self::Fr => "fr",
+        }
+    }
+}
+
+/// Revision of the derivation scheme. Bumped whenever a new option could
+/// change the password derived from an existing phrase, so that configs
+/// stored before the change keep their old behaviour.
+pub(crate) const CONFIG_VERSION_LEGACY: u32 = 1;
+const CONFIG_VERSION_ALPHABET_OPTIONS: u32 = 2;
+/// `Log` encoding uses rejection sampling instead of the 6/7-bit packing
+pub(crate) const CONFIG_VERSION_UNIFORM: u32 = 3;
+const CONFIG_VERSION_LATEST: u32 = CONFIG_VERSION_UNIFORM;
+
+fn default_config_version() -> u32 {
+    CONFIG_VERSION_LEGACY
+}
+
+crate::ts_interface! {
+    #[derive(Deserialize, Debug)]
+    #[serde(rename_all = "camelCase")]
+    pub(crate) struct ConfigData {
+        characters: bool,
+        digits: bool,
+        punctuation: bool,
+        special: bool,
+        /// Number of characters for `Log`, number of raw bytes for the byte encodings
+        length: i32,
+
+        #[serde(default)]
+        encoding: OutputEncoding,
+
+        #[serde(default = "default_config_version")]
+        version: u32,
+
+        #[serde(default)]
+        ascii_only: bool,
+
+        #[serde(default)]
+        exclude_ambiguous: bool,
+
+        #[serde(default)]
+        layout: Option<KeyboardLayout>,
+    }
+}
+
+impl ConfigData {
+    fn validate(&self) -> Result<(), String> {
+        if self.version < CONFIG_VERSION_LEGACY || self.version > CONFIG_VERSION_LATEST {
+            return Err(format!("Unsupported config version {}", self.version));
+        }
+        let uses_alphabet_options = self.ascii_only || self.exclude_ambiguous || self.layout.is_some();
+        if uses_alphabet_options && self.version < CONFIG_VERSION_ALPHABET_OPTIONS {
+            return Err(format!(
+                "asciiOnly, excludeAmbiguous and layout require config version {}",
+                CONFIG_VERSION_ALPHABET_OPTIONS
+            ));
+        }
+        Ok(())
+    }
 }
 
 impl ToPyObject for ConfigData {
@@ -43,15 +139,22 @@ impl ToPyObject for ConfigData {
         obj.set_item("punctuation", self.punctuation).unwrap();
         obj.set_item("special", self.special).unwrap();
         obj.set_item("length", self.length).unwrap();
+        obj.set_item("encoding", self.encoding.as_str()).unwrap();
+        obj.set_item("version", self.version).unwrap();
+        obj.set_item("ascii_only", self.ascii_only).unwrap();
+        obj.set_item("exclude_ambiguous", self.exclude_ambiguous).unwrap();
+        obj.set_item("layout", self.layout.map(|l| l.as_str())).unwrap();
 
         obj.into()
     }
 }
 
-#[derive(Serialize)]
-struct PharseData {
-    phrase: Vec<String>,
-    password: String
+crate::ts_interface! {
+    #[derive(Serialize)]
+    pub(crate) struct PharseData {
+        phrase: Vec<String>,
+        pub(crate) password: String
+    }
 }
 
 fn execute_python_function<F>(
@@ -76,32 +179,20 @@ where
     })
 }
 
-fn generate_mnemonic_phrase_impl(data: ConfigData) -> Result<PharseData, String> {
+pub(crate) fn generate_mnemonic_phrase(data: ConfigData) -> Result<PharseData, String> {
+    selftest::ensure_passed()?;
+    data.validate()?;
     execute_python_function("generate_phrase", move |py| PyTuple::new(py, vec![data]))
 }
 
-pub fn generate_mnemonic_phrase(invoke: Invoke) -> Option<()> {
-    let arguments = deserialize_arguments(invoke.clone())?;
-    let mut iter = arguments.iter();
-    let resolver = invoke.resolver.clone();
-
-    let result = generate_mnemonic_phrase_impl(
-        get_argument(iter.next()?, resolver.clone())?
-    );
-
-    match result {
-        Ok(data) => {
-            resolver.resolve(data);
-        }
-        Err(err) => {
-            resolver.reject(err);
-        } 
-    }
-
-    Some(())
+pub(crate) fn from_mnemonic_phrase(phrase: Vec<String>, config: ConfigData) -> Result<PharseData, String> {
+    selftest::ensure_passed()?;
+    derive_from_phrase(phrase, config)
 }
 
-fn from_mnemonic_phrase_impl(phrase: Vec<String>, config: ConfigData) -> Result<PharseData, String> {
+/// Derivation without the self-test gate, only the self-test itself may call this directly.
+pub(crate) fn derive_from_phrase(phrase: Vec<String>, config: ConfigData) -> Result<PharseData, String> {
+    config.validate()?;
     execute_python_function("from_phrase", move |py| {
         let phrase = phrase.to_object(py);
         let config = config.to_object(py);
@@ -110,29 +201,7 @@ fn from_mnemonic_phrase_impl(phrase: Vec<String>, config: ConfigData) -> Result<
     })
 }
 
-pub fn from_mnemonic_phrase(invoke: Invoke) -> Option<()> {
-    let arguments = deserialize_arguments(invoke.clone())?;
-    let mut iter = arguments.iter();
-    let resolver = invoke.resolver.clone();
-
-    let result = from_mnemonic_phrase_impl(
-        get_argument(iter.next()?, resolver.clone())?,
-        get_argument(iter.next()?, resolver.clone())?,
-    );
-
-    match result {
-        Ok(data) => {
-            resolver.resolve(data);
-        }
-        Err(err) => {
-            resolver.reject(err);
-        } 
-    }
-
-    Some(())
-}
-
-fn check_checksum_impl(phrase: Vec<String>) -> Result<bool, String> {
+pub fn check_checksum(phrase: Vec<String>) -> Result<bool, String> {
     Python::with_gil(|py| {
         let locals = CONTEXT.as_ref(py);
         
@@ -147,25 +216,70 @@ fn check_checksum_impl(phrase: Vec<String>) -> Result<bool, String> {
     })
 }
 
-pub fn check_checksum(invoke: Invoke) -> Option<()> {
-    let arguments = deserialize_arguments(invoke.clone())?;
-    let mut iter = arguments.iter();
-    let resolver = invoke.resolver.clone();
+/// Salted hash of a single phrase word, so quiz sessions never hold the phrase itself.
+pub(crate) fn hash_word(salt: &[u8], word: &str) -> Result<String, String> {
+    Python::with_gil(|py| {
+        let locals = CONTEXT.as_ref(py);
+
+        let function = locals.get_item("hash_word")
+            .ok_or("Can't find function".to_string())?;
 
-    let result = check_checksum_impl(
-        get_argument(iter.next()?, resolver.clone())?,
-    );
+        let salt = PyBytes::new(py, salt).to_object(py);
+        let word = word.to_object(py);
+        let result = function.call(PyTuple::new(py, vec![salt, word]), None)
+            .map_err(|err| err.to_string())?;
 
-    match result {
-        Ok(data) => {
-            resolver.resolve(data);
-        }
-        Err(err) => {
-            resolver.reject(err);
-        } 
-    }
+        Ok(result.extract().map_err(|err| err.to_string())?)
+    })
+}
+
+fn execute_python_bytes<F>(
+    name: &str,
+    args: F
+) -> Result<Vec<u8>, String>
+where
+    F: for<'py> FnOnce(Python<'py>) -> Vec<PyObject>
+{
+    Python::with_gil(|py| {
+        let locals = CONTEXT.as_ref(py);
+
+        let function = locals.get_item(name)
+            .ok_or("Can't find function".to_string())?;
+
+        let result = function.call(PyTuple::new(py, args(py)), None)
+            .map_err(|err| err.to_string())?;
+
+        let bytes: &PyBytes = result.downcast().map_err(|err| err.to_string())?;
+        Ok(bytes.as_bytes().to_vec())
+    })
+}
+
+/// 64 byte settings key, `kind` is either `phrase` (secret is a word list) or `passphrase`.
+pub(crate) fn derive_store_key<S: ToPyObject>(kind: &str, secret: &S, salt: &[u8]) -> Result<Vec<u8>, String> {
+    execute_python_bytes("derive_store_key", |py| vec![
+        kind.to_object(py),
+        secret.to_object(py),
+        PyBytes::new(py, salt).to_object(py)
+    ])
+}
 
-    Some(())
+/// Character frequencies of the configured encoder over `samples` random
+/// seeds, in alphabet order. The seeds are drawn from `seed` if given, so the
+/// histogram is reproducible.
+pub(crate) fn encoder_histogram(config: ConfigData, samples: u32, seed: Option<u64>) -> Result<Vec<(String, u64)>, String> {
+    config.validate()?;
+    Python::with_gil(|py| {
+        let locals = CONTEXT.as_ref(py);
+
+        let function = locals.get_item("encoder_histogram")
+            .ok_or("Can't find function".to_string())?;
+
+        let config = config.to_object(py);
+        let result = function.call(PyTuple::new(py, vec![config, samples.to_object(py), seed.to_object(py)]), None)
+            .map_err(|err| err.to_string())?;
+
+        Ok(result.extract().map_err(|err| err.to_string())?)
+    })
 }
 
 #[pyfunction]
@@ -0,0 +1,658 @@
+use std::{collections::HashMap, ffi::OsStr, sync::Arc};
+use std::path::PathBuf;
+use std::io::prelude::*;
+use std::str::FromStr;
+use std::fs::File;
+
+use tar::Archive;
+use wry::application::{
+    event::Event,
+    event_loop::{
+        EventLoopWindowTarget, ControlFlow, EventLoopProxy as WryEventLoopProxy
+    }
+};
+use tauri_runtime_wry::{Wry, Plugin, EventLoopIterationContext, WebContextStore, Message, EventProxy, WindowMessage};
+use tauri_runtime::{
+    Runtime, RunEvent, UserAttentionType,
+    window::{PendingWindow, DetachedWindow, WindowEvent},
+    webview::{WebviewAttributes}, 
+    Dispatch, RuntimeHandle, SystemTray, TrayIcon, SystemTrayEvent, EventLoopProxy,
+    menu::{SystemTrayMenu, CustomMenuItem}, 
+    http::{ResponseBuilder, Response, Request}
+};
+use tauri_utils::{config::{WindowUrl, WindowConfig,}, Theme};
+
+use windows::Win32::Foundation::HWND;
+use url::Url;
+
+use crate::win32::JumpTask;
+use crate::{
+    bindings, commands, config, handler, ipc, overrides, quiz, selftest, trace, transfer, vault, win32, workers,
+    ipc::CHANNELS,
+    app::AppState,
+    events::{ApplicationOpenLocation, EventLoopMessage},
+    webview::{Sink, WindowShell}
+};
+
+struct ApplicationMessagePlugin(HWND);
+
+impl Plugin<EventLoopMessage> for ApplicationMessagePlugin {
+    fn on_event(
+        &mut self,
+        event: &Event<Message<EventLoopMessage>>,
+        _event_loop: &EventLoopWindowTarget<Message<EventLoopMessage>>,
+        _proxy: &WryEventLoopProxy<Message<EventLoopMessage>>,
+        control_flow: &mut ControlFlow,
+        _context: EventLoopIterationContext<'_, EventLoopMessage>,
+        _web_context: &WebContextStore,
+  ) -> bool {
+        match event {
+            Event::UserEvent(Message::UserEvent(EventLoopMessage::ApplicationQuit)) => {
+                *control_flow = ControlFlow::Exit;
+            }
+            Event::UserEvent(
+                Message::Window(_, WindowMessage::Close)
+            ) => {
+                win32::send_close_message(self.0);
+                return true;
+            }
+            _ => ()
+        }
+        return false;
+    }
+}
+
+fn check_start_with_launcher(
+    args: &Vec<String>
+) -> bool {
+    // autostart entries may put other flags, e.g. `--portable`, first
+    has_flag_argument(args, "--launcher")
+}
+
+fn check_portable_argument(
+    args: &Vec<String>
+) -> bool {
+    args.iter().skip(1).any(|arg| arg.to_lowercase() == "--portable")
+}
+
+fn check_self_test_argument(
+    args: &Vec<String>
+) -> bool {
+    if args.len() <= 1 {
+        return false;
+    }
+    &args[1].to_lowercase() == "--self-test"
+}
+
+fn check_measure_bias_argument(
+    args: &Vec<String>
+) -> bool {
+    if args.len() <= 1 {
+        return false;
+    }
+    &args[1].to_lowercase() == "--measure-bias"
+}
+
+/// `--emit-bindings [file]`, regenerates the TypeScript types of the IPC commands
+fn check_emit_bindings_argument(
+    args: &Vec<String>
+) -> Option<&str> {
+    if args.len() <= 1 || &args[1].to_lowercase() != "--emit-bindings" {
+        return None;
+    }
+    Some(args.get(2).map_or(bindings::BINDINGS_PATH, |path| path.as_str()))
+}
+
+/// `--replay-ipc <file> [--data-dir <dir>] [--report <file>]`, re-runs a trace recorded with `--trace-ipc`
+fn check_replay_argument(
+    args: &Vec<String>
+) -> Option<&str> {
+    if args.len() <= 2 || &args[1].to_lowercase() != "--replay-ipc" {
+        return None;
+    }
+    Some(args[2].as_str())
+}
+
+fn print_bias_reports(samples: u32) -> bool {
+    match selftest::measure_bias(samples) {
+        Ok(reports) => {
+            for report in reports.iter() {
+                println!(
+                    "encoder v{}: {} characters, chi-square {:.1} (critical {:.1}), max deviation {:.1}% -> {}",
+                    report.version,
+                    report.alphabet_size,
+                    report.chi_square,
+                    report.critical_value,
+                    report.max_deviation * 100.0,
+                    if report.is_biased() { "biased" } else { "uniform" }
+                );
+            }
+            // the legacy encoder is known to be biased, only the current one has to pass
+            reports.last().map_or(false, |r| !r.is_biased())
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            false
+        }
+    }
+}
+
+fn check_settings_transfer_argument(
+    args: &Vec<String>
+) -> Option<&'static str> {
+    if args.len() <= 2 {
+        return None;
+    }
+    ["--export-settings", "--import-settings"].iter()
+        .find(|&&s| s == args[1].to_lowercase().as_str())
+        .copied()
+}
+
+fn get_option_argument<'a>(args: &'a Vec<String>, name: &str) -> Option<&'a String> {
+    let index = args.iter().position(|arg| arg.to_lowercase() == name)?;
+    args.get(index + 1)
+}
+
+fn has_flag_argument(args: &Vec<String>, name: &str) -> bool {
+    args.iter().skip(1).any(|arg| arg.to_lowercase() == name)
+}
+
+/// `--phrase-file <file>` or `--passphrase-file <file>`, read from a file so the
+/// secret doesn't show up in the process list
+fn get_unlock_secret_argument(args: &Vec<String>) -> Result<Option<vault::UnlockSecret>, String> {
+    if let Some(file) = get_option_argument(args, "--phrase-file") {
+        let text = std::fs::read_to_string(file).map_err(|err| err.to_string())?;
+        let phrase = text.split_whitespace().map(|word| word.to_string()).collect();
+        return Ok(Some(vault::UnlockSecret::Phrase(phrase)));
+    }
+    if let Some(file) = get_option_argument(args, "--passphrase-file") {
+        let text = std::fs::read_to_string(file).map_err(|err| err.to_string())?;
+        // only the line break an editor leaves at the end isn't part of the passphrase
+        let passphrase = text.trim_end_matches(|c| c == '\r' || c == '\n').to_string();
+        return Ok(Some(vault::UnlockSecret::Passphrase(passphrase)));
+    }
+    Ok(None)
+}
+
+/// `--export-settings <file|-> [--path a.b] [--format json|toml] [--profile name]` and
+/// `--import-settings <file> [--path a.b] [--format json|toml] [--profile name] [--replace] [--dry-run]`,
+/// both taking `--phrase-file <file>` or `--passphrase-file <file>` for encrypted settings
+fn run_settings_transfer(args: &Vec<String>, command: &str) -> Result<(), String> {
+    if let Some(secret) = get_unlock_secret_argument(args)? {
+        vault::config_unlock(secret)?;
+    }
+    let file = &args[2];
+    let path: Vec<String> = get_option_argument(args, "--path")
+        .map_or(vec![], |p| p.split('.').map(|s| s.to_string()).collect());
+    let profile = get_option_argument(args, "--profile").map(|p| p.as_str());
+    let format = match get_option_argument(args, "--format") {
+        Some(name) => transfer::TransferFormat::from_name(name)?,
+        None => transfer::TransferFormat::from_path(file)
+    };
+
+    if command == "--export-settings" {
+        let data = transfer::export_settings(&path, format, profile)?;
+        if file == "-" {
+            println!("{}", data);
+            return Ok(());
+        }
+        return std::fs::write(file, data).map_err(|err| err.to_string());
+    }
+
+    let text = std::fs::read_to_string(file).map_err(|err| err.to_string())?;
+    let mode = if has_flag_argument(args, "--replace") {
+        transfer::ImportMode::Replace
+    } else { transfer::ImportMode::Merge };
+    let dry_run = has_flag_argument(args, "--dry-run");
+
+    let changes = transfer::import_settings(&text, format, &path, mode, dry_run, profile)?;
+    for line in transfer::format_changes(&changes) {
+        println!("{}", line);
+    }
+    config::flush()
+}
+
+fn check_aol_argument(
+    args: &Vec<String>
+) -> Option<usize> {
+    // anywhere on the command line, `--portable --generate` opens the generate page as well
+    ["--generate", "--retrieve"].iter().position(|&s| has_flag_argument(args, s))
+}
+
+fn get_applicaton_open_location(args: &Vec<String>) -> ApplicationOpenLocation {
+    match check_aol_argument(args) {
+        Some(0) => ApplicationOpenLocation::Generate,
+        Some(1) => ApplicationOpenLocation::Retrieve,
+        _ => ApplicationOpenLocation::Auto
+    }
+}
+
+fn create_window_config() -> WindowConfig {
+    WindowConfig {
+        label: "1".to_string(),
+        url: WindowUrl::External(Url::from_str("http://localhost:3000").unwrap()),
+        file_drop_enabled: false,
+        center: true,
+        width: 800f64,
+        height: 630f64,
+        resizable: false,
+        title: "".to_string(),
+        fullscreen: false,
+        focus: true,
+        transparent: false,
+        maximized: false,
+        visible: false,
+        decorations: false,
+        always_on_top: false,
+        skip_taskbar: false,
+
+        x: None,
+        y: None,
+        min_width: None,
+        min_height: None,
+        max_width: None,
+        max_height: None,
+        theme: Some(Theme::Light)
+    }
+}
+
+fn create_custom_protocol_handler(
+) -> Box<dyn Fn(&Request) -> Result<Response, Box<dyn std::error::Error>> + Send + Sync + 'static> {
+    let mut current_exe = std::env::current_exe().unwrap();
+    current_exe.pop();
+    let tarfile = File::open(current_exe.join("bundle.tar")).unwrap();
+    let tar: HashMap<PathBuf, Vec<u8>> = Archive::new(tarfile)
+        .entries()
+        .unwrap()
+        .map(|e| {
+            let mut e = e.unwrap();
+            let path = e.path().unwrap().as_ref().to_owned();
+            let mut buffer: Vec<u8> = Vec::default();
+            e.read_to_end(&mut buffer).unwrap();
+            (path, buffer)
+        }).collect();
+    Box::new(move |request| {
+        let path = request.uri().replace("mne://", "");
+        let path = std::path::Path::new(&path);
+
+        if path.starts_with("apps") {
+            let path = path.strip_prefix("apps").unwrap();
+
+            
+            if let Some(data) = tar.get(path) {
+                let mimetype = match &path.extension().and_then(OsStr::to_str).unwrap().to_lowercase() as &str {
+                    "html" => "text/html",
+                    "svg" => "image/svg+xml",
+                    "ttf" => "font/ttf",
+                    "js" => "text/javascript",
+                    "css" => "text/css",
+                    _ => panic!("Hi Mom!")
+                };
+                return Ok(ResponseBuilder::new().status(200).mimetype(mimetype).body(data.to_vec()).unwrap());
+            }
+        }
+
+        Ok(ResponseBuilder::new().status(404).body(vec![]).unwrap())
+    })
+}
+
+fn create_window_form_runtime(
+    runtime: &Wry<EventLoopMessage>
+) -> (DetachedWindow<EventLoopMessage, Wry<EventLoopMessage>>, HWND) {
+    let config = create_window_config();
+    let url = config.url.clone();
+    let mut pending = PendingWindow::with_config(
+        config, 
+        WebviewAttributes::new(url), 
+        "1"
+    ).unwrap();
+    if cfg!(debug_assertions)
+    {
+        pending.url = "http://localhost:3000".to_string();
+    } else {
+        pending.register_uri_scheme_protocol("mne", create_custom_protocol_handler());
+        pending.url = "mne://apps/index.html".to_string();
+    }
+    pending.ipc_handler = Some(handler::create_ipc_handler(runtime.handle()));
+    pending.webview_attributes.initialization_scripts.push(handler::init_script());
+    let detached = runtime.create_window(pending).unwrap();
+
+    let hwnd = detached.dispatcher.hwnd().expect("Couldn't get hwnd");
+    win32::set_icon_from_resource(hwnd, 1).expect("seticon failed");
+    win32::install_event_hook(hwnd, runtime.handle().create_proxy());
+
+    (detached, hwnd)
+}
+
+fn report_self_test_failure(hwnd: HWND, err: String) {
+    let _ = commands::show_message_box_impl(hwnd.0, commands::MessageBoxOptions {
+        message: "Mnemonic failed its self-test".to_string(),
+        detail: format!(
+            "This build doesn't derive the expected passwords, so deriving passwords has been disabled.\n\n{}",
+            err
+        ),
+        dialog_type: "error".to_string(),
+        title: "Mnemonic".to_string(),
+        no_link: true,
+        ..Default::default()
+    });
+}
+
+fn show_window_borderless(
+    window: &DetachedWindow<EventLoopMessage, Wry<EventLoopMessage>>,
+    hwnd: HWND
+) {
+    let _ = window.dispatcher.show();
+    win32::window_enable_visual_styles(hwnd).expect("Couldn't make window borderless");
+}
+
+/// The application window as `AppState` sees it
+struct NativeWindow {
+    window: DetachedWindow<EventLoopMessage, Wry<EventLoopMessage>>,
+    hwnd: HWND,
+    proxy: EventProxy<EventLoopMessage>
+}
+
+impl WindowShell for NativeWindow {
+    fn show(&self) {
+        show_window_borderless(&self.window, self.hwnd);
+    }
+
+    fn hide(&self) {
+        let _ = self.window.dispatcher.hide();
+    }
+
+    fn close(&self) {
+        let _ = self.window.dispatcher.close();
+    }
+
+    fn minimize(&self) {
+        let _ = self.window.dispatcher.minimize();
+    }
+
+    fn is_visible(&self) -> bool {
+        self.window.dispatcher.is_visible().unwrap_or(true)
+    }
+
+    fn request_attention(&self) {
+        let _ = self.window.dispatcher.set_focus();
+        let _ = self.window.dispatcher.request_user_attention(
+            Some(UserAttentionType::Informational)
+        );
+    }
+
+    fn show_sys_menu(&self, x: i32, y: i32) {
+        let _ = win32::show_sys_menu(self.hwnd, x, y);
+    }
+
+    fn notify(&self, title: &str, message: &str) {
+        let _ = win32::show_notification(self.hwnd, title, message);
+    }
+
+    fn notify_close_requested(&self) {
+        ipc::request_window_close(self.proxy.clone());
+    }
+
+    fn sink(&self) -> Sink {
+        Arc::new(self.window.clone())
+    }
+}
+
+fn run(
+    args: Vec<String>,
+    runtime: Wry<EventLoopMessage>,
+    is_launcher: bool,
+    (window, hwnd): (DetachedWindow<EventLoopMessage, Wry<EventLoopMessage>>, HWND)
+) {
+    let should_show_window = if is_launcher {
+        config::load_settings().general_app.show_on_start
+    } else { true };
+
+    let application_open_location = if should_show_window {
+        Some(get_applicaton_open_location(&args))
+    } else { None };
+
+    let current_path = std::env::current_exe().unwrap();
+    let current_path = current_path.as_os_str().to_str().unwrap();
+    
+    let _ = win32::set_jump_list(vec![
+        JumpTask {
+            title: "Generate new Phrase",
+            description: "Jump to Generate Page",
+            arguments: "--generate",
+            icon_path: current_path,
+            icon_index: 1,
+            program: current_path,
+        },
+        JumpTask {
+            title: "Retrieve Password",
+            description: "Jump to Retrieve Page",
+            arguments: "--retrieve",
+            icon_path: current_path,
+            icon_index: 2,
+            program: current_path,
+        }
+    ]);
+
+    let channels = &*CHANNELS;
+    let shell = NativeWindow { window, hwnd, proxy: runtime.create_proxy() };
+    let mut state = AppState::new(is_launcher, application_open_location);
+    runtime.run(move |event| match event {
+        RunEvent::UserEvent(message) => {
+            // the settings live outside `AppState`, which is tested off Windows as well
+            match &message {
+                EventLoopMessage::PageLoading => config::clear_subscriptions(),
+                EventLoopMessage::ApplicationQuit => { let _ = config::flush(); }
+                _ => ()
+            }
+            state.handle(message, &shell, channels);
+        }
+        RunEvent::WindowEvent { event, .. } => {
+            match event {
+                WindowEvent::CloseRequested { signal_tx } => {
+                    if state.close_requested(&shell) {
+                        let _ = signal_tx.send(true);
+                    }
+                }
+                _ => ()
+            }
+        }
+        RunEvent::Exit => {
+            let _ = config::flush();
+        }
+        _ => ()
+    });
+}
+
+static PIPE_PATH: &str = r#"\\?\pipe\mn\{FA1CF89B-458F-416F-8251-7A3DE2157E9D}"#;
+
+fn start_pipe_server(
+    proxy: EventProxy<EventLoopMessage>,
+) {
+    std::thread::spawn(move || {
+        win32::create_pipe_server(
+            PIPE_PATH.to_string(),
+            |mut server_file| {
+                let mut buffer = [0; 1];
+                server_file.read(&mut buffer).unwrap();
+
+                let aol = match buffer[0] {
+                    0x42 => Some(ApplicationOpenLocation::Auto),
+                    0x44 => Some(ApplicationOpenLocation::Generate),
+                    0x46 => Some(ApplicationOpenLocation::Retrieve),
+                    _ => None
+                };
+                
+                if let Some(aol) = aol {
+                    let _ = proxy.send_event(EventLoopMessage::ApplicationOpenWindow(aol));
+                }
+            }
+        );
+    });
+}
+
+fn notify_window_process(aol: ApplicationOpenLocation) {
+    let payload: u8 = match aol {
+        ApplicationOpenLocation::Auto => 0x42,
+        ApplicationOpenLocation::Generate => 0x44,
+        ApplicationOpenLocation::Retrieve => 0x46,
+    };
+    let mut client_pipe_handle = win32::connect_to_pipe(PIPE_PATH.to_string());
+    client_pipe_handle.write(&[payload]).unwrap();
+}
+
+fn init_system_tray(runtime: &mut Wry<EventLoopMessage>) {
+    let open = CustomMenuItem::new("open", "Open");
+    let quit = CustomMenuItem::new("quit", "Quit");
+
+    let menu = SystemTrayMenu::new()
+        .add_item(open.clone())
+        .add_item(quit.clone());
+
+    let icon_data = include_bytes!("../Icons/Icon.ico").to_vec();
+    let tray = SystemTray::new().with_icon(TrayIcon::Raw(icon_data))
+        .with_menu(menu);
+
+    let _tray_handler = runtime.system_tray(tray)
+        .expect("Failed to run as tray");
+    runtime.on_system_tray_event(
+        create_system_tray_handler(runtime.create_proxy(), (open.id, quit.id))
+    );
+}
+
+fn create_system_tray_handler(
+    proxy: EventProxy<EventLoopMessage>,
+    (open, quit): (u16, u16)
+) -> Box<dyn Fn(&SystemTrayEvent) + Send + 'static> {
+    Box::new(move |event| match event {
+        SystemTrayEvent::LeftClick { .. } => {
+            let _ = proxy.send_event(EventLoopMessage::ApplicationOpenWindow(ApplicationOpenLocation::Auto));
+        }
+        SystemTrayEvent::MenuItemClick(e) => {
+            let _ = if e == &open {
+                proxy.send_event(EventLoopMessage::ApplicationOpenWindow(ApplicationOpenLocation::Auto))
+            } else if e == &quit {
+                proxy.send_event(EventLoopMessage::ApplicationQuit)
+            } else { Ok(()) };
+        }
+        _ => ()
+    })
+}
+
+fn install_plugin(
+    runtime: &mut Wry<EventLoopMessage>,
+    (_, window): &(DetachedWindow<EventLoopMessage, Wry<EventLoopMessage>>, HWND)
+) {
+    runtime.plugin(ApplicationMessagePlugin(window.clone()))
+}
+
+pub fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    config::set_portable(check_portable_argument(&args));
+    overrides::set_command_line_arguments(&args);
+
+    if check_self_test_argument(&args) {
+        win32::attach_parent_console();
+        let result = selftest::run();
+        match &result {
+            Ok(_) => println!("Self-test passed"),
+            Err(err) => eprintln!("{}", err)
+        }
+        std::process::exit(if result.is_ok() { 0 } else { 1 });
+    }
+
+    if check_measure_bias_argument(&args) {
+        win32::attach_parent_console();
+        let samples = args.get(2)
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(5000);
+        std::process::exit(if print_bias_reports(samples) { 0 } else { 1 });
+    }
+
+    if let Some(path) = check_emit_bindings_argument(&args) {
+        win32::attach_parent_console();
+        let result = bindings::emit(path);
+        if let Err(err) = &result {
+            eprintln!("{}", err);
+        }
+        std::process::exit(if result.is_ok() { 0 } else { 1 });
+    }
+
+    if let Some(path) = check_replay_argument(&args) {
+        win32::attach_parent_console();
+        // a scratch directory unless asked otherwise, the replay writes settings
+        let directory = get_option_argument(&args, "--data-dir")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| std::env::temp_dir().join(format!("mnemonic-replay-{}", uuid::Uuid::new_v4())));
+        config::set_data_directory(directory);
+        let report_path = get_option_argument(&args, "--report")
+            .cloned()
+            .unwrap_or_else(|| format!("{}.replay.txt", path));
+        let result = trace::replay(path, handler::replay_command).and_then(|report| {
+            let text = report.lines.join("\n");
+            println!("{}", text);
+            std::fs::write(&report_path, text + "\n").map_err(|err| err.to_string())?;
+            Ok(report.matched)
+        });
+        match &result {
+            Ok(false) => eprintln!("Some responses differ from the trace, see {}", report_path),
+            Err(err) => eprintln!("{}", err),
+            Ok(true) => ()
+        }
+        std::process::exit(if matches!(result, Ok(true)) { 0 } else { 1 });
+    }
+
+    if let Some(command) = check_settings_transfer_argument(&args) {
+        win32::attach_parent_console();
+        let result = run_settings_transfer(&args, command);
+        if let Err(err) = &result {
+            eprintln!("{}", err);
+        }
+        std::process::exit(if result.is_ok() { 0 } else { 1 });
+    }
+
+    // `--trace-ipc <file>`
+    if let Some(path) = get_option_argument(&args, "--trace-ipc") {
+        if let Err(err) = trace::start(path) {
+            eprintln!("Couldn't start the IPC trace: {}", err);
+        }
+    }
+
+    let mutex = win32::try_open_allocation_mutex();
+
+    let mut runtime = Wry::<EventLoopMessage>::new()
+        .expect("Couldn't build wry runtime");
+
+    if mutex.is_none() { // App already open in other process
+        notify_window_process(get_applicaton_open_location(&args));
+        return;
+    }
+
+    let window_tpl = create_window_form_runtime(&runtime);
+
+    // the self-test runs python, so it mustn't hold up the window. Derivations
+    // wait for its outcome themselves.
+    let hwnd = window_tpl.1;
+    workers::spawn(async move {
+        if let Err(err) = selftest::ensure_passed() {
+            workers::spawn_blocking(move || report_self_test_failure(hwnd, err)).await;
+        }
+    });
+    
+    let use_launcher = check_start_with_launcher(&args);
+    
+    if use_launcher {
+        init_system_tray(&mut runtime);
+    }
+    install_plugin(&mut runtime, &window_tpl);
+    start_pipe_server(runtime.create_proxy());
+    quiz::start_reminder_scheduler(runtime.create_proxy());
+    config::install_change_notifications(runtime.create_proxy());
+
+    run(
+        args,
+        runtime,
+        use_launcher,
+        window_tpl
+    );
+}
@@ -0,0 +1,381 @@
+use std::{fs, path::PathBuf, sync::Mutex};
+
+use bson::{Bson, Document};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{config, settings::Settings};
+
+/// Where an effective setting comes from. Later layers win over earlier ones.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum ConfigLayer {
+    Default,
+    Policy,
+    User,
+    Environment,
+    CommandLine,
+}
+
+const ENVIRONMENT_PREFIX: &str = "MNEMONIC_";
+const POLICY_FILE: &str = "policy.json";
+
+/// Contents of the system-wide policy file, e.g.
+/// `{"settings": {"generalApp": {"showOnStart": false}}, "locked": ["generalApp.showOnStart"]}`.
+/// Locked settings ignore `user.db` and can't be changed from the UI.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+struct PolicyFile {
+    settings: Document,
+    locked: Vec<String>,
+}
+
+/// A single setting forced by a layer above the defaults
+#[derive(Debug, Clone)]
+struct Override {
+    path: Vec<String>,
+    value: Bson,
+}
+
+#[derive(Default)]
+struct Policy {
+    overrides: Vec<Override>,
+    locked: Vec<Vec<String>>,
+}
+
+lazy_static! {
+    static ref POLICY: Policy = read_policy();
+    static ref ENVIRONMENT: Vec<Override> = read_environment();
+    static ref COMMAND_LINE: Mutex<Vec<Override>> = Mutex::new(Vec::new());
+    /// Last reported reason the layers couldn't be combined, reported once
+    static ref CONFLICT: Mutex<Option<String>> = Mutex::new(None);
+}
+
+/// `%ProgramData%\Mnemonic` on Windows, `/etc/mnemonic` elsewhere
+fn policy_directory() -> Option<PathBuf> {
+    if cfg!(windows) {
+        std::env::var_os("ProgramData").map(|p| PathBuf::from(p).join("Mnemonic"))
+    } else {
+        Some(PathBuf::from("/etc/mnemonic"))
+    }
+}
+
+fn split_path(dotted_path: &str) -> Vec<String> {
+    dotted_path.split('.').map(|s| s.to_string()).collect()
+}
+
+/// Values are read as JSON, anything that doesn't parse is taken as a string
+fn parse_value(raw: &str) -> Option<Bson> {
+    let value = serde_json::from_str(raw).unwrap_or_else(|_| Value::String(raw.to_string()));
+    bson::to_bson(&value).ok()
+}
+
+/// Drops overrides that name unknown settings or have the wrong type, so a
+/// single typo doesn't disable the whole layer.
+fn is_valid_override(item: &Override) -> bool {
+    let mut document = config::settings_to_document(&Settings::default());
+    config::set_prop_at_path(&item.path, &mut document, item.value.clone()).is_ok()
+        && config::settings_from_document(document).is_ok()
+}
+
+fn flatten(document: &Document, prefix: &mut Vec<String>, overrides: &mut Vec<Override>) {
+    for (key, value) in document {
+        prefix.push(key.clone());
+        match value {
+            Bson::Document(child) => flatten(child, prefix, overrides),
+            value => overrides.push(Override { path: prefix.clone(), value: value.clone() })
+        }
+        prefix.pop();
+    }
+}
+
+fn read_policy() -> Policy {
+    let file = policy_directory()
+        .and_then(|directory| fs::read_to_string(directory.join(POLICY_FILE)).ok())
+        .and_then(|text| serde_json::from_str::<Value>(&text).ok())
+        .and_then(|value| bson::to_bson(&value).ok())
+        .and_then(|value| bson::from_bson::<PolicyFile>(value).ok())
+        .unwrap_or_default();
+
+    let mut overrides = Vec::new();
+    flatten(&file.settings, &mut Vec::new(), &mut overrides);
+    Policy {
+        overrides: overrides.into_iter().filter(is_valid_override).collect(),
+        locked: file.locked.iter().map(|path| split_path(path)).collect(),
+    }
+}
+
+/// `GENERAL_APP` -> `generalApp`
+fn camel_case(segment: &str) -> String {
+    let mut result = String::new();
+    for (i, word) in segment.to_lowercase().split('_').enumerate() {
+        let mut chars = word.chars();
+        match chars.next() {
+            Some(first) if i > 0 => {
+                result.extend(first.to_uppercase());
+                result.extend(chars);
+            }
+            _ => result.push_str(word)
+        }
+    }
+    result
+}
+
+/// `MNEMONIC_PASSWORD_GENERATION_RULES__LENGTH=32` sets `passwordGenerationRules.length`
+fn read_environment() -> Vec<Override> {
+    std::env::vars()
+        .filter_map(|(name, value)| {
+            let setting = name.strip_prefix(ENVIRONMENT_PREFIX)?;
+            Some(Override {
+                path: setting.split("__").map(camel_case).collect(),
+                value: parse_value(&value)?,
+            })
+        })
+        .filter(is_valid_override)
+        .collect()
+}
+
+/// Collects every `--set passwordGenerationRules.length=32` argument.
+pub fn set_command_line_arguments(args: &Vec<String>) {
+    let overrides = args.iter()
+        .zip(args.iter().skip(1))
+        .filter(|(flag, _)| flag.to_lowercase() == "--set")
+        .filter_map(|(_, assignment)| {
+            let (path, value) = assignment.split_once('=')?;
+            Some(Override { path: split_path(path), value: parse_value(value)? })
+        })
+        .filter(is_valid_override)
+        .collect();
+    *COMMAND_LINE.lock().unwrap() = overrides;
+}
+
+fn covers(item: &[String], path: &[String]) -> bool {
+    path.starts_with(item) || item.starts_with(path)
+}
+
+impl Policy {
+    fn is_locked(&self, path: &[String]) -> bool {
+        self.locked.iter().any(|locked| path.starts_with(locked))
+    }
+
+    /// Defaults with every policy value on top
+    fn document(&self) -> Document {
+        let mut document = config::settings_to_document(&Settings::default());
+        for item in self.overrides.iter() {
+            let _ = config::set_prop_at_path(&item.path, &mut document, item.value.clone());
+        }
+        document
+    }
+
+    /// `settings` with the locked settings replaced by their policy values.
+    fn enforce_locked(&self, settings: &Settings) -> Result<Settings, String> {
+        let policy = self.document();
+        let mut document = config::settings_to_document(settings);
+        for path in self.locked.iter() {
+            match config::get_prop_from_path(path, policy.clone()) {
+                Some(value) => config::set_prop_at_path(path, &mut document, value)?,
+                None => {
+                    config::remove_prop_at_path(path, &mut document)?;
+                }
+            }
+        }
+        config::settings_from_document(document)
+    }
+}
+
+/// Whether the setting at `path` (without `globalConfig`) is locked by policy.
+pub fn is_locked(path: &[String]) -> bool {
+    POLICY.is_locked(path)
+}
+
+/// Rejects writes to settings locked by policy.
+pub fn check_writable(path: &[String]) -> Result<(), String> {
+    if is_locked(path) {
+        return Err(format!("{} is locked by policy", path.join(".")));
+    }
+    Ok(())
+}
+
+/// Rejects replacing `old` with `new` if that changes a setting locked by
+/// policy, for writes that replace more than a single path.
+pub fn check_changes_writable(old: &Settings, new: &Settings) -> Result<(), String> {
+    let mut changed = Vec::new();
+    config::diff_documents(
+        &config::settings_to_document(old),
+        &config::settings_to_document(new),
+        &mut Vec::new(),
+        &mut changed
+    );
+    changed.iter().try_for_each(|path| check_writable(path))
+}
+
+/// Values of `settings` at the keys stored in `user.db`, `stored` being the
+/// profile as written there. A stored key hides the policy even if it holds
+/// the default value.
+fn user_overrides(settings: &Settings, stored: &Document) -> Vec<Override> {
+    let document = config::settings_to_document(settings);
+    let mut keys = Vec::new();
+    flatten(stored, &mut Vec::new(), &mut keys);
+    keys.into_iter()
+        .filter_map(|key| Some(Override {
+            value: config::get_prop_from_path(&key.path, document.clone())?,
+            path: key.path,
+        }))
+        .collect()
+}
+
+fn report_conflict(message: String) {
+    let mut reported = CONFLICT.lock().unwrap();
+    if reported.as_ref() != Some(&message) {
+        eprintln!("{}", message);
+        *reported = Some(message);
+    }
+}
+
+/// Resolves all layers on top of the settings stored in `user.db`. If the
+/// layers combine into invalid settings, only the locked policy values are
+/// applied to the stored ones and the conflict is reported.
+pub fn apply(settings: &Settings, stored: &Document) -> Settings {
+    let mut overrides = ENVIRONMENT.clone();
+    overrides.extend(COMMAND_LINE.lock().unwrap().iter().cloned());
+    resolve(settings, stored, &POLICY, &overrides).unwrap_or_else(|(err, fallback)| {
+        report_conflict(format!("The settings layers don't combine into valid settings ({}), only the locked policy values are applied", err));
+        fallback
+    })
+}
+
+/// `apply` for the given policy and overrides above the user layer. Fails
+/// with the reason and the settings to use instead.
+fn resolve(
+    settings: &Settings,
+    stored: &Document,
+    policy: &Policy,
+    overrides: &[Override]
+) -> Result<Settings, (String, Settings)> {
+    if policy.overrides.is_empty() && policy.locked.is_empty() && overrides.is_empty() {
+        return Ok(settings.clone());
+    }
+
+    let mut effective = policy.document();
+    let user = user_overrides(settings, stored);
+    let layers = user.iter().filter(|item| !policy.is_locked(&item.path))
+        .chain(overrides.iter());
+    for item in layers {
+        let _ = config::set_prop_at_path(&item.path, &mut effective, item.value.clone());
+    }
+
+    config::settings_from_document(effective).map_err(|err| {
+        let fallback = policy.enforce_locked(settings)
+            .or_else(|_| config::settings_from_document(policy.document()))
+            .unwrap_or_default();
+        (err, fallback)
+    })
+}
+
+/// Highest layer that sets anything at or below `path`, `stored` being the
+/// profile as written to `user.db`.
+pub fn source_of(path: &[String], stored: &Document) -> ConfigLayer {
+    if COMMAND_LINE.lock().unwrap().iter().any(|item| covers(&item.path, path)) {
+        return ConfigLayer::CommandLine;
+    }
+    if ENVIRONMENT.iter().any(|item| covers(&item.path, path)) {
+        return ConfigLayer::Environment;
+    }
+
+    let policy = POLICY.overrides.iter().any(|item| covers(&item.path, path));
+    if is_locked(path) {
+        return if policy { ConfigLayer::Policy } else { ConfigLayer::Default };
+    }
+    let mut user = Vec::new();
+    flatten(stored, &mut Vec::new(), &mut user);
+    if user.iter().any(|item| covers(&item.path, path) && !is_locked(&item.path)) {
+        return ConfigLayer::User;
+    }
+    if policy { ConfigLayer::Policy } else { ConfigLayer::Default }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(dotted_path: &str) -> Vec<String> {
+        split_path(dotted_path)
+    }
+
+    fn policy(settings: &[(&str, Bson)], locked: &[&str]) -> Policy {
+        Policy {
+            overrides: settings.iter()
+                .map(|(dotted_path, value)| Override { path: path(dotted_path), value: value.clone() })
+                .collect(),
+            locked: locked.iter().map(|dotted_path| path(dotted_path)).collect(),
+        }
+    }
+
+    fn stored(dotted_paths: &[&str], settings: &Settings) -> Document {
+        let document = config::settings_to_document(settings);
+        let mut stored = Document::new();
+        for dotted_path in dotted_paths {
+            let value = config::get_prop_from_path(&path(dotted_path), document.clone()).unwrap();
+            config::set_prop_at_path(&path(dotted_path), &mut stored, value).unwrap();
+        }
+        stored
+    }
+
+    #[test]
+    fn unset_keys_follow_the_policy() {
+        let policy = policy(&[("generalApp.showOnStart", Bson::Boolean(true))], &[]);
+        let settings = Settings::default();
+        let effective = resolve(&settings, &Document::new(), &policy, &[]).unwrap();
+        assert!(effective.general_app.show_on_start);
+    }
+
+    #[test]
+    fn stored_default_hides_an_unlocked_policy_value() {
+        let policy = policy(&[("generalApp.showOnStart", Bson::Boolean(true))], &[]);
+        let settings = Settings::default();
+        let stored = stored(&["generalApp.showOnStart"], &settings);
+        let effective = resolve(&settings, &stored, &policy, &[]).unwrap();
+        assert!(!effective.general_app.show_on_start);
+    }
+
+    #[test]
+    fn locked_policy_value_wins_over_the_user() {
+        let policy = policy(&[("restorePage.closeOnBlur", Bson::Boolean(false))], &["restorePage"]);
+        let settings = Settings::default();
+        let stored = stored(&["restorePage.closeOnBlur"], &settings);
+        let effective = resolve(&settings, &stored, &policy, &[]).unwrap();
+        assert!(!effective.restore_page.close_on_blur);
+    }
+
+    #[test]
+    fn conflicting_layers_keep_the_locked_policy_values() {
+        // the policy locks the only character group the user kept enabled
+        let policy = policy(&[
+            ("passwordGenerationRules.characters", Bson::Boolean(false)),
+            ("generalApp.showOnStart", Bson::Boolean(true)),
+        ], &["passwordGenerationRules.characters", "generalApp"]);
+        let mut settings = Settings::default();
+        settings.password_generation_rules.digits = false;
+        settings.password_generation_rules.punctuation = false;
+        let stored = stored(&[
+            "passwordGenerationRules.digits",
+            "passwordGenerationRules.punctuation",
+        ], &settings);
+
+        let (err, fallback) = resolve(&settings, &stored, &policy, &[]).unwrap_err();
+        assert!(err.contains("character group"));
+        // the user's rules can't satisfy the lock either, so only the policy is left
+        assert!(fallback.general_app.show_on_start);
+        assert!(!fallback.password_generation_rules.characters);
+        assert!(fallback.password_generation_rules.digits);
+    }
+
+    #[test]
+    fn overrides_win_over_locked_policy_values() {
+        let policy = policy(&[("passwordGenerationRules.length", Bson::Int32(16))], &["passwordGenerationRules"]);
+        let overrides = [Override { path: path("passwordGenerationRules.length"), value: Bson::Int32(20) }];
+        let effective = resolve(&Settings::default(), &Document::new(), &policy, &overrides).unwrap();
+        assert_eq!(effective.password_generation_rules.length, 20);
+    }
+}
@@ -0,0 +1,190 @@
+use serde::Serialize;
+
+use crate::{
+    config,
+    settings::{Settings, Profiles}
+};
+
+crate::ts_interface! {
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct ProfileList {
+        active_profile: String,
+        profiles: Vec<String>,
+    }
+}
+
+pub fn profile_list() -> Result<ProfileList, String> {
+    let profiles = config::load_profiles();
+    Ok(ProfileList {
+        profiles: profiles.profiles.keys().cloned().collect(),
+        active_profile: profiles.active_profile,
+    })
+}
+
+fn insert_profile(current: &Profiles, name: String, settings: Settings) -> Result<Profiles, String> {
+    if current.profiles.contains_key(&name) {
+        return Err(format!("profile {} already exists", name));
+    }
+    let mut profiles = current.clone();
+    profiles.profiles.insert(name, settings);
+    Ok(profiles)
+}
+
+pub fn profile_switch(name: String) -> Result<(), String> {
+    config::update_profiles(|current| {
+        current.get(Some(&name))?;
+        let mut profiles = current.clone();
+        profiles.active_profile = name;
+        Ok(profiles)
+    })
+}
+
+pub fn profile_create(name: String) -> Result<(), String> {
+    config::update_profiles(|current| insert_profile(current, name, Settings::default()))
+}
+
+pub fn profile_clone(source: String, name: String) -> Result<(), String> {
+    config::update_profiles(|current| {
+        let settings = current.get(Some(&source))?.clone();
+        insert_profile(current, name, settings)
+    })
+}
+
+/// Renames a profile, the active one stays active under its new name.
+pub fn profile_rename(name: String, new_name: String) -> Result<(), String> {
+    config::update_profiles(|current| {
+        let settings = current.get(Some(&name))?.clone();
+        let mut profiles = insert_profile(current, new_name.clone(), settings)?;
+        profiles.profiles.remove(&name);
+        if profiles.active_profile == name {
+            profiles.active_profile = new_name;
+        }
+        Ok(profiles)
+    })
+}
+
+pub fn profile_delete(name: String) -> Result<(), String> {
+    config::update_profiles(|current| {
+        current.get(Some(&name))?;
+        if current.active_profile == name {
+            return Err("the active profile can't be deleted, switch to another one first".to_string());
+        }
+        let mut profiles = current.clone();
+        profiles.profiles.remove(&name);
+        Ok(profiles)
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use bson::Bson;
+
+    use super::*;
+
+    fn list() -> (String, Vec<String>) {
+        let list = profile_list().unwrap();
+        (list.active_profile, list.profiles)
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn show_on_start(profile: &str) -> bool {
+        config::load_stored_settings(Some(profile)).unwrap().general_app.show_on_start
+    }
+
+    fn set_show_on_start(profile: &str, value: bool) {
+        let path = names(&["globalConfig", "generalApp", "showOnStart"]);
+        config::js_config_set_property(path, Bson::Boolean(value), Some(profile.to_string())).unwrap();
+    }
+
+    #[test]
+    fn created_profiles_start_from_the_defaults() {
+        let _directory = config::test_directory();
+        set_show_on_start("default", true);
+        profile_create("work".to_string()).unwrap();
+
+        assert_eq!(list(), ("default".to_string(), names(&["default", "work"])));
+        assert!(!show_on_start("work"));
+        assert_eq!(
+            profile_create("work".to_string()),
+            Err("profile work already exists".to_string())
+        );
+    }
+
+    #[test]
+    fn cloned_profiles_copy_the_settings() {
+        let _directory = config::test_directory();
+        set_show_on_start("default", true);
+        profile_clone("default".to_string(), "work".to_string()).unwrap();
+        assert!(show_on_start("work"));
+
+        // independent from then on
+        set_show_on_start("work", false);
+        assert!(show_on_start("default"));
+        assert!(profile_clone("default".to_string(), "work".to_string()).is_err());
+        assert!(profile_clone("missing".to_string(), "other".to_string()).is_err());
+    }
+
+    #[test]
+    fn switching_changes_the_active_settings() {
+        let _directory = config::test_directory();
+        profile_create("work".to_string()).unwrap();
+        profile_switch("work".to_string()).unwrap();
+        set_show_on_start("work", true);
+
+        assert_eq!(list().0, "work");
+        assert!(config::load_settings().general_app.show_on_start);
+        assert!(!show_on_start("default"));
+
+        assert_eq!(
+            profile_switch("missing".to_string()),
+            Err("profile missing doesn't exist".to_string())
+        );
+        assert_eq!(list().0, "work");
+    }
+
+    #[test]
+    fn renamed_profiles_keep_their_settings() {
+        let _directory = config::test_directory();
+        profile_create("work".to_string()).unwrap();
+        set_show_on_start("work", true);
+
+        profile_rename("work".to_string(), "office".to_string()).unwrap();
+        assert_eq!(list(), ("default".to_string(), names(&["default", "office"])));
+        assert!(show_on_start("office"));
+
+        // the active profile stays active
+        profile_rename("default".to_string(), "home".to_string()).unwrap();
+        assert_eq!(list(), ("home".to_string(), names(&["home", "office"])));
+
+        assert_eq!(
+            profile_rename("home".to_string(), "office".to_string()),
+            Err("profile office already exists".to_string())
+        );
+        assert!(profile_rename("missing".to_string(), "other".to_string()).is_err());
+        assert_eq!(list(), ("home".to_string(), names(&["home", "office"])));
+    }
+
+    #[test]
+    fn only_inactive_profiles_can_be_deleted() {
+        let _directory = config::test_directory();
+        profile_create("work".to_string()).unwrap();
+        set_show_on_start("work", true);
+
+        assert_eq!(
+            profile_delete("default".to_string()),
+            Err("the active profile can't be deleted, switch to another one first".to_string())
+        );
+        assert!(profile_delete("missing".to_string()).is_err());
+
+        profile_delete("work".to_string()).unwrap();
+        assert_eq!(list(), ("default".to_string(), names(&["default"])));
+        // the name is free again, without the old settings
+        profile_create("work".to_string()).unwrap();
+        assert!(!show_on_start("work"));
+    }
+}
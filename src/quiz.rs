@@ -0,0 +1,242 @@
+use std::{collections::HashMap, sync::Mutex, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use lazy_static::lazy_static;
+use serde::Serialize;
+use tauri_runtime::EventLoopProxy;
+use tauri_runtime_wry::EventProxy;
+
+use crate::{
+    config, mnemonic,
+    events::{EventLoopMessage, QuizEvent},
+    settings::PhraseSchedule
+};
+
+const PHRASE_LENGTH: usize = 12;
+const QUESTIONS_PER_SESSION: usize = 3;
+const MAX_INTERVAL_DAYS: i64 = 64;
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+const REMINDER_POLL_INTERVAL: Duration = Duration::from_secs(60);
+/// Mixed into the phrase fingerprint, so it isn't a plain hash of the phrase
+const PHRASE_KEY_SALT: &[u8] = b"mnemonic-quiz-schedule";
+/// Hex digits of the fingerprint that are stored
+const PHRASE_KEY_LENGTH: usize = 16;
+
+struct QuizSession {
+    phrase_key: String,
+    salt: [u8; 16],
+    word_hashes: Vec<String>,
+    positions: Vec<usize>,
+    answers: HashMap<usize, bool>,
+}
+
+lazy_static! {
+    static ref SESSIONS: Mutex<HashMap<uuid::Uuid, QuizSession>> = Mutex::new(HashMap::new());
+}
+
+crate::ts_interface! {
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct QuizStart {
+        session_id: String,
+        positions: Vec<usize>,
+    }
+}
+
+crate::ts_interface! {
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct QuizSchedule {
+        interval_days: i64,
+        next_review: Option<i64>,
+        streak: i64,
+        due: bool,
+    }
+}
+
+crate::ts_interface! {
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct QuizResult {
+        correct: usize,
+        total: usize,
+        schedule: QuizSchedule,
+    }
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64)
+}
+
+fn random_bytes() -> [u8; 16] {
+    // v4 uuids are backed by the OS random number generator
+    *uuid::Uuid::new_v4().as_bytes()
+}
+
+/// Uniform random number below `bound`, which has to be at most 256
+fn random_below(bound: usize) -> usize {
+    // rejection sampling: bytes above the largest multiple of `bound`
+    // would favour the small numbers
+    let limit = 256 - 256 % bound;
+    loop {
+        if let Some(byte) = random_bytes().iter().find(|byte| (**byte as usize) < limit) {
+            return *byte as usize % bound;
+        }
+    }
+}
+
+fn pick_positions(count: usize) -> Vec<usize> {
+    let mut positions: Vec<usize> = (0..PHRASE_LENGTH).collect();
+    for i in 0..count {
+        let j = i + random_below(PHRASE_LENGTH - i);
+        positions.swap(i, j);
+    }
+    positions.truncate(count);
+    positions
+}
+
+fn normalize_word(word: &str) -> String {
+    word.trim().to_lowercase()
+}
+
+fn check_phrase(phrase: &[String]) -> Result<(), String> {
+    if phrase.len() != PHRASE_LENGTH {
+        return Err(format!("phrase has to consist of {} words", PHRASE_LENGTH));
+    }
+    Ok(())
+}
+
+/// Identifies the schedule of `phrase` in the settings without storing the phrase
+fn phrase_key(phrase: &[String]) -> Result<String, String> {
+    let words: Vec<String> = phrase.iter().map(|word| normalize_word(word)).collect();
+    let mut key = mnemonic::hash_word(PHRASE_KEY_SALT, &words.join(" "))?;
+    key.truncate(PHRASE_KEY_LENGTH);
+    Ok(key)
+}
+
+fn parse_session_id(session_id: &str) -> Result<uuid::Uuid, String> {
+    uuid::Uuid::parse_str(session_id).map_err(|err| err.to_string())
+}
+
+fn to_schedule(phrase: &PhraseSchedule) -> QuizSchedule {
+    QuizSchedule {
+        interval_days: phrase.interval_days,
+        next_review: phrase.next_review,
+        streak: phrase.streak,
+        due: phrase.next_review.map_or(true, |next| next <= unix_now()),
+    }
+}
+
+fn read_schedule(phrase_key: &str) -> QuizSchedule {
+    let phrases = config::load_settings().quiz.phrases;
+    to_schedule(&phrases.get(phrase_key).cloned().unwrap_or_default())
+}
+
+fn update_schedule(phrase_key: &str, passed: bool) -> Result<QuizSchedule, String> {
+    let current = read_schedule(phrase_key);
+    let (interval_days, streak) = if passed {
+        let interval = if current.next_review.is_none() { 1 } else { current.interval_days * 2 };
+        (interval.min(MAX_INTERVAL_DAYS), current.streak + 1)
+    } else {
+        (1, 0)
+    };
+    let next_review = unix_now() + interval_days * SECONDS_PER_DAY;
+
+    config::update_settings(|settings| {
+        settings.quiz.phrases.insert(
+            phrase_key.to_string(),
+            PhraseSchedule { interval_days, streak, next_review: Some(next_review) }
+        );
+    })?;
+
+    Ok(QuizSchedule { interval_days, next_review: Some(next_review), streak, due: false })
+}
+
+pub fn quiz_start(phrase: Vec<String>) -> Result<QuizStart, String> {
+    check_phrase(&phrase)?;
+
+    let phrase_key = phrase_key(&phrase)?;
+    let salt = random_bytes();
+    let word_hashes = phrase.iter()
+        .map(|word| mnemonic::hash_word(&salt, &normalize_word(word)))
+        .collect::<Result<Vec<String>, String>>()?;
+    let positions = pick_positions(QUESTIONS_PER_SESSION);
+
+    let id = uuid::Uuid::new_v4();
+    SESSIONS.lock().unwrap().insert(id, QuizSession {
+        phrase_key,
+        salt,
+        word_hashes,
+        positions: positions.clone(),
+        answers: HashMap::new()
+    });
+
+    Ok(QuizStart { session_id: id.to_string(), positions })
+}
+
+pub fn quiz_answer(session_id: String, position: usize, word: String) -> Result<bool, String> {
+    let id = parse_session_id(&session_id)?;
+    let mut sessions = SESSIONS.lock().unwrap();
+    let session = sessions.get_mut(&id)
+        .ok_or("quiz session not found".to_string())?;
+
+    if !session.positions.contains(&position) {
+        return Err(format!("position {} isn't part of this quiz", position));
+    }
+    // a second try would turn the quiz into guessing
+    if session.answers.contains_key(&position) {
+        return Err(format!("position {} has already been answered", position));
+    }
+
+    let hash = mnemonic::hash_word(&session.salt, &normalize_word(&word))?;
+    let correct = hash == session.word_hashes[position];
+    session.answers.insert(position, correct);
+
+    Ok(correct)
+}
+
+pub fn quiz_finish(session_id: String) -> Result<QuizResult, String> {
+    let id = parse_session_id(&session_id)?;
+    let session = SESSIONS.lock().unwrap().remove(&id)
+        .ok_or("quiz session not found".to_string())?;
+
+    // unanswered positions count as wrong
+    let total = session.positions.len();
+    let correct = session.answers.values().filter(|correct| **correct).count();
+    let schedule = update_schedule(&session.phrase_key, correct == total)?;
+
+    Ok(QuizResult { correct, total, schedule })
+}
+
+pub fn quiz_get_schedule(phrase: Vec<String>) -> Result<QuizSchedule, String> {
+    check_phrase(&phrase)?;
+    Ok(read_schedule(&phrase_key(&phrase)?))
+}
+
+pub fn quiz_cancel(session_id: String) -> Result<(), String> {
+    let id = parse_session_id(&session_id)?;
+    SESSIONS.lock().unwrap().remove(&id);
+    Ok(())
+}
+
+/// Polls the review schedules and posts a single `QuizReminder` per phrase and due date.
+pub fn start_reminder_scheduler(proxy: EventProxy<EventLoopMessage>) {
+    std::thread::spawn(move || {
+        let mut last_reminded: HashMap<String, i64> = HashMap::new();
+        loop {
+            for (key, phrase) in config::load_settings().quiz.phrases.iter() {
+                let schedule = to_schedule(phrase);
+                if let Some(next_review) = schedule.next_review {
+                    if schedule.due && last_reminded.get(key) != Some(&next_review) {
+                        last_reminded.insert(key.clone(), next_review);
+                        let _ = proxy.send_event(EventLoopMessage::QuizReminder(
+                            QuizEvent::Reminder { due: next_review }
+                        ));
+                    }
+                }
+            }
+            std::thread::sleep(REMINDER_POLL_INTERVAL);
+        }
+    });
+}
@@ -0,0 +1,530 @@
+use std::{collections::HashMap, future::Future, marker::PhantomData, sync::{Arc, Mutex}};
+
+use lazy_static::lazy_static;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use tauri_runtime_wry::EventProxy;
+
+use crate::{
+    bindings::{CommandSignature, Declarations, TypeScript, TypeScriptArguments, TypeScriptResult},
+    events::EventLoopMessage,
+    ipc::{Invoke, InvokeError, InvokeResolver, InvokeResponse, Window},
+    workers::{self, BoxFuture, TaskHandle}
+};
+
+/// What a command needs besides its arguments, declared by the type of its
+/// first parameter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CommandContext {
+    None,
+    Window,
+    Proxy
+}
+
+/// Everything a command can ask for
+#[derive(Clone)]
+pub struct CommandScope {
+    pub window: Window,
+    pub proxy: EventProxy<EventLoopMessage>
+}
+
+/// Context a command takes as its first parameter
+pub trait FromScope: Sized + 'static {
+    const CONTEXT: CommandContext;
+
+    fn from_scope(scope: &CommandScope) -> Self;
+}
+
+impl FromScope for Window {
+    const CONTEXT: CommandContext = CommandContext::Window;
+
+    fn from_scope(scope: &CommandScope) -> Self {
+        scope.window.clone()
+    }
+}
+
+impl FromScope for EventProxy<EventLoopMessage> {
+    const CONTEXT: CommandContext = CommandContext::Proxy;
+
+    fn from_scope(scope: &CommandScope) -> Self {
+        scope.proxy.clone()
+    }
+}
+
+/// What a command returns, either its response or a future resolving to it
+pub enum CommandOutput {
+    Ready(InvokeResponse),
+    Pending(BoxFuture<InvokeResponse>)
+}
+
+/// Return value of a command. The error is sent to the web-app as is, most
+/// commands use a `String`.
+pub trait IntoCommandOutput {
+    fn into_output(self) -> CommandOutput;
+}
+
+impl<T: Serialize, E: Serialize> IntoCommandOutput for Result<T, E> {
+    fn into_output(self) -> CommandOutput {
+        CommandOutput::Ready(self.map_err(InvokeError::from).into())
+    }
+}
+
+/// Result of a command that finishes later with a `T`, see `deferred`
+pub struct Deferred<T>(BoxFuture<InvokeResponse>, PhantomData<fn() -> T>);
+
+impl<T> IntoCommandOutput for Deferred<T> {
+    fn into_output(self) -> CommandOutput {
+        CommandOutput::Pending(self.0)
+    }
+}
+
+impl<T: TypeScript> TypeScriptResult for Deferred<T> {
+    fn ts_result(declarations: &mut Declarations) -> String {
+        T::ts_type(declarations)
+    }
+}
+
+/// Lets a command return a future. It is polled on the worker pool and the
+/// web-app gets its result once it completes.
+pub fn deferred<F, T, E>(future: F) -> Deferred<T>
+where
+    F: Future<Output = Result<T, E>> + Send + 'static,
+    T: Serialize,
+    E: Serialize
+{
+    Deferred(Box::pin(async move { future.await.map_err(InvokeError::from).into() }), PhantomData)
+}
+
+/// Positional arguments of a command. Trailing arguments the web-app leaves
+/// out decode like `null`, so they can be taken as `Option`. Arguments beyond
+/// the ones the command takes are ignored.
+pub trait CommandArguments: Sized + 'static {
+    fn decode(arguments: &[Value]) -> Result<Self, String>;
+}
+
+fn decode_argument<D: DeserializeOwned>(value: Option<&Value>) -> Result<D, String> {
+    let result = match value {
+        Some(value) => D::deserialize(value),
+        None => D::deserialize(&Value::Null)
+    };
+    result.map_err(|err| err.to_string())
+}
+
+/// A command without context
+pub trait Handler<Args>: Send + Sync + 'static {
+    type Output;
+
+    fn call(&self, args: Args) -> CommandOutput;
+}
+
+/// A command taking a `Window` or `EventProxy` before its arguments
+pub trait ContextHandler<C, Args>: Send + Sync + 'static {
+    type Output;
+
+    fn call(&self, context: C, args: Args) -> CommandOutput;
+}
+
+macro_rules! impl_handlers {
+    ($($arg:ident),*) => {
+        impl<$($arg: DeserializeOwned + 'static,)*> CommandArguments for ($($arg,)*) {
+            #[allow(unused_variables, unused_mut)]
+            fn decode(arguments: &[Value]) -> Result<Self, String> {
+                let mut iter = arguments.iter();
+                Ok(($(decode_argument::<$arg>(iter.next())?,)*))
+            }
+        }
+
+        impl<F, R, $($arg,)*> Handler<($($arg,)*)> for F
+        where
+            F: Fn($($arg),*) -> R + Send + Sync + 'static,
+            R: IntoCommandOutput
+        {
+            type Output = R;
+
+            #[allow(non_snake_case)]
+            fn call(&self, ($($arg,)*): ($($arg,)*)) -> CommandOutput {
+                self($($arg),*).into_output()
+            }
+        }
+
+        impl<F, R, C, $($arg,)*> ContextHandler<C, ($($arg,)*)> for F
+        where
+            F: Fn(C, $($arg),*) -> R + Send + Sync + 'static,
+            R: IntoCommandOutput
+        {
+            type Output = R;
+
+            #[allow(non_snake_case)]
+            fn call(&self, context: C, ($($arg,)*): ($($arg,)*)) -> CommandOutput {
+                self(context, $($arg),*).into_output()
+            }
+        }
+    };
+}
+
+impl_handlers!();
+impl_handlers!(A);
+impl_handlers!(A, B);
+impl_handlers!(A, B, C1);
+impl_handlers!(A, B, C1, D);
+impl_handlers!(A, B, C1, D, E);
+impl_handlers!(A, B, C1, D, E, G);
+
+/// Called without a scope when invoked headlessly, see `handler::dispatch_headless`
+type BoxedHandler = Arc<dyn Fn(Option<&CommandScope>, &[Value]) -> CommandOutput + Send + Sync>;
+
+/// Invokes running on the worker pool, by the callback handle of the web-app
+struct PendingInvoke {
+    resolver: InvokeResolver,
+    task: TaskHandle
+}
+
+lazy_static! {
+    static ref PENDING: Mutex<HashMap<usize, PendingInvoke>> = Mutex::new(HashMap::new());
+}
+
+fn respond(resolver: InvokeResolver, response: InvokeResponse) {
+    InvokeResolver::return_response(resolver.sink.as_ref(), response, resolver.callback, resolver.error);
+}
+
+/// Runs `future` on the worker pool and resolves the invoke with its result,
+/// unless it has been cancelled in the meantime.
+fn spawn_tracked(resolver: InvokeResolver, future: BoxFuture<InvokeResponse>) {
+    let id = resolver.callback.0;
+    // held until the invoke is tracked, so a quick task can't finish before
+    let mut pending = PENDING.lock().unwrap();
+    let task = workers::spawn(async move {
+        let response = future.await;
+        let finished = PENDING.lock().unwrap().remove(&id);
+        if let Some(finished) = finished {
+            respond(finished.resolver, response);
+        }
+    });
+    pending.insert(id, PendingInvoke { resolver, task });
+}
+
+/// Cancels the invoke registered with the callback handle `id` and rejects
+/// its promise. Invokes that already finished are left alone.
+pub fn cancel_invoke(id: usize) -> Result<bool, String> {
+    let cancelled = PENDING.lock().unwrap().remove(&id);
+    match cancelled {
+        Some(cancelled) => {
+            cancelled.task.cancel();
+            cancelled.resolver.reject("cancelled");
+            Ok(true)
+        }
+        None => Ok(false)
+    }
+}
+
+pub struct Command {
+    context: CommandContext,
+    on_worker: bool,
+    traced: bool,
+    sensitive: bool,
+    signature: fn(&mut Declarations) -> CommandSignature,
+    handler: BoxedHandler
+}
+
+fn signature<Args: TypeScriptArguments, R: TypeScriptResult>(declarations: &mut Declarations) -> CommandSignature {
+    CommandSignature {
+        arguments: Args::ts_arguments(declarations),
+        result: R::ts_result(declarations)
+    }
+}
+
+impl Command {
+    /// Runs the command on the worker pool instead of the thread handling the
+    /// IPC message, for handlers that take long enough to freeze the window.
+    pub fn on_worker(&mut self) -> &mut Self {
+        self.on_worker = true;
+        self
+    }
+
+    /// Records the arguments of the command in the IPC trace. They are left
+    /// out otherwise, positional arguments can't be told apart from secrets.
+    pub fn traced(&mut self) -> &mut Self {
+        self.traced = true;
+        self
+    }
+
+    /// Keeps the arguments and result of the command out of the IPC trace,
+    /// for commands handling the phrase or passwords.
+    pub fn sensitive(&mut self) -> &mut Self {
+        self.sensitive = true;
+        self
+    }
+
+    pub fn context(&self) -> CommandContext {
+        self.context
+    }
+
+    pub fn is_traced(&self) -> bool {
+        self.traced && !self.sensitive
+    }
+
+    pub fn is_sensitive(&self) -> bool {
+        self.sensitive
+    }
+
+    fn invoke(&self, scope: Option<CommandScope>, invoke: Invoke) {
+        let resolver = invoke.resolver;
+        let arguments = match invoke.message.payload {
+            Value::Array(arguments) => arguments,
+            _ => return resolver.reject("payload has to be an array.")
+        };
+
+        let handler = self.handler.clone();
+        if self.on_worker {
+            spawn_tracked(resolver, Box::pin(async move {
+                match handler(scope.as_ref(), &arguments) {
+                    CommandOutput::Ready(response) => response,
+                    CommandOutput::Pending(future) => future.await
+                }
+            }));
+            return;
+        }
+
+        match handler(scope.as_ref(), &arguments) {
+            CommandOutput::Ready(response) => respond(resolver, response),
+            CommandOutput::Pending(future) => spawn_tracked(resolver, future)
+        }
+    }
+}
+
+/// Commands the web-app can invoke, by name
+pub struct CommandRegistry {
+    commands: HashMap<&'static str, Command>
+}
+
+impl CommandRegistry {
+    pub fn new() -> Self {
+        Self { commands: HashMap::new() }
+    }
+
+    fn insert(
+        &mut self,
+        name: &'static str,
+        context: CommandContext,
+        signature: fn(&mut Declarations) -> CommandSignature,
+        handler: BoxedHandler
+    ) -> &mut Command {
+        let previous = self.commands.insert(name, Command { context, on_worker: false, traced: false, sensitive: false, signature, handler });
+        assert!(previous.is_none(), "command {} registered twice", name);
+        self.commands.get_mut(name).unwrap()
+    }
+
+    /// Registers a command that only needs its arguments.
+    pub fn command<F, Args>(&mut self, name: &'static str, handler: F) -> &mut Command
+    where
+        F: Handler<Args>,
+        F::Output: TypeScriptResult,
+        Args: CommandArguments + TypeScriptArguments
+    {
+        self.insert(name, CommandContext::None, signature::<Args, F::Output>, Arc::new(move |_, arguments| {
+            match Args::decode(arguments) {
+                Ok(args) => handler.call(args),
+                Err(err) => CommandOutput::Ready(InvokeResponse::Err(err.into()))
+            }
+        }))
+    }
+
+    /// Registers a command taking its context as the first parameter.
+    pub fn context_command<F, C, Args>(&mut self, name: &'static str, handler: F) -> &mut Command
+    where
+        F: ContextHandler<C, Args>,
+        F::Output: TypeScriptResult,
+        C: FromScope,
+        Args: CommandArguments + TypeScriptArguments
+    {
+        self.insert(name, C::CONTEXT, signature::<Args, F::Output>, Arc::new(move |scope, arguments| {
+            let scope = match scope {
+                Some(scope) => scope,
+                None => return CommandOutput::Ready(InvokeResponse::Err("command needs a window".into()))
+            };
+            match Args::decode(arguments) {
+                Ok(args) => handler.call(C::from_scope(scope), args),
+                Err(err) => CommandOutput::Ready(InvokeResponse::Err(err.into()))
+            }
+        }))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Command> {
+        self.commands.get(name)
+    }
+
+    /// Argument and result types of every command, for the generated bindings
+    pub fn signatures(&self, declarations: &mut Declarations) -> Vec<(&'static str, CommandSignature)> {
+        self.commands.iter()
+            .map(|(name, command)| (*name, (command.signature)(declarations)))
+            .collect()
+    }
+
+    /// Decodes the arguments, calls the command and resolves the promise of
+    /// the web-app with its result. Without a scope, commands that need the
+    /// window or the event loop are rejected.
+    pub fn dispatch(&self, scope: Option<CommandScope>, invoke: Invoke) {
+        match self.commands.get(invoke.message.command.as_str()) {
+            Some(command) => command.invoke(scope, invoke),
+            None => {
+                let message = format!("command {} not found", invoke.message.command);
+                invoke.resolver.reject(message);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::atomic::{AtomicUsize, Ordering}, time::Duration};
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::{ipc::{CallbackFn, InvokeMessage}, webview::{MemoryWebview, WebviewRecord}};
+
+    fn add(a: u32, b: u32) -> Result<u32, String> {
+        Ok(a + b)
+    }
+
+    fn greet(name: String, greeting: Option<String>) -> Result<String, String> {
+        Ok(format!("{}, {}", greeting.unwrap_or_else(|| "Hello".to_string()), name))
+    }
+
+    fn nothing() -> Result<(), String> {
+        Ok(())
+    }
+
+    fn later(value: u32) -> Deferred<u32> {
+        deferred(async move { Ok::<_, String>(value) })
+    }
+
+    fn ping(_proxy: EventProxy<EventLoopMessage>, value: u32) -> Result<u32, String> {
+        Ok(value)
+    }
+
+    fn registry() -> CommandRegistry {
+        let mut registry = CommandRegistry::new();
+        registry.command("add", add);
+        registry.command("greet", greet);
+        registry.command("nothing", nothing);
+        registry.command("later", later);
+        registry.command("addOnWorker", add).on_worker();
+        registry.context_command("ping", ping);
+        registry
+    }
+
+    /// Pending invokes are tracked by their callback handle, tests running
+    /// at the same time mustn't share one
+    static NEXT_CALLBACK: AtomicUsize = AtomicUsize::new(1);
+
+    fn invoke(command: &str, payload: Value) -> Result<Value, Value> {
+        let webview = MemoryWebview::new();
+        let callback = NEXT_CALLBACK.fetch_add(2, Ordering::SeqCst);
+        registry().dispatch(None, Invoke {
+            message: InvokeMessage { command: command.to_string(), payload },
+            resolver: InvokeResolver { sink: webview.clone(), callback: CallbackFn(callback), error: CallbackFn(callback + 1) }
+        });
+        webview.wait_for_response(callback, Duration::from_secs(10)).expect("no response")
+    }
+
+    fn error(result: Result<Value, Value>) -> String {
+        result.unwrap_err().as_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn arguments_are_positional() {
+        assert_eq!(invoke("add", json!([1, 2])), Ok(json!(3)));
+        assert_eq!(invoke("nothing", json!([])), Ok(Value::Null));
+        assert_eq!(invoke("later", json!([4])), Ok(json!(4)));
+    }
+
+    #[test]
+    fn missing_arguments_decode_like_null() {
+        assert_eq!(invoke("greet", json!(["Ada"])), Ok(json!("Hello, Ada")));
+        assert_eq!(invoke("greet", json!(["Ada", null])), Ok(json!("Hello, Ada")));
+        assert_eq!(invoke("greet", json!(["Ada", "Hi"])), Ok(json!("Hi, Ada")));
+
+        assert_eq!(error(invoke("add", json!([1]))), "invalid type: null, expected u32");
+        assert_eq!(error(invoke("greet", json!([]))), "invalid type: null, expected a string");
+    }
+
+    #[test]
+    fn arguments_of_the_wrong_type_are_rejected() {
+        assert_eq!(error(invoke("add", json!(["1", 2]))), "invalid type: string \"1\", expected u32");
+        assert_eq!(error(invoke("add", json!([1, -2]))), "invalid value: integer `-2`, expected u32");
+        assert_eq!(error(invoke("greet", json!(["Ada", 1]))), "invalid type: integer `1`, expected a string");
+        assert_eq!(error(invoke("addOnWorker", json!([1, true]))), "invalid type: boolean `true`, expected u32");
+        assert_eq!(error(invoke("add", json!({ "a": 1, "b": 2 }))), "payload has to be an array.");
+    }
+
+    #[test]
+    fn extra_arguments_are_ignored() {
+        assert_eq!(invoke("add", json!([1, 2, 3])), Ok(json!(3)));
+        assert_eq!(invoke("nothing", json!([1])), Ok(Value::Null));
+        assert_eq!(invoke("addOnWorker", json!([1, 2, "three"])), Ok(json!(3)));
+    }
+
+    #[test]
+    fn context_commands_need_a_scope() {
+        assert_eq!(error(invoke("ping", json!([1]))), "command needs a window");
+        assert_eq!(error(invoke("missing", json!([]))), "command missing not found");
+    }
+
+    #[test]
+    fn context_is_declared_by_the_first_parameter() {
+        assert_eq!(<Window as FromScope>::CONTEXT, CommandContext::Window);
+        assert_eq!(<EventProxy<EventLoopMessage> as FromScope>::CONTEXT, CommandContext::Proxy);
+
+        let registry = registry();
+        assert_eq!(registry.get("ping").unwrap().context(), CommandContext::Proxy);
+        assert_eq!(registry.get("add").unwrap().context(), CommandContext::None);
+    }
+
+    #[test]
+    #[should_panic(expected = "command add registered twice")]
+    fn commands_are_registered_once() {
+        let mut registry = registry();
+        registry.command("add", add);
+    }
+
+    fn responses(webview: &MemoryWebview, callback: usize) -> usize {
+        webview.records().iter()
+            .filter(|record| matches!(record, WebviewRecord::Response { callback: id, .. } if *id == callback))
+            .count()
+    }
+
+    #[test]
+    fn cancelled_invokes_never_resolve() {
+        let webview = MemoryWebview::new();
+        let callback = NEXT_CALLBACK.fetch_add(2, Ordering::SeqCst);
+        let (completer, response) = workers::oneshot::<InvokeResponse>();
+        let resolver = InvokeResolver { sink: webview.clone(), callback: CallbackFn(callback), error: CallbackFn(callback + 1) };
+        spawn_tracked(resolver, Box::pin(response));
+
+        assert_eq!(cancel_invoke(callback), Ok(true));
+        assert_eq!(webview.wait_for_response(callback, Duration::from_secs(10)), Some(Err(json!("cancelled"))));
+        completer.complete(InvokeResponse::Ok(json!(1)));
+        std::thread::sleep(Duration::from_millis(200));
+        assert_eq!(responses(&webview, callback), 1);
+        assert_eq!(cancel_invoke(callback), Ok(false));
+    }
+
+    #[test]
+    fn finished_invokes_cant_be_cancelled() {
+        let webview = MemoryWebview::new();
+        let callback = NEXT_CALLBACK.fetch_add(2, Ordering::SeqCst);
+        let resolver = InvokeResolver { sink: webview.clone(), callback: CallbackFn(callback), error: CallbackFn(callback + 1) };
+        spawn_tracked(resolver, Box::pin(workers::spawn_blocking(|| InvokeResponse::Ok(json!("done")))));
+
+        assert_eq!(webview.wait_for_response(callback, Duration::from_secs(10)), Some(Ok(json!("done"))));
+        assert_eq!(cancel_invoke(callback), Ok(false));
+        assert_eq!(responses(&webview, callback), 1);
+    }
+
+    #[test]
+    fn worker_commands_resolve_with_their_result() {
+        assert_eq!(invoke("addOnWorker", json!([2, 3])), Ok(json!(5)));
+        assert_eq!(invoke("later", json!([7])), Ok(json!(7)));
+    }
+}
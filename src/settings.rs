@@ -0,0 +1,204 @@
+use std::collections::BTreeMap;
+
+use bson::Bson;
+use serde::{Deserialize, Serialize};
+
+use crate::mnemonic::{OutputEncoding, KeyboardLayout};
+
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Root of `user.db`: named settings profiles and the one currently in use.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct Profiles {
+    pub active_profile: String,
+    pub profiles: BTreeMap<String, Settings>,
+    /// Oldest first, bounded by `history::HISTORY_LIMIT`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub history: Vec<HistoryEntry>,
+}
+
+crate::ts_interface! {
+    /// Previous and new values of every setting changed by a single write
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+    #[serde(rename_all = "camelCase", deny_unknown_fields)]
+    pub struct HistoryEntry {
+        pub id: String,
+        /// Unix timestamp of the write
+        pub timestamp: i64,
+        pub changes: Vec<HistoryChange>,
+    }
+}
+
+crate::ts_interface! {
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+    #[serde(rename_all = "camelCase", deny_unknown_fields)]
+    pub struct HistoryChange {
+        /// Path below the root of `user.db`, e.g. `profiles.default.generalApp.showOnStart`
+        pub path: Vec<String>,
+        /// `None` if the key didn't exist
+        pub old_value: Option<Bson>,
+        pub new_value: Option<Bson>,
+    }
+}
+
+impl Default for Profiles {
+    fn default() -> Self {
+        Self {
+            active_profile: DEFAULT_PROFILE.to_string(),
+            profiles: BTreeMap::from([(DEFAULT_PROFILE.to_string(), Settings::default())]),
+            history: Vec::new(),
+        }
+    }
+}
+
+/// Settings of a single profile. Every key the web-app or the backend may store
+/// has to be declared here, unknown keys and mistyped values are rejected.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct Settings {
+    pub general_app: GeneralApp,
+    pub generate_page: GeneratePage,
+    pub restore_page: RestorePage,
+    pub password_generation_rules: PasswordGenerationRules,
+    pub quiz: QuizSettings,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct GeneralApp {
+    pub show_on_start: bool,
+    pub skip_to_retrieve: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct GeneratePage {
+    pub ask_on_leave: bool,
+}
+
+impl Default for GeneratePage {
+    fn default() -> Self {
+        Self { ask_on_leave: true }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct RestorePage {
+    pub close_on_blur: bool,
+}
+
+impl Default for RestorePage {
+    fn default() -> Self {
+        Self { close_on_blur: true }
+    }
+}
+
+/// Mirrors the `ConfigData` the web-app hands to the derivation commands.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct PasswordGenerationRules {
+    pub characters: bool,
+    pub digits: bool,
+    pub punctuation: bool,
+    pub special: bool,
+    pub length: i32,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<OutputEncoding>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ascii_only: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exclude_ambiguous: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub layout: Option<KeyboardLayout>,
+}
+
+/// Same as `passwordGenerationRulesDefault` of the web-app, which reads the
+/// stored rules and falls back to its own defaults for them.
+impl Default for PasswordGenerationRules {
+    fn default() -> Self {
+        Self {
+            characters: true,
+            digits: true,
+            punctuation: true,
+            special: false,
+            length: 48,
+            encoding: None,
+            version: None,
+            ascii_only: None,
+            exclude_ambiguous: None,
+            layout: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct QuizSettings {
+    /// Review schedules by phrase fingerprint, see `quiz::phrase_key`
+    pub phrases: BTreeMap<String, PhraseSchedule>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct PhraseSchedule {
+    pub interval_days: i64,
+    pub streak: i64,
+    /// Unix timestamp of the next review, `None` until the first quiz is finished
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_review: Option<i64>,
+}
+
+impl Default for PhraseSchedule {
+    fn default() -> Self {
+        Self { interval_days: 1, streak: 0, next_review: None }
+    }
+}
+
+impl Settings {
+    /// Range checks the type system can't express.
+    pub fn validate(&self) -> Result<(), String> {
+        let rules = &self.password_generation_rules;
+        if !(1..=255).contains(&rules.length) {
+            return Err("passwordGenerationRules.length has to be between 1 and 255".to_string());
+        }
+        if !(rules.characters || rules.digits || rules.punctuation || rules.special) {
+            return Err("passwordGenerationRules needs at least one character group".to_string());
+        }
+        for (key, schedule) in self.quiz.phrases.iter() {
+            if schedule.interval_days < 1 {
+                return Err(format!("quiz.phrases.{}.intervalDays has to be at least 1", key));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Profiles {
+    /// The named profile, the active one for `None`
+    pub fn get(&self, profile: Option<&str>) -> Result<&Settings, String> {
+        let name = profile.unwrap_or(&self.active_profile);
+        self.profiles.get(name).ok_or(format!("profile {} doesn't exist", name))
+    }
+
+    pub fn active(&self) -> &Settings {
+        self.get(None).expect("the active profile always exists")
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.profiles.contains_key(&self.active_profile) {
+            return Err(format!("active profile {} doesn't exist", self.active_profile));
+        }
+        for (name, settings) in self.profiles.iter() {
+            if name.is_empty() || name.contains('.') {
+                return Err(format!("{:?} isn't a valid profile name", name));
+            }
+            settings.validate().map_err(|err| format!("profile {}: {}", name, err))?;
+        }
+        Ok(())
+    }
+}
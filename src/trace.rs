@@ -0,0 +1,354 @@
+use std::{
+    collections::HashMap, fs::{self, File, OpenOptions}, io::{LineWriter, Write},
+    sync::Mutex, time::Instant
+};
+
+use lazy_static::lazy_static;
+use serde_json::{json, Map, Value};
+
+use crate::ipc::IpcPayload;
+
+const REDACTED: &str = "[redacted]";
+
+/// Object keys whose values never reach the trace, compared in lowercase
+const SECRET_KEYS: &[&str] = &["phrase", "password", "passphrase"];
+
+struct Tracer {
+    file: LineWriter<File>,
+    started: Instant,
+    /// Requests waiting for a response by callback handle, with when they
+    /// arrived and whether their result is redacted
+    pending: HashMap<usize, (Instant, bool)>
+}
+
+impl Tracer {
+    fn write(&mut self, kind: &str, mut record: Map<String, Value>) {
+        record.insert("atMs".to_string(), json!(self.started.elapsed().as_secs_f64() * 1000.0));
+        record.insert("kind".to_string(), json!(kind));
+        if let Ok(line) = serde_json::to_string(&record) {
+            let _ = writeln!(self.file, "{}", line);
+        }
+    }
+}
+
+lazy_static! {
+    static ref TRACER: Mutex<Option<Tracer>> = Mutex::new(None);
+}
+
+/// Starts appending every IPC request, response and channel message to `path`
+/// as one JSON object per line.
+pub fn start(path: &str) -> Result<(), String> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|err| err.to_string())?;
+    *TRACER.lock().unwrap() = Some(Tracer {
+        file: LineWriter::new(file),
+        started: Instant::now(),
+        pending: HashMap::new()
+    });
+    Ok(())
+}
+
+/// `value` with everything below a secret key replaced
+fn redact(value: &Value) -> Value {
+    match value {
+        Value::Object(object) => Value::Object(object.iter()
+            .map(|(key, value)| {
+                let secret = SECRET_KEYS.iter().any(|secret| key.to_lowercase().contains(secret));
+                (key.clone(), if secret { json!(REDACTED) } else { redact(value) })
+            })
+            .collect()),
+        Value::Array(items) => Value::Array(items.iter().map(redact).collect()),
+        value => value.clone()
+    }
+}
+
+fn record(fields: Value) -> Map<String, Value> {
+    match fields {
+        Value::Object(fields) => fields,
+        _ => Map::new()
+    }
+}
+
+/// Records a request of the web-app. Only the arguments of `traced` commands
+/// are recorded, the result of `sensitive` commands is left out as well.
+pub fn request(payload: &IpcPayload, traced: bool, sensitive: bool) {
+    let mut tracer = TRACER.lock().unwrap();
+    if let Some(tracer) = tracer.as_mut() {
+        tracer.pending.insert(payload.callback.0, (Instant::now(), sensitive));
+        let inner = if traced { redact(&payload.inner) } else { json!(REDACTED) };
+        tracer.write("request", record(json!({
+            "command": payload.command,
+            "callback": payload.callback.0,
+            "error": payload.error.0,
+            "inner": inner
+        })));
+    }
+}
+
+/// Records the response resolving the promise with the `callback` handle.
+pub fn response(callback: usize, result: &Result<Value, Value>) {
+    let mut tracer = TRACER.lock().unwrap();
+    if let Some(tracer) = tracer.as_mut() {
+        let (elapsed, sensitive) = match tracer.pending.remove(&callback) {
+            Some((arrived, sensitive)) => (Some(arrived.elapsed().as_secs_f64() * 1000.0), sensitive),
+            None => (None, false)
+        };
+        let mut fields = record(json!({
+            "callback": callback,
+            "elapsedMs": elapsed
+        }));
+        match result {
+            Ok(_) if sensitive => fields.insert("ok".to_string(), json!(REDACTED)),
+            Ok(value) => fields.insert("ok".to_string(), redact(value)),
+            Err(error) => fields.insert("err".to_string(), redact(error))
+        };
+        tracer.write("response", fields);
+    }
+}
+
+/// Records an event sent to the web-app on the channel `name`.
+pub fn channel(name: &str, event: &Value) {
+    let mut tracer = TRACER.lock().unwrap();
+    if let Some(tracer) = tracer.as_mut() {
+        tracer.write("channel", record(json!({
+            "channel": name,
+            "event": redact(event)
+        })));
+    }
+}
+
+/// A request read back from a trace, with the response it got
+struct RecordedRequest {
+    command: String,
+    inner: Value,
+    response: Option<Result<Value, Value>>,
+    elapsed: Option<f64>
+}
+
+fn read_requests(text: &str) -> Result<Vec<RecordedRequest>, String> {
+    let mut requests: Vec<RecordedRequest> = Vec::new();
+    // callback handles start over with every page load, so a response
+    // belongs to the latest request with its handle
+    let mut open: HashMap<u64, usize> = HashMap::new();
+    for (number, line) in text.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+        let record: Value = serde_json::from_str(line)
+            .map_err(|err| format!("line {}: {}", number + 1, err))?;
+        let callback = record["callback"].as_u64().unwrap_or_default();
+        match record["kind"].as_str() {
+            Some("request") => {
+                open.insert(callback, requests.len());
+                requests.push(RecordedRequest {
+                    command: record["command"].as_str().unwrap_or_default().to_string(),
+                    inner: record["inner"].clone(),
+                    response: None,
+                    elapsed: None
+                });
+            }
+            Some("response") => {
+                if let Some(index) = open.remove(&callback) {
+                    let request = &mut requests[index];
+                    request.elapsed = record["elapsedMs"].as_f64();
+                    request.response = Some(match record.get("err") {
+                        Some(error) => Err(error.clone()),
+                        None => Ok(record["ok"].clone())
+                    });
+                }
+            }
+            _ => ()
+        }
+    }
+    Ok(requests)
+}
+
+fn contains_redacted(value: &Value) -> bool {
+    match value {
+        Value::String(text) => text == REDACTED,
+        Value::Array(items) => items.iter().any(contains_redacted),
+        Value::Object(object) => object.values().any(contains_redacted),
+        _ => false
+    }
+}
+
+/// Outcome of replaying a trace
+pub struct ReplayReport {
+    /// One line per recorded request, followed by the responses that differ
+    pub lines: Vec<String>,
+    /// Whether every replayed response matched the recorded one
+    pub matched: bool
+}
+
+/// Re-runs the requests recorded in the trace at `path` in order through
+/// `run`, and reports where a response differs from the recorded one.
+/// Requests `run` can't handle without a window, or whose arguments were
+/// redacted, are skipped.
+pub fn replay<F>(path: &str, run: F) -> Result<ReplayReport, String>
+where
+    F: Fn(&str, &Value) -> Option<Result<Value, Value>>
+{
+    let text = fs::read_to_string(path).map_err(|err| err.to_string())?;
+    let mut report = ReplayReport { lines: Vec::new(), matched: true };
+    for (index, request) in read_requests(&text)?.iter().enumerate() {
+        let prefix = format!("{:>4} {}", index + 1, request.command);
+        if contains_redacted(&request.inner) {
+            report.lines.push(format!("{}: skipped, arguments were redacted", prefix));
+            continue;
+        }
+
+        let started = Instant::now();
+        let result = match run(&request.command, &request.inner) {
+            Some(result) => result,
+            None => {
+                report.lines.push(format!("{}: skipped, needs a window", prefix));
+                continue;
+            }
+        };
+        let timing = format!(
+            "{:.1} ms, recorded {}",
+            started.elapsed().as_secs_f64() * 1000.0,
+            request.elapsed.map_or("-".to_string(), |elapsed| format!("{:.1} ms", elapsed))
+        );
+        let result = match &result {
+            Ok(value) => Ok(redact(value)),
+            Err(error) => Err(redact(error))
+        };
+
+        match &request.response {
+            Some(recorded) if contains_redacted(&response_value(recorded)) => {
+                report.lines.push(format!("{}: replayed, result was redacted ({})", prefix, timing));
+            }
+            Some(recorded) if recorded == &result => {
+                report.lines.push(format!("{}: same ({})", prefix, timing));
+            }
+            Some(recorded) => {
+                report.matched = false;
+                report.lines.push(format!("{}: differs ({})", prefix, timing));
+                report.lines.push(format!("     recorded: {}", response_value(recorded)));
+                report.lines.push(format!("     replayed: {}", response_value(&result)));
+            }
+            None => report.lines.push(format!("{}: replayed, no recorded response ({})", prefix, timing))
+        }
+    }
+    Ok(report)
+}
+
+fn response_value(response: &Result<Value, Value>) -> Value {
+    match response {
+        Ok(value) => json!({ "ok": value }),
+        Err(error) => json!({ "err": error })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(records: &[Value]) -> String {
+        records.iter().map(|record| record.to_string()).collect::<Vec<_>>().join("\n")
+    }
+
+    #[test]
+    fn secrets_are_redacted_at_any_depth() {
+        let value = json!({
+            "length": 12,
+            "newPassword": "hunter2",
+            "items": [{ "Phrase": ["a", "b"] }, "phrase"],
+            "nested": { "passphraseHint": { "text": "x" }, "digits": true }
+        });
+        assert_eq!(redact(&value), json!({
+            "length": 12,
+            "newPassword": REDACTED,
+            "items": [{ "Phrase": REDACTED }, "phrase"],
+            "nested": { "passphraseHint": REDACTED, "digits": true }
+        }));
+        assert_eq!(redact(&json!(["phrase", 1])), json!(["phrase", 1]));
+    }
+
+    #[test]
+    fn responses_belong_to_the_latest_request_with_their_handle() {
+        let text = lines(&[
+            json!({ "kind": "request", "command": "first", "callback": 0, "inner": [] }),
+            json!({ "kind": "response", "callback": 0, "ok": 1, "elapsedMs": 2.0 }),
+            // the page was loaded again and the handles start over
+            json!({ "kind": "channel", "channel": "ui-events", "event": {} }),
+            json!({ "kind": "request", "command": "lost", "callback": 2, "inner": [] }),
+            json!({ "kind": "request", "command": "second", "callback": 0, "inner": [1] }),
+            json!({ "kind": "request", "command": "third", "callback": 2, "inner": [] }),
+            json!({ "kind": "response", "callback": 2, "err": "failed" }),
+            json!({ "kind": "response", "callback": 0, "ok": 2 }),
+            json!({ "kind": "response", "callback": 0, "ok": 3 })
+        ]);
+        let requests = read_requests(&text).unwrap();
+        let summary: Vec<(&str, Option<Result<Value, Value>>)> = requests.iter()
+            .map(|request| (request.command.as_str(), request.response.clone()))
+            .collect();
+        assert_eq!(summary, vec![
+            ("first", Some(Ok(json!(1)))),
+            ("lost", None),
+            ("second", Some(Ok(json!(2)))),
+            ("third", Some(Err(json!("failed"))))
+        ]);
+        assert_eq!(requests[0].elapsed, Some(2.0));
+        assert_eq!(requests[2].inner, json!([1]));
+    }
+
+    #[test]
+    fn malformed_lines_are_reported() {
+        let error = read_requests("{\"kind\": \"request\"}\n\nnot json").err().unwrap();
+        assert!(error.starts_with("line 3: "), "{}", error);
+    }
+
+    #[test]
+    fn replay_compares_the_responses() {
+        let path = std::env::temp_dir().join(format!("mnemonic-trace-{}.jsonl", uuid::Uuid::new_v4()));
+        fs::write(&path, lines(&[
+            json!({ "kind": "request", "command": "same", "callback": 0, "inner": [] }),
+            json!({ "kind": "response", "callback": 0, "ok": 1 }),
+            json!({ "kind": "request", "command": "differs", "callback": 2, "inner": [] }),
+            json!({ "kind": "response", "callback": 2, "ok": 1 }),
+            json!({ "kind": "request", "command": "windowClose", "callback": 4, "inner": [] }),
+            json!({ "kind": "request", "command": "sensitive", "callback": 6, "inner": REDACTED }),
+            json!({ "kind": "request", "command": "hidden", "callback": 8, "inner": [] }),
+            json!({ "kind": "response", "callback": 8, "ok": REDACTED }),
+            json!({ "kind": "request", "command": "unanswered", "callback": 10, "inner": [] })
+        ])).unwrap();
+
+        let report = replay(path.to_str().unwrap(), |command, _| match command {
+            "windowClose" => None,
+            "differs" => Some(Err(json!("failed"))),
+            "sensitive" => panic!("redacted arguments are replayed"),
+            _ => Some(Ok(json!(1)))
+        }).unwrap();
+        let _ = fs::remove_file(&path);
+
+        assert!(!report.matched);
+        let lines: Vec<&str> = report.lines.iter().map(|line| line.as_str()).collect();
+        assert!(lines[0].starts_with("   1 same: same ("), "{}", lines[0]);
+        assert!(lines[1].starts_with("   2 differs: differs ("), "{}", lines[1]);
+        assert_eq!(lines[2], "     recorded: {\"ok\":1}");
+        assert_eq!(lines[3], "     replayed: {\"err\":\"failed\"}");
+        assert_eq!(lines[4], "   3 windowClose: skipped, needs a window");
+        assert_eq!(lines[5], "   4 sensitive: skipped, arguments were redacted");
+        assert!(lines[6].starts_with("   5 hidden: replayed, result was redacted ("), "{}", lines[6]);
+        assert!(lines[7].starts_with("   6 unanswered: replayed, no recorded response ("), "{}", lines[7]);
+        assert_eq!(lines.len(), 8);
+    }
+
+    #[test]
+    fn matching_trace_replays_headlessly() {
+        let _directory = crate::config::test_directory();
+        let path = std::env::temp_dir().join(format!("mnemonic-trace-{}.jsonl", uuid::Uuid::new_v4()));
+        fs::write(&path, lines(&[
+            json!({ "kind": "request", "command": "configIsReadOnly", "callback": 0, "inner": [] }),
+            json!({ "kind": "response", "callback": 0, "ok": false }),
+            json!({ "kind": "request", "command": "windowClose", "callback": 2, "inner": [] })
+        ])).unwrap();
+
+        let report = replay(path.to_str().unwrap(), crate::handler::replay_command).unwrap();
+        let _ = fs::remove_file(&path);
+        assert!(report.matched, "{:?}", report.lines);
+        assert_eq!(report.lines[1], "   2 windowClose: skipped, needs a window");
+    }
+}
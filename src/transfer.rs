@@ -0,0 +1,358 @@
+use bson::Bson;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    config, overrides,
+    settings::Settings
+};
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferFormat {
+    Json,
+    Toml
+}
+
+crate::ts_string_enum!(TransferFormat, ["json", "toml"]);
+
+impl TransferFormat {
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        match name.to_lowercase().as_str() {
+            "json" => Ok(Self::Json),
+            "toml" => Ok(Self::Toml),
+            _ => Err(format!("Unknown settings format {}, use json or toml", name))
+        }
+    }
+
+    /// Format implied by a file extension, json for anything else
+    pub fn from_path(path: &str) -> Self {
+        if path.to_lowercase().ends_with(".toml") { Self::Toml } else { Self::Json }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportMode {
+    /// Keys missing from the import keep their current value
+    Merge,
+    /// Keys missing from the import are reset to their defaults
+    Replace
+}
+
+crate::ts_string_enum!(ImportMode, ["merge", "replace"]);
+
+crate::ts_interface! {
+    #[derive(Serialize, Debug, Clone)]
+    #[serde(rename_all = "camelCase")]
+    pub struct SettingsChange {
+        pub path: Vec<String>,
+        pub old_value: Option<Value>,
+        pub new_value: Option<Value>,
+    }
+}
+
+/// TOML has no null, unset optional settings are left out instead
+fn strip_nulls(value: Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .filter(|(_, v)| !v.is_null())
+                .map(|(k, v)| (k, strip_nulls(v)))
+                .collect()
+        ),
+        Value::Array(values) => Value::Array(values.into_iter().map(strip_nulls).collect()),
+        value => value
+    }
+}
+
+/// Serializes the settings below `path`, the whole document if it's empty.
+pub fn export_settings(
+    path: &Vec<String>,
+    format: TransferFormat,
+    profile: Option<&str>
+) -> Result<String, String> {
+    let document = config::settings_to_document(&config::load_stored_settings(profile)?);
+    let value = config::get_prop_from_path(path, document)
+        .ok_or(format!("{} doesn't exist", path.join(".")))?
+        .into_relaxed_extjson();
+
+    match format {
+        TransferFormat::Json => serde_json::to_string_pretty(&value).map_err(|err| err.to_string()),
+        TransferFormat::Toml => {
+            if !value.is_object() {
+                return Err("TOML can only export a section, not a single value".to_string());
+            }
+            // toml::Value orders plain values before tables, as TOML requires
+            toml::Value::try_from(strip_nulls(value))
+                .and_then(|value| toml::to_string_pretty(&value))
+                .map_err(|err| err.to_string())
+        }
+    }
+}
+
+fn parse_import(text: &str, format: TransferFormat) -> Result<Bson, String> {
+    let value: Value = match format {
+        TransferFormat::Json => serde_json::from_str(text).map_err(|err| err.to_string())?,
+        TransferFormat::Toml => toml::from_str(text).map_err(|err| err.to_string())?
+    };
+    bson::to_bson(&value).map_err(|err| err.to_string())
+}
+
+/// Applies an import to `current` without saving it. The returned settings
+/// are validated, the changes list every leaf that differs.
+fn plan_import(
+    current: &Settings,
+    path: &Vec<String>,
+    imported: Bson,
+    mode: ImportMode
+) -> Result<(Settings, Vec<SettingsChange>), String> {
+    let old_document = config::settings_to_document(current);
+    let mut new_document = old_document.clone();
+
+    match path.split_last() {
+        None => {
+            let imported = match imported {
+                Bson::Document(document) => document,
+                _ => return Err("Imported settings have to be an object".to_string())
+            };
+            match mode {
+                ImportMode::Merge => config::merge_documents(&mut new_document, imported),
+                ImportMode::Replace => new_document = imported
+            }
+        }
+        Some(_) => {
+            let merge = mode == ImportMode::Merge
+                && matches!(config::get_prop_from_path(path, new_document.clone()), Some(Bson::Document(_)));
+            match imported {
+                Bson::Document(imported) if merge => {
+                    let mut root = Bson::Document(new_document);
+                    if let Ok(Bson::Document(existing)) = config::iter_prop_tree_mut(path, &mut root) {
+                        config::merge_documents(existing, imported);
+                    }
+                    if let Bson::Document(root) = root {
+                        new_document = root;
+                    }
+                }
+                imported => config::set_prop_at_path(path, &mut new_document, imported)?
+            }
+        }
+    }
+
+    let settings = config::settings_from_document(new_document)?;
+    let new_document = config::settings_to_document(&settings);
+
+    let mut changed = Vec::new();
+    config::diff_documents(&old_document, &new_document, &mut Vec::new(), &mut changed);
+    let changes = changed.into_iter()
+        .map(|path| SettingsChange {
+            old_value: config::get_prop_from_path(&path, old_document.clone())
+                .map(|v| v.into_relaxed_extjson()),
+            new_value: config::get_prop_from_path(&path, new_document.clone())
+                .map(|v| v.into_relaxed_extjson()),
+            path,
+        })
+        .collect::<Vec<SettingsChange>>();
+    // a dry run fails like the import itself would
+    for change in changes.iter() {
+        overrides::check_writable(&change.path)?;
+    }
+
+    Ok((settings, changes))
+}
+
+/// Validates and applies `text` at `path`. With `dry_run` only the changes
+/// that would be made are returned.
+pub fn import_settings(
+    text: &str,
+    format: TransferFormat,
+    path: &Vec<String>,
+    mode: ImportMode,
+    dry_run: bool,
+    profile: Option<&str>
+) -> Result<Vec<SettingsChange>, String> {
+    overrides::check_writable(path)?;
+    let imported = parse_import(text, format)?;
+
+    if dry_run {
+        return plan_import(&config::load_stored_settings(profile)?, path, imported, mode)
+            .map(|(_, changes)| changes);
+    }
+
+    let mut changes = Vec::new();
+    config::replace_settings(profile, |current| {
+        let (settings, planned) = plan_import(current, path, imported, mode)?;
+        changes = planned;
+        Ok(settings)
+    })?;
+    Ok(changes)
+}
+
+/// One `path: old -> new` line per change, as `--dry-run` prints them
+pub fn format_changes(changes: &[SettingsChange]) -> Vec<String> {
+    if changes.is_empty() {
+        return vec!["No changes".to_string()];
+    }
+    changes.iter()
+        .map(|change| format!(
+            "{}: {} -> {}",
+            change.path.join("."),
+            change.old_value.as_ref().map_or("(unset)".to_string(), |v| v.to_string()),
+            change.new_value.as_ref().map_or("(unset)".to_string(), |v| v.to_string())
+        ))
+        .collect()
+}
+
+pub fn config_export_settings(
+    path: Vec<String>,
+    format: TransferFormat,
+    profile: Option<String>
+) -> Result<String, String> {
+    let path = config::strip_global_config(path)?;
+    export_settings(&path, format, profile.as_deref())
+}
+
+pub fn config_import_settings(
+    text: String,
+    format: TransferFormat,
+    path: Vec<String>,
+    mode: ImportMode,
+    dry_run: bool,
+    profile: Option<String>
+) -> Result<Vec<SettingsChange>, String> {
+    let path = config::strip_global_config(path)?;
+    import_settings(&text, format, &path, mode, dry_run, profile.as_deref())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(dotted_path: &str) -> Vec<String> {
+        if dotted_path.is_empty() {
+            return Vec::new();
+        }
+        dotted_path.split('.').map(|s| s.to_string()).collect()
+    }
+
+    fn set(dotted_path: &str, value: Bson) {
+        let mut path = path(dotted_path);
+        path.insert(0, "globalConfig".to_string());
+        config::js_config_set_property(path, value, None).unwrap();
+    }
+
+    fn stored() -> Settings {
+        config::load_stored_settings(None).unwrap()
+    }
+
+    fn import(text: &str, format: TransferFormat, dotted_path: &str, mode: ImportMode, dry_run: bool) -> Result<Vec<String>, String> {
+        import_settings(text, format, &path(dotted_path), mode, dry_run, None)
+            .map(|changes| format_changes(&changes))
+    }
+
+    #[test]
+    fn merge_keeps_the_keys_missing_from_the_import() {
+        let _directory = config::test_directory();
+        set("generalApp.showOnStart", Bson::Boolean(true));
+
+        let text = r#"{ "generatePage": { "askOnLeave": false } }"#;
+        import(text, TransferFormat::Json, "", ImportMode::Merge, false).unwrap();
+        let settings = stored();
+        assert!(settings.general_app.show_on_start);
+        assert!(!settings.generate_page.ask_on_leave);
+    }
+
+    #[test]
+    fn replace_resets_the_keys_missing_from_the_import() {
+        let _directory = config::test_directory();
+        set("generalApp.showOnStart", Bson::Boolean(true));
+
+        let text = r#"{ "generatePage": { "askOnLeave": false } }"#;
+        let lines = import(text, TransferFormat::Json, "", ImportMode::Replace, false).unwrap();
+        assert_eq!(lines, vec![
+            "generalApp.showOnStart: true -> false".to_string(),
+            "generatePage.askOnLeave: true -> false".to_string()
+        ]);
+        let settings = stored();
+        assert!(!settings.general_app.show_on_start);
+        assert!(!settings.generate_page.ask_on_leave);
+    }
+
+    #[test]
+    fn path_scopes_the_import() {
+        let _directory = config::test_directory();
+        set("generalApp.showOnStart", Bson::Boolean(true));
+        set("generatePage.askOnLeave", Bson::Boolean(false));
+
+        let text = r#"{ "skipToRetrieve": true }"#;
+        import(text, TransferFormat::Json, "generalApp", ImportMode::Merge, false).unwrap();
+        assert!(stored().general_app.show_on_start);
+        assert!(stored().general_app.skip_to_retrieve);
+
+        // replacing the section leaves the others alone
+        import(text, TransferFormat::Json, "generalApp", ImportMode::Replace, false).unwrap();
+        assert!(!stored().general_app.show_on_start);
+        assert!(stored().general_app.skip_to_retrieve);
+        assert!(!stored().generate_page.ask_on_leave);
+
+        // a single value
+        import("true", TransferFormat::Json, "generalApp.showOnStart", ImportMode::Merge, false).unwrap();
+        assert!(stored().general_app.show_on_start);
+
+        assert!(import(text, TransferFormat::Json, "noSuchSection", ImportMode::Merge, false).is_err());
+        assert!(import("1", TransferFormat::Json, "", ImportMode::Merge, false).is_err());
+    }
+
+    #[test]
+    fn dry_run_lists_the_changes_without_applying_them() {
+        let _directory = config::test_directory();
+        let before = stored();
+
+        let text = "[generalApp]\nshowOnStart = true\n\n[generatePage]\naskOnLeave = true\n";
+        let lines = import(text, TransferFormat::Toml, "", ImportMode::Merge, true).unwrap();
+        assert_eq!(lines, vec!["generalApp.showOnStart: false -> true".to_string()]);
+        assert_eq!(stored(), before);
+
+        let lines = import("{}", TransferFormat::Json, "", ImportMode::Merge, true).unwrap();
+        assert_eq!(lines, vec!["No changes".to_string()]);
+        assert!(import("{ \"generalApp\": { \"unknown\": 1 } }", TransferFormat::Json, "", ImportMode::Merge, true).is_err());
+    }
+
+    fn customize() {
+        set("generalApp.showOnStart", Bson::Boolean(true));
+        set("passwordGenerationRules.length", Bson::Int32(20));
+        set("quiz.phrases.abc", Bson::Document(bson::doc! { "intervalDays": 4, "nextReview": 1_700_000_000_i64 }));
+    }
+
+    fn round_trip(format: TransferFormat) {
+        let _directory = config::test_directory();
+        customize();
+        let exported = export_settings(&Vec::new(), format, None).unwrap();
+        let customized = stored();
+
+        config::js_config_delete_property(vec!["globalConfig".to_string()], None).unwrap();
+        assert_eq!(stored(), Settings::default());
+
+        import(&exported, format, "", ImportMode::Replace, false).unwrap();
+        assert_eq!(stored(), customized);
+    }
+
+    #[test]
+    fn json_round_trip() {
+        round_trip(TransferFormat::Json);
+    }
+
+    #[test]
+    fn toml_round_trip() {
+        round_trip(TransferFormat::Toml);
+    }
+
+    #[test]
+    fn toml_exports_only_sections() {
+        let _directory = config::test_directory();
+        let section = export_settings(&path("generalApp"), TransferFormat::Toml, None).unwrap();
+        assert_eq!(section, "showOnStart = false\nskipToRetrieve = false\n");
+        assert!(export_settings(&path("generalApp.showOnStart"), TransferFormat::Toml, None).is_err());
+        assert_eq!(export_settings(&path("generalApp.showOnStart"), TransferFormat::Json, None), Ok("false".to_string()));
+    }
+}
@@ -0,0 +1,384 @@
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes256Gcm, Nonce
+};
+use bson::{Bson, Document, Binary, spec::BinarySubtype};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::sync::{Mutex, atomic::{AtomicBool, Ordering}};
+
+use crate::{
+    bindings::{Declarations, TypeScript},
+    config::{self, SCHEMA_VERSION_KEY},
+    mnemonic
+};
+
+/// Top level keys that stay readable while the settings are locked
+const BOOTSTRAP_KEYS: &[&str] = &["activeProfile"];
+/// Keys of every profile that stay readable while the settings are locked
+const BOOTSTRAP_PROFILE_KEYS: &[&str] = &["generalApp"];
+
+const BOOTSTRAP_KEY: &str = "bootstrap";
+const SEALED_KEY: &str = "sealed";
+
+/// Stored with the sealed data, envelopes with any other cipher are rejected
+const CIPHER: &str = "aes-256-gcm";
+const NONCE_LENGTH: usize = 12;
+
+#[derive(Deserialize, Debug, Clone)]
+pub enum UnlockSecret {
+    Phrase(Vec<String>),
+    Passphrase(String)
+}
+
+impl TypeScript for UnlockSecret {
+    fn ts_type(declarations: &mut Declarations) -> String {
+        declarations.declare("UnlockSecret", |declarations| format!(
+            "{{ Phrase: {} }} | {{ Passphrase: {} }}",
+            Vec::<String>::ts_type(declarations),
+            String::ts_type(declarations)
+        ))
+    }
+}
+
+impl UnlockSecret {
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::Phrase(_) => "phrase",
+            Self::Passphrase(_) => "passphrase"
+        }
+    }
+
+    fn derive_key(&self, salt: &[u8]) -> Result<Vec<u8>, String> {
+        match self {
+            Self::Phrase(phrase) => mnemonic::derive_store_key(self.kind(), phrase, salt),
+            Self::Passphrase(passphrase) => mnemonic::derive_store_key(self.kind(), passphrase, salt)
+        }
+    }
+}
+
+struct VaultKey {
+    kind: &'static str,
+    salt: Vec<u8>,
+    key: Vec<u8>
+}
+
+lazy_static! {
+    static ref KEY: Mutex<Option<VaultKey>> = Mutex::new(None);
+}
+
+/// Whether `user.db` holds a sealed document, learned when it is opened
+static ENCRYPTED: AtomicBool = AtomicBool::new(false);
+
+crate::ts_interface! {
+    #[derive(Serialize)]
+    pub struct VaultStatus {
+        encrypted: bool,
+        locked: bool
+    }
+}
+
+fn binary(bytes: Vec<u8>) -> Bson {
+    Bson::Binary(Binary { subtype: BinarySubtype::Generic, bytes })
+}
+
+fn random_salt() -> Vec<u8> {
+    uuid::Uuid::new_v4().as_bytes().to_vec()
+}
+
+pub fn is_locked() -> bool {
+    ENCRYPTED.load(Ordering::SeqCst) && KEY.lock().unwrap().is_none()
+}
+
+/// The key derivation and its salt are authenticated along with the data, so
+/// neither can be swapped without unsealing failing.
+fn associated_data(kind: &str, salt: &[u8]) -> Vec<u8> {
+    let mut data = format!("mnemonic-settings:{}:", kind).into_bytes();
+    data.extend_from_slice(salt);
+    data
+}
+
+fn cipher(key: &[u8]) -> Result<Aes256Gcm, String> {
+    // the derived key is 64 bytes long, AES-256 takes the first half
+    Aes256Gcm::new_from_slice(&key[..32]).map_err(|err| err.to_string())
+}
+
+/// Returns the random nonce followed by the ciphertext and its tag.
+fn seal(key: &[u8], plaintext: &[u8], associated_data: &[u8]) -> Result<Vec<u8>, String> {
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher(key)?
+        .encrypt(&nonce, Payload { msg: plaintext, aad: associated_data })
+        .map_err(|_| "Couldn't seal the settings".to_string())?;
+    let mut blob = nonce.to_vec();
+    blob.extend(ciphertext);
+    Ok(blob)
+}
+
+fn unseal(key: &[u8], blob: &[u8], associated_data: &[u8]) -> Result<Vec<u8>, String> {
+    if blob.len() < NONCE_LENGTH {
+        return Err("SealError: Wrong key or damaged settings".to_string());
+    }
+    let (nonce, ciphertext) = blob.split_at(NONCE_LENGTH);
+    cipher(key)?
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: associated_data })
+        .map_err(|_| "SealError: Wrong key or damaged settings".to_string())
+}
+
+fn unseal_document(key: &[u8], sealed: &Document) -> Result<Document, String> {
+    let data = sealed.get_binary_generic("data").map_err(|err| err.to_string())?;
+    if sealed.get_str("cipher").ok() != Some(CIPHER) {
+        return Err("Settings are sealed with an unknown cipher".to_string());
+    }
+    let kind = sealed.get_str("kdf").map_err(|err| err.to_string())?;
+    let salt = sealed.get_binary_generic("salt").map_err(|err| err.to_string())?;
+    let plaintext = unseal(key, data, &associated_data(kind, salt))?;
+    Document::from_reader(plaintext.as_slice()).map_err(|err| err.to_string())
+}
+
+/// Whether `document`, as read from disk, holds sealed settings
+pub fn is_sealed(document: &Document) -> bool {
+    document.contains_key(SEALED_KEY)
+}
+
+/// Turns a document read from disk into the plain settings document. While
+/// locked only the bootstrap keys are returned.
+pub fn open_document(document: Document) -> Document {
+    let sealed = match document.get_document(SEALED_KEY) {
+        Ok(sealed) => sealed.clone(),
+        Err(_) => {
+            ENCRYPTED.store(false, Ordering::SeqCst);
+            return document;
+        }
+    };
+    ENCRYPTED.store(true, Ordering::SeqCst);
+
+    let mut opened = document.get_document(BOOTSTRAP_KEY).cloned().unwrap_or_default();
+    if let Some(version) = document.get(SCHEMA_VERSION_KEY) {
+        opened.insert(SCHEMA_VERSION_KEY, version.clone());
+    }
+
+    let mut key = KEY.lock().unwrap();
+    let inner = key.as_ref().map(|k| unseal_document(&k.key, &sealed));
+    match inner {
+        Some(Ok(inner)) => {
+            config::merge_documents(&mut opened, inner);
+        }
+        Some(Err(_)) => {
+            // replaced by a document sealed with another secret
+            *key = None;
+        }
+        None => ()
+    }
+    opened
+}
+
+/// Counterpart of `open_document`, applied right before a document is written.
+pub fn seal_document(mut document: Document) -> Result<Document, String> {
+    let key = KEY.lock().unwrap();
+    let key = match key.as_ref() {
+        Some(key) => key,
+        None if ENCRYPTED.load(Ordering::SeqCst) => {
+            return Err("Settings are locked".to_string());
+        }
+        None => return Ok(document)
+    };
+
+    let mut sealed = Document::new();
+    if let Some(version) = document.remove(SCHEMA_VERSION_KEY) {
+        sealed.insert(SCHEMA_VERSION_KEY, version);
+    }
+    let mut bootstrap = Document::new();
+    for name in BOOTSTRAP_KEYS {
+        if let Some(value) = document.remove(*name) {
+            bootstrap.insert(*name, value);
+        }
+    }
+    if let Ok(profiles) = document.get_document_mut("profiles") {
+        let mut bootstrap_profiles = Document::new();
+        for (profile, settings) in profiles.iter_mut() {
+            let mut bootstrap_settings = Document::new();
+            if let Bson::Document(settings) = settings {
+                for name in BOOTSTRAP_PROFILE_KEYS {
+                    if let Some(value) = settings.remove(*name) {
+                        bootstrap_settings.insert(*name, value);
+                    }
+                }
+            }
+            bootstrap_profiles.insert(profile.clone(), bootstrap_settings);
+        }
+        bootstrap.insert("profiles", bootstrap_profiles);
+    }
+
+    let mut plaintext = Vec::new();
+    document.to_writer(&mut plaintext).map_err(|err| err.to_string())?;
+    let data = seal(&key.key, &plaintext, &associated_data(key.kind, &key.salt))?;
+
+    let mut envelope = Document::new();
+    envelope.insert("cipher", CIPHER);
+    envelope.insert("kdf", key.kind);
+    envelope.insert("salt", binary(key.salt.clone()));
+    envelope.insert("data", binary(data));
+
+    sealed.insert(BOOTSTRAP_KEY, bootstrap);
+    sealed.insert(SEALED_KEY, envelope);
+    ENCRYPTED.store(true, Ordering::SeqCst);
+    Ok(sealed)
+}
+
+pub fn config_unlock(secret: UnlockSecret) -> Result<(), String> {
+    let document = config::read_raw_document()?;
+    let sealed = document.get_document(SEALED_KEY)
+        .map_err(|_| "Settings aren't encrypted".to_string())?;
+
+    let kind = sealed.get_str("kdf").map_err(|err| err.to_string())?;
+    if kind != secret.kind() {
+        return Err(format!("Settings have to be unlocked with a {}", kind));
+    }
+    let salt = sealed.get_binary_generic("salt").map_err(|err| err.to_string())?.clone();
+    let key = secret.derive_key(&salt)?;
+
+    // fails on a wrong secret before anything is replaced
+    unseal_document(&key, sealed)?;
+
+    *KEY.lock().unwrap() = Some(VaultKey { kind: secret.kind(), salt, key });
+    config::reopen();
+    Ok(())
+}
+
+pub fn config_enable_encryption(secret: UnlockSecret) -> Result<(), String> {
+    if is_locked() {
+        return Err("Settings are locked".to_string());
+    }
+    let salt = random_salt();
+    let key = secret.derive_key(&salt)?;
+
+    let previous = KEY.lock().unwrap().replace(VaultKey { kind: secret.kind(), salt, key });
+    config::rewrite().map_err(|err| {
+        *KEY.lock().unwrap() = previous;
+        err
+    })?;
+    // backups written before still hold the settings in plaintext
+    config::seal_plaintext_copies()
+}
+
+pub fn config_disable_encryption() -> Result<(), String> {
+    if is_locked() {
+        return Err("Settings are locked".to_string());
+    }
+    let previous = KEY.lock().unwrap().take();
+    ENCRYPTED.store(false, Ordering::SeqCst);
+    config::rewrite().map_err(|err| {
+        *KEY.lock().unwrap() = previous;
+        ENCRYPTED.store(true, Ordering::SeqCst);
+        err
+    })
+}
+
+pub fn config_lock() -> Result<(), String> {
+    if !ENCRYPTED.load(Ordering::SeqCst) {
+        return Err("Settings aren't encrypted".to_string());
+    }
+    config::flush()?;
+    *KEY.lock().unwrap() = None;
+    config::reopen();
+    Ok(())
+}
+
+pub fn config_encryption_status() -> Result<VaultStatus, String> {
+    let _ = config::load_settings();
+    Ok(VaultStatus {
+        encrypted: ENCRYPTED.load(Ordering::SeqCst),
+        locked: is_locked()
+    })
+}
+
+/// Forgets the key and whether the settings are sealed, see `config::test_directory`.
+#[cfg(test)]
+pub(crate) fn reset() {
+    *KEY.lock().unwrap_or_else(|err| err.into_inner()) = None;
+    ENCRYPTED.store(false, Ordering::SeqCst);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn passphrase() -> UnlockSecret {
+        UnlockSecret::Passphrase("correct horse battery staple".to_string())
+    }
+
+    #[test]
+    fn locking_evicts_the_sealed_settings() {
+        let _directory = config::test_directory();
+        config::update_settings(|settings| {
+            settings.general_app.show_on_start = true;
+            settings.restore_page.close_on_blur = false;
+        }).unwrap();
+        config_enable_encryption(passphrase()).unwrap();
+
+        config_lock().unwrap();
+        assert!(is_locked());
+        assert!(config::load_stored_settings(None).is_err());
+        let settings = config::load_settings();
+        // generalApp is a bootstrap key, restorePage is only in the sealed part
+        assert!(settings.general_app.show_on_start);
+        assert!(settings.restore_page.close_on_blur);
+
+        config_unlock(passphrase()).unwrap();
+        assert!(!config::load_settings().restore_page.close_on_blur);
+    }
+
+    #[test]
+    fn wrong_secret_keeps_the_settings_locked() {
+        let _directory = config::test_directory();
+        config_enable_encryption(passphrase()).unwrap();
+        config_lock().unwrap();
+
+        let wrong = UnlockSecret::Passphrase("wrong".to_string());
+        assert!(config_unlock(wrong).is_err());
+        assert!(is_locked());
+        assert!(config_unlock(UnlockSecret::Phrase(Vec::new())).is_err());
+    }
+
+    #[test]
+    fn envelopes_without_the_cipher_are_rejected() {
+        let _directory = config::test_directory();
+        config_enable_encryption(passphrase()).unwrap();
+        let mut raw = config::read_raw_document().unwrap();
+        let sealed = raw.get_document(SEALED_KEY).unwrap().clone();
+        let key = passphrase().derive_key(sealed.get_binary_generic("salt").unwrap()).unwrap();
+        assert!(unseal_document(&key, &sealed).is_ok());
+
+        let mut legacy = sealed.clone();
+        legacy.remove("cipher");
+        assert!(unseal_document(&key, &legacy).is_err());
+        let mut unknown = sealed;
+        unknown.insert("cipher", "xor");
+        assert!(unseal_document(&key, &unknown).is_err());
+
+        raw.insert(SEALED_KEY, legacy);
+        assert!(open_document(raw).get_document("profiles").unwrap()
+            .get_document(crate::settings::DEFAULT_PROFILE).unwrap()
+            .get_document("restorePage").is_err());
+    }
+
+    #[test]
+    fn sealed_file_has_no_plaintext_settings() {
+        let directory = config::test_directory();
+        config::update_settings(|settings| settings.restore_page.close_on_blur = false).unwrap();
+        config_enable_encryption(passphrase()).unwrap();
+
+        let raw = config::read_raw_document().unwrap();
+        assert!(is_sealed(&raw));
+        let bootstrap = raw.get_document(BOOTSTRAP_KEY).unwrap();
+        assert!(bootstrap.get_document("profiles").unwrap()
+            .get_document(crate::settings::DEFAULT_PROFILE).unwrap()
+            .get_document("restorePage").is_err());
+        // the flush thread may have written a plaintext generation before
+        let backup = directory.user_db().with_extension("db.1");
+        if backup.exists() {
+            let backup = Document::from_reader(std::fs::File::open(backup).unwrap()).unwrap();
+            assert!(is_sealed(&backup));
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use std::{sync::{Arc, Condvar, Mutex}, time::{Duration, Instant}};
+
+use serde_json::Value;
+
+use crate::ipc::CallbackFn;
+
+/// Where responses and channel events for the web-app go. The webview
+/// evaluates them as scripts, `MemoryWebview` keeps them.
+pub trait ResponseSink: Send + Sync {
+    /// Resolves the promise registered under `callback` or rejects it through `error`.
+    fn respond(&self, result: Result<Value, Value>, callback: CallbackFn, error: CallbackFn);
+
+    /// Hands `event` to the channel listeners of the web-app.
+    fn channel_event(&self, event: Value);
+}
+
+pub type Sink = Arc<dyn ResponseSink>;
+
+/// The window around the web-app, as far as the event loop drives it
+pub trait WindowShell {
+    fn show(&self);
+    fn hide(&self);
+    fn close(&self);
+    fn minimize(&self);
+    fn is_visible(&self) -> bool;
+    /// Focuses the window and flashes it in the taskbar
+    fn request_attention(&self);
+    fn show_sys_menu(&self, x: i32, y: i32);
+    /// Shows a notification outside the window that opens it when clicked
+    fn notify(&self, title: &str, message: &str);
+    /// Lets the web-app know the user wants to close the window
+    fn notify_close_requested(&self);
+    /// Sink for the channels the web-app establishes in this window
+    fn sink(&self) -> Sink;
+}
+
+/// Everything a `MemoryWebview` was asked to do, in order
+#[derive(Debug, Clone, PartialEq)]
+pub enum WebviewRecord {
+    Response { callback: usize, result: Result<Value, Value> },
+    ChannelEvent(Value),
+    Shown,
+    Hidden,
+    Closed,
+    Minimized,
+    AttentionRequested,
+    SysMenu { x: i32, y: i32 },
+    Notified { title: String, message: String },
+    CloseRequested
+}
+
+#[derive(Default)]
+struct MemoryState {
+    records: Vec<WebviewRecord>,
+    visible: bool
+}
+
+/// A webview without a window that records responses, channel events and
+/// window changes, for driving the IPC layer and the event loop headlessly.
+#[derive(Default)]
+pub struct MemoryWebview {
+    state: Mutex<MemoryState>,
+    changed: Condvar
+}
+
+impl MemoryWebview {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    fn record(&self, record: WebviewRecord) {
+        let mut state = self.state.lock().unwrap();
+        match record {
+            WebviewRecord::Shown => state.visible = true,
+            WebviewRecord::Hidden | WebviewRecord::Closed | WebviewRecord::Minimized => state.visible = false,
+            _ => ()
+        }
+        state.records.push(record);
+        self.changed.notify_all();
+    }
+
+    #[cfg(test)]
+    pub fn records(&self) -> Vec<WebviewRecord> {
+        self.state.lock().unwrap().records.clone()
+    }
+
+    /// Returns the records so far and starts over.
+    #[cfg(test)]
+    pub fn take_records(&self) -> Vec<WebviewRecord> {
+        std::mem::take(&mut self.state.lock().unwrap().records)
+    }
+
+    /// Events sent on any channel, accept and close included
+    #[cfg(test)]
+    pub fn channel_events(&self) -> Vec<Value> {
+        self.state.lock().unwrap().records.iter()
+            .filter_map(|record| match record {
+                WebviewRecord::ChannelEvent(event) => Some(event.clone()),
+                _ => None
+            })
+            .collect()
+    }
+
+    /// Waits up to `timeout` for the response to `callback`. Commands on the
+    /// worker pool answer from another thread.
+    pub fn wait_for_response(&self, callback: usize, timeout: Duration) -> Option<Result<Value, Value>> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.state.lock().unwrap();
+        loop {
+            let response = state.records.iter().find_map(|record| match record {
+                WebviewRecord::Response { callback: id, result } if *id == callback => Some(result.clone()),
+                _ => None
+            });
+            if response.is_some() {
+                return response;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
+            }
+            state = self.changed.wait_timeout(state, deadline - now).unwrap().0;
+        }
+    }
+}
+
+impl ResponseSink for MemoryWebview {
+    /// Rejections are recorded under the callback handle as well
+    fn respond(&self, result: Result<Value, Value>, callback: CallbackFn, _: CallbackFn) {
+        self.record(WebviewRecord::Response { callback: callback.0, result });
+    }
+
+    fn channel_event(&self, event: Value) {
+        self.record(WebviewRecord::ChannelEvent(event));
+    }
+}
+
+impl WindowShell for Arc<MemoryWebview> {
+    fn show(&self) {
+        self.record(WebviewRecord::Shown);
+    }
+
+    fn hide(&self) {
+        self.record(WebviewRecord::Hidden);
+    }
+
+    fn close(&self) {
+        self.record(WebviewRecord::Closed);
+    }
+
+    fn minimize(&self) {
+        self.record(WebviewRecord::Minimized);
+    }
+
+    fn is_visible(&self) -> bool {
+        self.state.lock().unwrap().visible
+    }
+
+    fn request_attention(&self) {
+        self.record(WebviewRecord::AttentionRequested);
+    }
+
+    fn show_sys_menu(&self, x: i32, y: i32) {
+        self.record(WebviewRecord::SysMenu { x, y });
+    }
+
+    fn notify(&self, title: &str, message: &str) {
+        self.record(WebviewRecord::Notified { title: title.to_string(), message: message.to_string() });
+    }
+
+    fn notify_close_requested(&self) {
+        self.record(WebviewRecord::CloseRequested);
+    }
+
+    fn sink(&self) -> Sink {
+        self.clone()
+    }
+}
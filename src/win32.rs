@@ -1,4 +1,3 @@
-use serde::{Serialize, Deserialize};
 use tauri_runtime::{EventLoopProxy};
 use tauri_runtime_wry::EventProxy;
 use windows::{
@@ -6,27 +5,29 @@ use windows::{
     Win32::{
         Foundation::{HWND, LPARAM, WPARAM, LRESULT, HANDLE, ERROR_FILE_NOT_FOUND, ERROR_SUCCESS},
         System::{
+            Console::{AttachConsole, ATTACH_PARENT_PROCESS},
             Registry::{HKEY, HKEY_CURRENT_USER, RegCloseKey, RegOpenKeyExW, KEY_WRITE, RegSetValueExA, REG_SZ, RegGetValueA, RRF_RT_REG_SZ, KEY_READ, RegDeleteValueA},
             LibraryLoader::*, 
-            Threading::{OpenMutexW, CreateMutexW, ReleaseMutex}, 
+            Threading::{OpenMutexW, CreateMutexW, ReleaseMutex, WaitForSingleObject}, 
             Pipes::{CreateNamedPipeW, PIPE_TYPE_BYTE, PIPE_WAIT, PIPE_READMODE_BYTE, ConnectNamedPipe}, 
             SystemServices::{WRITE_DAC, GENERIC_READ, GENERIC_WRITE}, Com::{CoCreateInstance, CLSCTX_INPROC_SERVER} 
         },
         UI::{
             WindowsAndMessaging::*,
             Controls::MARGINS,
-            Shell::{SetWindowSubclass, DefSubclassProc, ICustomDestinationList, IShellLinkW, Common::{IObjectCollection, IObjectArray}, DestinationList, EnumerableObjectCollection, ShellLink, PropertiesSystem::{IPropertyStore, InitPropVariantFromStringVector}}, 
+            Shell::{SetWindowSubclass, DefSubclassProc, Shell_NotifyIconW, NOTIFYICONDATAW, NIM_ADD, NIM_MODIFY, NIM_DELETE, NIF_ICON, NIF_MESSAGE, NIF_TIP, NIF_INFO, NIIF_INFO, NIN_BALLOONUSERCLICK, NIN_BALLOONTIMEOUT, ICustomDestinationList, IShellLinkW, Common::{IObjectCollection, IObjectArray}, DestinationList, EnumerableObjectCollection, ShellLink, PropertiesSystem::{IPropertyStore, InitPropVariantFromStringVector}}, 
             Input::KeyboardAndMouse::{KEYBD_EVENT_FLAGS, KEYEVENTF_KEYUP, keybd_event}
         }, 
-        Graphics::{Gdi::SetWindowRgn, Dwm::DwmExtendFrameIntoClientArea}, Storage::{FileSystem::{PIPE_ACCESS_DUPLEX, FILE_FLAG_FIRST_PIPE_INSTANCE, FILE_FLAG_OVERLAPPED, FILE_FLAGS_AND_ATTRIBUTES, CreateFileA, OPEN_EXISTING, FILE_ACCESS_FLAGS, FILE_SHARE_MODE}, EnhancedStorage::PKEY_Title}, Security::SECURITY_ATTRIBUTES,
+        Graphics::{Gdi::SetWindowRgn, Dwm::DwmExtendFrameIntoClientArea}, Storage::{FileSystem::{PIPE_ACCESS_DUPLEX, FILE_FLAG_FIRST_PIPE_INSTANCE, FILE_FLAG_OVERLAPPED, FILE_FLAGS_AND_ATTRIBUTES, CreateFileA, OPEN_EXISTING, FILE_ACCESS_FLAGS, FILE_SHARE_MODE, FindFirstChangeNotificationW, FindNextChangeNotification, FindCloseChangeNotification, FILE_NOTIFY_CHANGE_LAST_WRITE, FILE_NOTIFY_CHANGE_FILE_NAME}, EnhancedStorage::PKEY_Title}, Security::SECURITY_ATTRIBUTES,
     }, ApplicationModel::DataTransfer::{Clipboard, DataPackage, ClipboardContentOptions},
 };
 
-use crate::events::EventLoopMessage;
+use crate::{commands::AutostartCommand, events::{EventLoopMessage, ApplicationOpenLocation}};
 
 use std::{
     fs::File,
-    os::windows::prelude::FromRawHandle
+    os::windows::prelude::FromRawHandle,
+    time::Duration
 };
 
 fn icon_from_resource(resource_id: u16) -> Result<HICON, Error> {
@@ -98,6 +99,51 @@ pub fn show_sys_menu(hwnd: HWND, x: i32, y: i32) -> Result<(), Error> {
 }
 
 
+/// Sent to the window for clicks on the notification icon and its balloon
+const NOTIFICATION_MESSAGE: u32 = WM_APP + 1;
+/// Distinct from the id of the system tray icon
+const NOTIFICATION_ICON_ID: u32 = 0x1338;
+
+fn copy_wide<const N: usize>(target: &mut [u16; N], text: &str) {
+    // the last element stays 0 as terminator
+    for (target, unit) in target.iter_mut().zip(text.encode_utf16().take(N - 1)) {
+        *target = unit;
+    }
+}
+
+fn notification_icon_data(hwnd: HWND) -> NOTIFYICONDATAW {
+    NOTIFYICONDATAW {
+        cbSize: std::mem::size_of::<NOTIFYICONDATAW>() as u32,
+        hWnd: hwnd,
+        uID: NOTIFICATION_ICON_ID,
+        ..Default::default()
+    }
+}
+
+/// Shows a balloon notification from an icon in the notification area. A click
+/// on it opens the window, the icon goes away with the balloon.
+pub fn show_notification(hwnd: HWND, title: &str, message: &str) -> Result<(), Error> {
+    let mut data = notification_icon_data(hwnd);
+    data.uFlags = NIF_ICON | NIF_MESSAGE | NIF_TIP | NIF_INFO;
+    data.uCallbackMessage = NOTIFICATION_MESSAGE;
+    data.hIcon = icon_from_resource(1)?;
+    data.dwInfoFlags = NIIF_INFO;
+    copy_wide(&mut data.szTip, title);
+    copy_wide(&mut data.szInfoTitle, title);
+    copy_wide(&mut data.szInfo, message);
+    unsafe {
+        // the icon is still there if the previous balloon hasn't gone yet
+        if !Shell_NotifyIconW(NIM_ADD, &data).as_bool() && !Shell_NotifyIconW(NIM_MODIFY, &data).as_bool() {
+            return Err(Error::from_win32());
+        }
+    }
+    Ok(())
+}
+
+fn remove_notification_icon(hwnd: HWND) {
+    unsafe { Shell_NotifyIconW(NIM_DELETE, &notification_icon_data(hwnd)) };
+}
+
 unsafe extern "system" fn pfn_subclass(
     hwnd: HWND,
     msg: u32,
@@ -126,6 +172,18 @@ unsafe extern "system" fn pfn_subclass(
                 _ => ()
             }
         }
+        NOTIFICATION_MESSAGE => {
+            match lparam.0 as u32 & 0xffff {
+                NIN_BALLOONUSERCLICK => {
+                    let _ = (*event_loop).send_event(
+                        EventLoopMessage::ApplicationOpenWindow(ApplicationOpenLocation::Auto)
+                    );
+                    remove_notification_icon(hwnd);
+                }
+                NIN_BALLOONTIMEOUT => remove_notification_icon(hwnd),
+                _ => ()
+            }
+        }
         _ => {
             return DefSubclassProc(hwnd, msg, wparam, lparam)
         }
@@ -229,15 +287,49 @@ pub fn connect_to_pipe(path: String) -> File {
     unsafe { File::from_raw_handle(handle.0 as *mut std::ffi::c_void) }
 }
 
+/// Blocks forever and calls `callback` whenever a file in `path` is
+/// written, created, renamed or deleted.
+/// Calls `callback` for changes in the directory at `path`, once per burst of
+/// changes that is followed by `quiet` without any.
+pub fn watch_directory<F>(path: String, quiet: Duration, callback: F) -> Result<(), Error>
+where
+    F: Fn()
+{
+    let handle = unsafe {
+        FindFirstChangeNotificationW(
+            path,
+            false,
+            FILE_NOTIFY_CHANGE_LAST_WRITE | FILE_NOTIFY_CHANGE_FILE_NAME
+        )
+    }?;
+
+    loop {
+        unsafe { WaitForSingleObject(handle, 0xFFFFFFFF) };
+        loop {
+            if !unsafe { FindNextChangeNotification(handle) }.as_bool() {
+                unsafe { FindCloseChangeNotification(handle) };
+                return Err(Error::from_win32());
+            }
+            // anything but WAIT_OBJECT_0 means nothing changed for `quiet`
+            if unsafe { WaitForSingleObject(handle, quiet.as_millis() as u32) } != 0 {
+                break;
+            }
+        }
+        callback();
+    }
+}
+
 pub fn send_close_message(hwnd: HWND) {
     unsafe {
         SendMessageA(hwnd, WM_CLOSE, WPARAM(0), LPARAM(0))
     };
 }
 
-#[derive(Serialize, Deserialize)]
-pub enum AutostartCommand {
-    Get, Activate, Deactivate
+/// Attaches to the console of the process that started us, so command line
+/// modes can print although the executable uses the windows subsystem.
+/// Does nothing when started without a console, e.g. from the explorer.
+pub fn attach_parent_console() -> bool {
+    unsafe { AttachConsole(ATTACH_PARENT_PROCESS) }.as_bool()
 }
 
 struct Finally(HKEY);
@@ -286,11 +378,14 @@ pub fn autostart_registry_execute_command(command: AutostartCommand) -> Option<b
         unsafe {
             match command {
                 AutostartCommand::Activate => {
-                    let exec_str = argquote(
-                        vec![
-                            try_get_executable_path()?,
-                            "--launcher".to_string()]
-                    );
+                    let mut args = vec![
+                        try_get_executable_path()?,
+                        "--launcher".to_string()
+                    ];
+                    if crate::config::is_portable() {
+                        args.push("--portable".to_string());
+                    }
+                    let exec_str = argquote(args);
                     
                     let (len, exec_str) = (exec_str.len(), std::ffi::CString::new(exec_str).ok()?);
                     let res = RegSetValueExA(
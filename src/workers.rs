@@ -0,0 +1,227 @@
+use std::{
+    future::Future, pin::Pin, thread,
+    sync::{Arc, Mutex, mpsc::{self, Receiver, Sender}, atomic::{AtomicBool, Ordering}},
+    task::{Context, Poll, Wake, Waker}
+};
+
+use lazy_static::lazy_static;
+
+/// Threads polling command futures, enough to keep a slow key derivation from
+/// holding up everything else
+const WORKER_COUNT: usize = 4;
+
+pub type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+
+/// A future driven by the pool. It is polled again whenever its waker fires.
+struct Task {
+    future: Mutex<Option<BoxFuture<()>>>,
+    cancelled: AtomicBool
+}
+
+impl Task {
+    fn run(self: Arc<Self>) {
+        let mut slot = self.future.lock().unwrap();
+        if self.cancelled.load(Ordering::SeqCst) {
+            // dropping the future is what cancels it
+            *slot = None;
+            return;
+        }
+        if let Some(mut future) = slot.take() {
+            let waker = Waker::from(self.clone());
+            let mut context = Context::from_waker(&waker);
+            if future.as_mut().poll(&mut context).is_pending() {
+                *slot = Some(future);
+            }
+        }
+    }
+}
+
+impl Wake for Task {
+    fn wake(self: Arc<Self>) {
+        POOL.schedule(self);
+    }
+}
+
+struct WorkerPool {
+    sender: Mutex<Sender<Arc<Task>>>
+}
+
+impl WorkerPool {
+    fn new(count: usize) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for index in 0..count {
+            let receiver = receiver.clone();
+            thread::Builder::new()
+                .name(format!("ipc-worker-{}", index))
+                .spawn(move || worker_loop(receiver))
+                .expect("unable to start ipc worker");
+        }
+        Self { sender: Mutex::new(sender) }
+    }
+
+    fn schedule(&self, task: Arc<Task>) {
+        let _ = self.sender.lock().unwrap().send(task);
+    }
+}
+
+fn worker_loop(receiver: Arc<Mutex<Receiver<Arc<Task>>>>) {
+    loop {
+        let task = match receiver.lock().unwrap().recv() {
+            Ok(task) => task,
+            Err(_) => return
+        };
+        task.run();
+    }
+}
+
+lazy_static! {
+    static ref POOL: WorkerPool = WorkerPool::new(WORKER_COUNT);
+}
+
+/// Handle to a spawned future
+#[derive(Clone)]
+pub struct TaskHandle(Arc<Task>);
+
+impl TaskHandle {
+    /// Drops the future the next time a worker picks it up. A synchronous
+    /// handler that already runs is finished, only its result is discarded.
+    pub fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::SeqCst);
+        POOL.schedule(self.0.clone());
+    }
+}
+
+/// Runs `future` on the worker pool.
+pub fn spawn<F: Future<Output = ()> + Send + 'static>(future: F) -> TaskHandle {
+    let task = Arc::new(Task {
+        future: Mutex::new(Some(Box::pin(future))),
+        cancelled: AtomicBool::new(false)
+    });
+    POOL.schedule(task.clone());
+    TaskHandle(task)
+}
+
+struct OneshotState<T> {
+    result: Option<T>,
+    waker: Option<Waker>
+}
+
+/// Completes the future returned together with it by `oneshot`
+pub struct Completer<T> {
+    state: Arc<Mutex<OneshotState<T>>>
+}
+
+impl<T> Completer<T> {
+    pub fn complete(self, value: T) {
+        let mut state = self.state.lock().unwrap();
+        state.result = Some(value);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// A future completed from elsewhere, by another thread or a message of the
+/// web-app.
+pub fn oneshot<T>() -> (Completer<T>, impl Future<Output = T>) {
+    let state = Arc::new(Mutex::new(OneshotState { result: None, waker: None }));
+    (Completer { state: state.clone() }, OneshotFuture { state })
+}
+
+struct OneshotFuture<T> {
+    state: Arc<Mutex<OneshotState<T>>>
+}
+
+impl<T> Future for OneshotFuture<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<T> {
+        let mut state = self.state.lock().unwrap();
+        match state.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.waker = Some(context.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Completes with the result of `f`, which runs on a thread of its own. For
+/// work that waits on the user, like a modal dialog, and would otherwise
+/// occupy a worker.
+pub fn spawn_blocking<T, F>(f: F) -> impl Future<Output = T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static
+{
+    let (completer, future) = oneshot();
+    thread::spawn(move || completer.complete(f()));
+    future
+}
+
+/// Waits for `future` on the pool, for tests driving async handlers.
+#[cfg(test)]
+pub fn block_on<T, F>(future: F) -> T
+where
+    T: Send + 'static,
+    F: Future<Output = T> + Send + 'static
+{
+    let (sender, receiver) = mpsc::channel();
+    spawn(async move {
+        let _ = sender.send(future.await);
+    });
+    receiver.recv_timeout(std::time::Duration::from_secs(10)).expect("future didn't complete")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    /// Reports being dropped
+    struct DropGuard(Sender<()>);
+
+    impl Drop for DropGuard {
+        fn drop(&mut self) {
+            let _ = self.0.send(());
+        }
+    }
+
+    #[test]
+    fn oneshot_completes_from_another_thread() {
+        let (completer, future) = oneshot();
+        thread::spawn(move || completer.complete("done"));
+        assert_eq!(block_on(future), "done");
+    }
+
+    #[test]
+    fn spawn_blocking_propagates_the_result() {
+        let result = block_on(spawn_blocking(|| thread::current().name().map(|name| name.to_string())));
+        // a thread of its own, the workers stay free
+        assert!(!result.unwrap_or_default().starts_with("ipc-worker"));
+
+        let result: Result<u32, String> = block_on(spawn_blocking(|| Err("failed".to_string())));
+        assert_eq!(result, Err("failed".to_string()));
+    }
+
+    #[test]
+    fn cancelled_tasks_drop_their_future() {
+        let (completer, reply) = oneshot::<()>();
+        let (dropped, was_dropped) = mpsc::channel();
+        let (finished, has_finished) = mpsc::channel();
+        let guard = DropGuard(dropped);
+        let task = spawn(async move {
+            let _guard = guard;
+            reply.await;
+            let _ = finished.send(());
+        });
+
+        task.cancel();
+        assert!(was_dropped.recv_timeout(Duration::from_secs(5)).is_ok());
+        completer.complete(());
+        assert!(has_finished.recv_timeout(Duration::from_millis(200)).is_err());
+    }
+}
@@ -2,6 +2,7 @@ import { Observable, Subject, filter, map, first, flatMap } from "rxjs";
 import { nullOrUndefined } from "./utils";
 import { dequal } from "dequal"
 import { Rust } from "./interface";
+import { Channels, MessageBoxOptions as RustMessageBoxOptions } from "./bindings";
 
 
 const event = new Event('application-init');
@@ -15,18 +16,23 @@ interface IPCHandler {
     registerResolver(callback: ResolveFn, error: RejectFn): Handles;
 } 
 
+/** The sender of init.js, it attaches the origin and nonce itself */
+interface IPC {
+    invoke(command: string, inner: any, handles: Handles): void;
+}
+
 type Uuid = [
     number, number, number, 
     [number, number, number, number,
     number, number, number, number]
 ];
 interface ChannelAcceptEvent { token: number, acceptId: Uuid }
-interface ChannelMessageEvent { channelId: Uuid, data: Record<string, any>, resolver?: number }
+interface ChannelMessageEvent { channelId: Uuid, data: any, resolver?: number }
 
 type Handle = number;
 type Handles = { callback: Handle, error: Handle }
 export interface IRustInterface {
-    sendIPCMessage: (rawMessage: string) => void;
+    ipc: IPC;
     ipcHandler: IPCHandler;
     onChannelEvent(
         type: "accept",
@@ -80,7 +86,7 @@ function randomId(): number {
     return window.crypto.getRandomValues(new Uint32Array(1))[0];
 }
 
-export async function callRustCommand<R>(name: string, args: any[]): Promise<R> {
+export async function callRustCommand<R>(name: string, args: any[], signal?: AbortSignal): Promise<R> {
     if (!rustInterface._ready) {
         throw new Error("Rust Interface API isn't ready jet; Make sure to call initializeApi() first.");
     }
@@ -89,11 +95,12 @@ export async function callRustCommand<R>(name: string, args: any[]): Promise<R>
 
     const prom = new Promise<R>((resolve, reject) => {
         const handles = rustInterface.ipcHandler.registerResolver(resolve, reject);
-        rustInterface.sendIPCMessage(JSON.stringify({
-            ...handles,
-            command: name,
-            inner: args
-        }));
+        rustInterface.ipc.invoke(name, args, handles);
+
+        // the backend rejects the promise once the command is cancelled
+        signal?.addEventListener("abort", () => {
+            callRustCommand("cancelInvoke", [handles.callback]);
+        }, { once: true });
     });
 
     return await prom;
@@ -107,7 +114,26 @@ async function rustOpenChannel(name: string): Promise<number> {
 
 
 
-export function establishChannel<T>(name: string): Observable<T> {
+type RequestHandler = (data: any) => any | Promise<any>;
+
+// ids of the established channels and the handlers for requests made on them
+const openChannels = new Map<keyof Channels, Uuid>();
+const requestHandlers = new Map<keyof Channels, RequestHandler>();
+
+async function replyToRequest(name: keyof Channels, channelId: Uuid, resolver: number, data: any) {
+    const handler = requestHandlers.get(name);
+    try {
+        if (nullOrUndefined(handler)) {
+            throw new Error(`No request handler for channel ${name}`);
+        }
+        const reply = await handler!(data);
+        await callRustCommand("channelReply", [channelId, resolver, reply ?? null]);
+    } catch (error) {
+        await callRustCommand("channelReply", [channelId, resolver, null, String(error)]);
+    }
+}
+
+export function establishChannel<K extends keyof Channels>(name: K): Observable<Channels[K]> {
     return new Observable(subscriber =>  {
         let storedChannelId: Uuid;
         
@@ -118,16 +144,21 @@ export function establishChannel<T>(name: string): Observable<T> {
 
         const setInitialzed = (channelId: Uuid) => {
             storedChannelId = channelId;
+            openChannels.set(name, channelId);
             rustInterface.onChannelEvent("message", event => {
                 if (dequal(event.channelId, channelId)) {
-                    const result = subscriber.next(event.data as T);
-                    if (!nullOrUndefined(event.resolver)) {
-                        callRustCommand("resolve", [event.resolver!, true]);
+                    if (nullOrUndefined(event.resolver)) {
+                        subscriber.next(event.data as Channels[K]);
+                    } else {
+                        replyToRequest(name, channelId, event.resolver!, event.data);
                     }
                 }
             });
             rustInterface.onChannelEvent("close", ({channelId: eventChannelId}) => {
                 if (dequal(eventChannelId, channelId)) {
+                    if (dequal(openChannels.get(name), channelId)) {
+                        openChannels.delete(name);
+                    }
                     subscriber.complete();
                 }
             })
@@ -144,12 +175,47 @@ export function establishChannel<T>(name: string): Observable<T> {
         });
 
         return () => {
-            if (storedChannelId)
+            if (storedChannelId) {
+                if (dequal(openChannels.get(name), storedChannelId)) {
+                    openChannels.delete(name);
+                }
                 callRustCommand("closeChannel", [storedChannelId]);
+            }
         };
     })
 }
 
+function openChannelId(name: keyof Channels): Uuid {
+    const channelId = openChannels.get(name);
+    if (nullOrUndefined(channelId)) {
+        throw new Error(`Channel ${name} hasn't been established`);
+    }
+    return channelId!;
+}
+
+/** Posts `data` to the handlers the backend subscribed to channel `name`. */
+export async function postChannelMessage(name: keyof Channels, data: any): Promise<void> {
+    await callRustCommand("channelPost", [openChannelId(name), data]);
+}
+
+/** Makes a request on channel `name` and resolves with the reply of the backend. */
+export async function channelRequest<R = any>(name: keyof Channels, data: any, signal?: AbortSignal): Promise<R> {
+    return await callRustCommand<R>("channelRequest", [openChannelId(name), data], signal);
+}
+
+/**
+ * Answers the requests the backend makes on channel `name` while it is
+ * established. Returns a function removing the handler again.
+ */
+export function onChannelRequest(name: keyof Channels, handler: RequestHandler): () => void {
+    requestHandlers.set(name, handler);
+    return () => {
+        if (requestHandlers.get(name) === handler) {
+            requestHandlers.delete(name);
+        }
+    };
+}
+
 interface MessageBoxOptions {
     message: string;
     type?: "none" | "info" | "warning" | "error" | "shield";
@@ -204,14 +270,15 @@ export function showMessageBox(options: MessageBoxOptions): Promise<DialogRespon
                 ] 
             )
         )
-    }).then(({ response, checkboxChecked }) => {
+    } as RustMessageBoxOptions).then(({ response: code, checkboxChecked }) => {
+        let response: DialogResult | number = code;
         if (response >= 100) {
             response -= 100;
             if (!nullOrUndefined(cancelId) && response === cancelId) {
                 response = DialogResult.Cancel;
             }
         } else {
-            response = resultMapping[response] ?? response;
+            response = (resultMapping[response] as DialogResult | undefined) ?? response;
         }
 
         return {
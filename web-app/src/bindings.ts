@@ -0,0 +1,82 @@
+// Generated by `mnemonic --emit-bindings`, don't edit by hand.
+
+export type ApplicationOpenLocation = "Auto" | "Generate" | "Retrieve";
+export type AutostartCommand = "Get" | "Activate" | "Deactivate";
+export type ConfigChangeEvent = { path: string[], value: any | null };
+export type ConfigData = { characters: boolean, digits: boolean, punctuation: boolean, special: boolean, length: number, encoding?: OutputEncoding, version?: number, asciiOnly?: boolean, excludeAmbiguous?: boolean, layout?: KeyboardLayout | null };
+export type HistoryChange = { path: string[], oldValue: any | null, newValue: any | null };
+export type HistoryEntry = { id: string, timestamp: number, changes: HistoryChange[] };
+export type ImportMode = "merge" | "replace";
+export type KeyboardLayout = "us" | "de" | "fr";
+export type MessageBoxOptions = { message: string, dialog_type?: string, buttons?: string[], default_id?: number, title?: string, detail?: string, checkbox_label?: string, checkbox_checked?: boolean, no_link?: boolean };
+export type MessageBoxResponse = { response: number, checkboxChecked: boolean };
+export type OutputEncoding = "log" | "hex" | "base32" | "base64url" | "uuid";
+export type PharseData = { phrase: string[], password: string };
+export type ProfileList = { activeProfile: string, profiles: string[] };
+export type QuizEvent = { Reminder: { due: number } };
+export type QuizResult = { correct: number, total: number, schedule: QuizSchedule };
+export type QuizSchedule = { intervalDays: number, nextReview: number | null, streak: number, due: boolean };
+export type QuizStart = { sessionId: string, positions: number[] };
+export type SettingsChange = { path: string[], oldValue: any | null, newValue: any | null };
+export type TransferFormat = "json" | "toml";
+export type UnlockSecret = { Phrase: string[] } | { Passphrase: string };
+export type VaultStatus = { encrypted: boolean, locked: boolean };
+
+export interface Commands {
+    autostartRegistryExecuteCommand: { args: [AutostartCommand], result: boolean | null };
+    cancelInvoke: { args: [number], result: boolean };
+    channelPost: { args: [[number, number, number, number[]], any], result: null };
+    channelReply: { args: [[number, number, number, number[]], number, any, (string | null)?], result: boolean };
+    channelRequest: { args: [[number, number, number, number[]], any], result: any };
+    checkChecksum: { args: [string[]], result: boolean };
+    clipboardWriteTextSecure: { args: [string], result: null };
+    closeChannel: { args: [[number, number, number, number[]]], result: null };
+    configCompareAndSwap: { args: [string[], any, any, (string | null)?], result: boolean };
+    configDeleteProperty: { args: [string[], (string | null)?], result: null };
+    configDisableEncryption: { args: [], result: null };
+    configEnableEncryption: { args: [UnlockSecret], result: null };
+    configEncryptionStatus: { args: [], result: VaultStatus };
+    configExportSettings: { args: [string[], TransferFormat, (string | null)?], result: string };
+    configGetProperty: { args: [string[], (string | null)?, (boolean | null)?], result: any };
+    configHistoryList: { args: [(number | null)?], result: HistoryEntry[] };
+    configHistoryRestore: { args: [number], result: null };
+    configHistoryRevert: { args: [string], result: null };
+    configImportSettings: { args: [string, TransferFormat, string[], ImportMode, boolean, (string | null)?], result: SettingsChange[] };
+    configIsFile: { args: [], result: boolean };
+    configIsReadOnly: { args: [], result: boolean };
+    configListKeys: { args: [string[], (string | null)?], result: string[] };
+    configLock: { args: [], result: null };
+    configObserveProperty: { args: [string[]], result: null };
+    configReadOnlyReason: { args: [], result: string | null };
+    configSetProperty: { args: [string[], any, (string | null)?], result: null };
+    configUnlock: { args: [UnlockSecret], result: null };
+    configUnobserveProperty: { args: [string[]], result: null };
+    fromMnemonicPhrase: { args: [string[], ConfigData], result: PharseData };
+    generateMnemonicPhrase: { args: [ConfigData], result: PharseData };
+    getWordlist: { args: [], result: string };
+    pageContentLoaded: { args: [], result: null };
+    profileClone: { args: [string, string], result: null };
+    profileCreate: { args: [string], result: null };
+    profileDelete: { args: [string], result: null };
+    profileList: { args: [], result: ProfileList };
+    profileRename: { args: [string, string], result: null };
+    profileSwitch: { args: [string], result: null };
+    quizAnswer: { args: [string, number, string], result: boolean };
+    quizCancel: { args: [string], result: null };
+    quizFinish: { args: [string], result: QuizResult };
+    quizGetSchedule: { args: [string[]], result: QuizSchedule };
+    quizStart: { args: [string[]], result: QuizStart };
+    showMessageBox: { args: [MessageBoxOptions], result: MessageBoxResponse };
+    windowClose: { args: [], result: null };
+    windowDragMove: { args: [], result: null };
+    windowMinimize: { args: [], result: null };
+    windowSetTitle: { args: [string], result: null };
+    windowShowSysMenu: { args: [number, number], result: null };
+}
+
+export interface Channels {
+    "config-events": ConfigChangeEvent;
+    "quiz-events": QuizEvent;
+    "ui-events": ApplicationOpenLocation;
+    "window-events": "focus" | "blur" | "minimized";
+}
@@ -290,7 +290,7 @@ export const GeneratePage: FunctionComponent = () => {
             setPassword(data.password);
             return;
         }
-        Rust.generateMnemonicPhrase<PasswordForm, PhraseData>(config).then(data => {
+        Rust.generateMnemonicPhrase(config).then(data => {
             router.history.replace(router.location, { phraseData: data })
             setWordlist(data.phrase);
             setPassword(data.password);
@@ -301,7 +301,7 @@ export const GeneratePage: FunctionComponent = () => {
     useEffect(() => {
         if (initialized !== 3) return;
         Config.globalConfig.passwordGenerationRules = config as any;
-        Rust.fromMnemonicPhrase<PasswordForm, PhraseData>(wordlist, config).then(data => {
+        Rust.fromMnemonicPhrase(wordlist, config).then(data => {
             setPassword(data.password);
         }).catch(console.error);
     }, [config]);
@@ -320,7 +320,7 @@ export const GeneratePage: FunctionComponent = () => {
         if (!animating) setAnimating(true);
     }
 
-    const updatePhrase = () => Rust.generateMnemonicPhrase<PasswordForm, PhraseData>(config).then(config => {
+    const updatePhrase = () => Rust.generateMnemonicPhrase(config).then(config => {
         setWordlist(config.phrase);
         setPassword(config.password);
     })
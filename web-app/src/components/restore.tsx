@@ -12,7 +12,6 @@ import {
     passwordGenerationRulesDefault, 
     PasswordOuput,
     PasswordSettings as InnerPassworSettings,
-    PhraseData, 
     Word as SimpleWord 
 } from "./generate"
 import { useNotifier } from "../notification";
@@ -575,7 +574,7 @@ const PasswordDisplay: FunctionComponent<{
             .catch(console.error); // todo: call global error handler        
     }
     useEffect(() => {
-        Rust.fromMnemonicPhrase<PasswordForm, PhraseData>(phrase, config).then(data => {
+        Rust.fromMnemonicPhrase(phrase, config).then(data => {
             setPassword(data.password);
             if (autoCopy.current) {
                 autoCopy.current = false;
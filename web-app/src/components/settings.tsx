@@ -8,13 +8,21 @@ import { nullOrUndefined } from "../utils";
 import { passwordGenerationRulesDefault } from "./generate";
 import { PasswordSettings } from "./restore";
 
-function useConfigState<T>(property: Config.LazyProperty, defaultValue: T): [T, StateUpdater<T>] {
+function useConfigState<T>(property: Config.LazyProperty, defaultValue: T): [T, StateUpdater<T>, boolean] {
     const [state, setState] = useState(defaultValue);
+    const [locked, setLocked] = useState(false);
 
     useEffect(() => {
         property.getOrDefault(defaultValue).then(data => {
             setState(data);
         })
+        Config.getSource(property).then(source => {
+            setLocked(source.locked);
+        })
+        const subscription = Config.observeProperty<T>(property).subscribe(value => {
+            setState(value ?? defaultValue);
+        });
+        return () => subscription.unsubscribe();
     }, [])
 
     const updateConfig = (value: T | ((prevState: T) => void)) => {
@@ -27,7 +35,8 @@ function useConfigState<T>(property: Config.LazyProperty, defaultValue: T): [T,
 
     return [
         state,
-        updateConfig
+        updateConfig,
+        locked
     ]
 }
 
@@ -65,10 +74,10 @@ export const SettingsPage: FunctionComponent = () => {
         })
     }
 
-    const [sos, setSos] = useConfigState(Config.globalConfig.generalApp.showOnStart, false);
-    const [aol, setAol] = useConfigState(Config.globalConfig.generatePage.askOnLeave, true);
-    const [cob, setCob] = useConfigState(Config.globalConfig.restorePage.closeOnBlur, true);
-    const [str, setStr] = useConfigState(Config.globalConfig.generalApp.skipToRetrieve, false);
+    const [sos, setSos, sosLocked] = useConfigState(Config.globalConfig.generalApp.showOnStart, false);
+    const [aol, setAol, aolLocked] = useConfigState(Config.globalConfig.generatePage.askOnLeave, true);
+    const [cob, setCob, cobLocked] = useConfigState(Config.globalConfig.restorePage.closeOnBlur, true);
+    const [str, setStr, strLocked] = useConfigState(Config.globalConfig.generalApp.skipToRetrieve, false);
 
     return (
         <>
@@ -85,16 +94,16 @@ export const SettingsPage: FunctionComponent = () => {
                             label="Bootstrap Mnemonic App when the Computer starts (Launcher)"
                             children={<ToggleSwitch checked={autolaunch} onChanged={updateAutolaunch}/>}/>
                         <ContainerRow>
-                            <Checkbox checked={sos} onChanged={setSos} disabled={!autolaunch} children="Launcher: Show Window at Startup"/>
+                            <Checkbox checked={sos} onChanged={setSos} disabled={!autolaunch || sosLocked} children="Launcher: Show Window at Startup"/>
                         </ContainerRow>
                         <ContainerRow>
-                            <Checkbox checked={aol} onChanged={setAol} children="Generation Page: Prompt User when Exiting"/>
+                            <Checkbox checked={aol} onChanged={setAol} disabled={aolLocked} children="Generation Page: Prompt User when Exiting"/>
                         </ContainerRow>
                         <ContainerRow>
-                            <Checkbox checked={cob} onChanged={setCob} children="Retrieve Page: Close Window on Blur"/>
+                            <Checkbox checked={cob} onChanged={setCob} disabled={cobLocked} children="Retrieve Page: Close Window on Blur"/>
                         </ContainerRow>
                         <ContainerRow>
-                            <Checkbox checked={str} onChanged={setStr} children="App Startup: Skip to Retrieve Page"/>
+                            <Checkbox checked={str} onChanged={setStr} disabled={strLocked} children="App Startup: Skip to Retrieve Page"/>
                         </ContainerRow>
                 </ExpansionContainer>
             </ExpansionGroup>
@@ -1,6 +1,8 @@
 import { dequal } from "dequal";
 import { useEffect, useRef, useState } from "preact/hooks";
+import { Observable, filter, map, share } from "rxjs";
 import { establishChannel } from "./api";
+import { ConfigChangeEvent } from "./bindings";
 import { Rust } from "./interface";
 import { resolveTypes } from "./utils";
 
@@ -26,6 +28,51 @@ export namespace Config {
         writeToConfig(path, value);
     }
 
+    // effective value, the layer it comes from and whether policy locks it
+    export function getSource(property: LazyProperty) {
+        const target = (property as any)[TARGET];
+        return Rust.configGetProperty(target['name'].split('.'), undefined, true);
+    }
+
+    export function deleteProperty(property: LazyProperty): Promise<void> {
+        const target = (property as any)[TARGET];
+        return Rust.configDeleteProperty(target['name'].split('.'));
+    }
+
+    export function listKeys(property: LazyProperty): Promise<string[]> {
+        const target = (property as any)[TARGET];
+        return Rust.configListKeys(target['name'].split('.'));
+    }
+
+    // resolves to false if the property no longer holds `expected`
+    export function compareAndSwap<T>(property: LazyProperty, expected: T | null, value: T): Promise<boolean> {
+        const target = (property as any)[TARGET];
+        return Rust.configCompareAndSwap(target['name'].split('.'), expected, value);
+    }
+
+    // opening a channel closes the previous one with the same name, so every observer shares this one
+    let configEvents: Observable<ConfigChangeEvent> | null = null;
+
+    export function observeProperty<T>(property: LazyProperty): Observable<T> {
+        const target = (property as any)[TARGET];
+        const path: string[] = target['name'].split('.');
+        if (configEvents === null) {
+            configEvents = establishChannel("config-events").pipe(share());
+        }
+        return new Observable<T>(subscriber => {
+            const subscription = configEvents!.pipe(
+                filter(event => dequal(event.path, path)),
+                map(event => event.value as T)
+            ).subscribe(subscriber);
+            Rust.configObserveProperty(path);
+
+            return () => {
+                subscription.unsubscribe();
+                Rust.configUnobserveProperty(path);
+            };
+        });
+    }
+
     // @ts-ignore: Type Error
     const lazyPropertyImpl: LazyProperty = {
         getOrDefault: async function (value?) {
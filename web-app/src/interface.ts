@@ -1,7 +1,12 @@
-import {  Rust as RustNamespace } from './interface.d';
+import { Commands } from './bindings';
 import { callRustCommand } from './api';
 
-type RustType = typeof RustNamespace;
+type RustType = {
+    [K in keyof Commands]: (...args: Commands[K]["args"]) => Promise<Commands[K]["result"]>
+} & {
+    // answered by the IPC layer itself, not a registered command
+    setInitialized(): Promise<void>;
+};
 export const Rust = new Proxy({}, {
     get(_, prop) {
         if (typeof prop === 'symbol') return;
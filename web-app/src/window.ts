@@ -1,7 +1,7 @@
 import { cloneElement, createContext, createElement, FunctionComponent, VNode } from "preact";
 import { useContext, useEffect, useMemo, useRef, useState } from "preact/hooks";
 import { filter } from "rxjs/operators";
-import { establishChannel, rustInterface } from "./api";
+import { establishChannel, onChannelRequest, rustInterface } from "./api";
 import { Rust } from "./interface";
 import { RouteChanged, RouteEvent, RouterInit, useRouter } from "./router";
 import { makeId, nullOrUndefined } from "./utils";
@@ -12,8 +12,6 @@ export interface EventProvider {
     off(type: string): void;
 }
 
-type WindowEvent = "minimized" | "focus" | "blur";
-
 export function useEventProvider(): EventProvider {
     const listenersMap = useMemo(() => new Map<string, ListenerType<any>>(), []);
     const router = useRouter();
@@ -107,7 +105,16 @@ const blur = new Event("deactivate");
 export function installWindowEventHook() {
     let minimized = false;
     let eatNextEvent = false;
-    establishChannel<WindowEvent>("window-events").subscribe({
+    // the backend asks before closing the window, listeners cancel the event to keep it open
+    onChannelRequest("window-events", request => {
+        if (request !== "close") {
+            throw new Error(`Unknown window request ${request}`);
+        }
+        const closeEvent = new CustomEvent("close", { cancelable: true });
+        window.dispatchEvent(closeEvent);
+        return !closeEvent.defaultPrevented;
+    });
+    establishChannel("window-events").subscribe({
         next(event) {
             switch (event) {
                 case "minimized":
@@ -148,7 +155,7 @@ export const Resetable: FunctionComponent<{ children: VNode<any> }> = ({ childre
     const [clc, setClc] = useState<LocationContextType>("Auto");
 
     useEffect(() => {
-        establishChannel<LocationContextType>("ui-events")
+        establishChannel("ui-events")
             .subscribe(location => {
                 setKey(makeId());
                 setClc(location);